@@ -0,0 +1,591 @@
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+use common::{config::Config, library, thumbnail};
+
+/// Emblem put on watched videos, which most icon themes draw as a check mark.
+const EMBLEM: &str = "emblem-default";
+const EMBLEMS_ATTRIBUTE: &str = "metadata::emblems";
+
+/// Thunar actions are found again by this prefix on their unique ID.
+const THUNAR_ID_PREFIX: &str = "last-watched-";
+const SUBMENU: &str = "Last Watched";
+
+#[derive(Args)]
+pub struct DesktopArgs {
+    #[command(subcommand)]
+    command: DesktopCommand,
+}
+
+#[derive(Subcommand)]
+enum DesktopCommand {
+    /// Add mark and unmark actions to the right click menus of Dolphin,
+    /// Nautilus and Thunar
+    Install,
+    /// Remove the actions added by install
+    Uninstall,
+    /// Set the emblem of every video in a library to match whether it's
+    /// watched, for when emblems are first turned on
+    Emblems {
+        /// Library directory to set the emblems in.
+        #[arg(default_value = ".")]
+        root: PathBuf,
+    },
+    /// Badge the cached thumbnails of every watched video in a library, and
+    /// remove the badge from unwatched ones, for when thumbnails are first
+    /// turned on
+    Thumbnails {
+        /// Library directory to update the thumbnails of.
+        #[arg(default_value = ".")]
+        root: PathBuf,
+    },
+}
+
+/// Something added to the file managers' menus, which runs the cli on the
+/// selected files.
+struct MenuAction {
+    id: &'static str,
+    name: &'static str,
+    icon: &'static str,
+    args: &'static [&'static str],
+    /// Whether the action only works on one file.
+    single: bool,
+}
+
+const ACTIONS: &[MenuAction] = &[
+    MenuAction {
+        id: "watched",
+        name: "Mark as watched",
+        icon: "checkmark",
+        args: &["watched"],
+        single: false,
+    },
+    MenuAction {
+        id: "watched-up-to",
+        name: "Mark all up to here as watched",
+        icon: "checkmark",
+        args: &["watched", "--up-to"],
+        single: true,
+    },
+    MenuAction {
+        id: "unwatched",
+        name: "Mark as unwatched",
+        icon: "edit-clear",
+        args: &["unwatched"],
+        single: false,
+    },
+];
+
+pub fn run(args: DesktopArgs) -> Result<()> {
+    match args.command {
+        DesktopCommand::Install => install(),
+        DesktopCommand::Uninstall => uninstall(),
+        DesktopCommand::Emblems { root } => {
+            let config = Config::for_path(&root)?;
+            for video in library::scan(&config, &root)? {
+                set_emblem(&video.path, video.entry.is_some_and(|x| x.watched))?;
+            }
+            Ok(())
+        }
+        DesktopCommand::Thumbnails { root } => {
+            let config = Config::for_path(&root)?;
+            for video in library::scan(&config, &root)? {
+                thumbnail::update(&video.path, video.entry.is_some_and(|x| x.watched))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn install() -> Result<()> {
+    let exe = env::current_exe()?;
+    let exe = exe.to_string_lossy();
+
+    let dolphin = dolphin_path()?;
+    write_executable(&dolphin, &dolphin_service_menu(&exe))?;
+    println!("Added Dolphin service menu {}", dolphin.display());
+
+    let nautilus = nautilus_dir()?;
+    fs::create_dir_all(&nautilus)?;
+    for action in ACTIONS {
+        write_executable(&nautilus.join(action.name), &nautilus_script(&exe, action))?;
+    }
+    println!("Added Nautilus scripts in {}", nautilus.display());
+
+    let thunar = thunar_path()?;
+    let existing = read_optional(&thunar)?;
+    let actions = existing
+        .as_deref()
+        .map_or(String::new(), remove_thunar_actions);
+    let actions = insert_thunar_actions(&actions, &thunar_actions(&exe));
+    fs::create_dir_all(thunar.parent().unwrap())?;
+    fs::write(&thunar, actions)?;
+    println!(
+        "Added Thunar custom actions to {}, Thunar has to be restarted to show them",
+        thunar.display()
+    );
+
+    Ok(())
+}
+
+fn uninstall() -> Result<()> {
+    let dolphin = dolphin_path()?;
+    if read_optional(&dolphin)?.is_some() {
+        fs::remove_file(&dolphin)?;
+        println!("Removed Dolphin service menu {}", dolphin.display());
+    }
+
+    let nautilus = nautilus_dir()?;
+    match fs::remove_dir_all(&nautilus) {
+        Ok(()) => println!("Removed Nautilus scripts in {}", nautilus.display()),
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    let thunar = thunar_path()?;
+    if let Some(actions) = read_optional(&thunar)? {
+        let removed = remove_thunar_actions(&actions);
+        if removed != actions {
+            fs::write(&thunar, removed)?;
+            println!("Removed Thunar custom actions from {}", thunar.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Updates the emblem and thumbnails of a video the cli just marked or
+/// unmarked, whichever are turned on in the config. The video is already
+/// marked, so failing to update them is only reported.
+pub fn sync(config: &Config, video: &Path, watched: bool) {
+    if config.emblems {
+        if let Err(err) = set_emblem(video, watched) {
+            println!(
+                "Failed to update the emblem of {}: {err:#}",
+                video.display()
+            );
+        }
+    }
+
+    if config.thumbnails {
+        if let Err(err) = thumbnail::update(video, watched) {
+            println!(
+                "Failed to update the thumbnails of {}: {err:#}",
+                video.display()
+            );
+        }
+    }
+}
+
+/// Adds or removes the watched emblem of a file, keeping any others it has.
+fn set_emblem(file: &Path, watched: bool) -> Result<()> {
+    let info = gio(&[
+        OsStr::new("info"),
+        OsStr::new("-a"),
+        OsStr::new(EMBLEMS_ATTRIBUTE),
+        file.as_os_str(),
+    ])?;
+    let mut emblems = parse_emblems(&info);
+    if emblems.iter().any(|x| x == EMBLEM) == watched {
+        return Ok(());
+    }
+
+    match watched {
+        true => emblems.push(EMBLEM.to_owned()),
+        false => emblems.retain(|x| x != EMBLEM),
+    }
+
+    let kind = match emblems.is_empty() {
+        true => "unset",
+        false => "stringv",
+    };
+    let mut args = vec![
+        OsStr::new("set"),
+        OsStr::new("-t"),
+        OsStr::new(kind),
+        file.as_os_str(),
+        OsStr::new(EMBLEMS_ATTRIBUTE),
+    ];
+    args.extend(emblems.iter().map(OsStr::new));
+    gio(&args)?;
+    Ok(())
+}
+
+/// Reads the emblems out of `gio info`, which lists them like
+/// `metadata::emblems: [emblem-default, emblem-important]`.
+fn parse_emblems(info: &str) -> Vec<String> {
+    let prefix = format!("{EMBLEMS_ATTRIBUTE}: ");
+    let Some(list) = info
+        .lines()
+        .find_map(|x| x.trim().strip_prefix(prefix.as_str()))
+    else {
+        return Vec::new();
+    };
+
+    list.trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+fn gio(args: &[&OsStr]) -> Result<String> {
+    let output = match Command::new("gio").args(args).output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            bail!("Emblems are set with gio, which isn't installed")
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    if !output.status.success() {
+        bail!(
+            "gio failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn dolphin_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join("kio/servicemenus/last-watched.desktop"))
+}
+
+fn nautilus_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("nautilus/scripts").join(SUBMENU))
+}
+
+fn thunar_path() -> Result<PathBuf> {
+    let dir = dirs::config_dir().context("Can't find the config directory")?;
+    Ok(dir.join("Thunar/uca.xml"))
+}
+
+fn data_dir() -> Result<PathBuf> {
+    dirs::data_dir().context("Can't find the data directory")
+}
+
+/// A service menu with every action in a submenu, shown for videos and
+/// directories.
+fn dolphin_service_menu(exe: &str) -> String {
+    let ids = ACTIONS
+        .iter()
+        .map(|x| format!("{};", x.id))
+        .collect::<String>();
+    let mut menu = format!(
+        "[Desktop Entry]\n\
+         Type=Service\n\
+         MimeType=video/*;inode/directory;\n\
+         Actions={ids}\n\
+         X-KDE-Submenu={SUBMENU}\n"
+    );
+
+    for action in ACTIONS {
+        let files = if action.single { "%f" } else { "%F" };
+        menu.push_str(&format!(
+            "\n[Desktop Action {}]\nName={}\nIcon={}\nExec={} -- {files}\n",
+            action.id,
+            action.name,
+            action.icon,
+            command(exe, action, desktop_quote),
+        ));
+    }
+
+    menu
+}
+
+/// Nautilus runs scripts from its scripts directory with the selected files
+/// as arguments. It can't limit a script to one file, so actions that need
+/// one do nothing for other selections.
+fn nautilus_script(exe: &str, action: &MenuAction) -> String {
+    let check = match action.single {
+        true => "[ \"$#\" -eq 1 ] || exit 0\n",
+        false => "",
+    };
+    format!(
+        "#!/bin/sh\n{check}exec {} -- \"$@\"\n",
+        command(exe, action, shell_quote)
+    )
+}
+
+fn thunar_actions(exe: &str) -> String {
+    let mut actions = String::new();
+    for action in ACTIONS {
+        let (files, range) = match action.single {
+            true => ("%f", "1"),
+            false => ("%F", ""),
+        };
+        let command = format!("{} -- {files}", command(exe, action, thunar_quote));
+        actions.push_str(&format!(
+            "<action>\n\
+             \t<icon>{}</icon>\n\
+             \t<name>{}</name>\n\
+             \t<submenu>{SUBMENU}</submenu>\n\
+             \t<unique-id>{THUNAR_ID_PREFIX}{}</unique-id>\n\
+             \t<command>{}</command>\n\
+             \t<description></description>\n\
+             \t<range>{range}</range>\n\
+             \t<patterns>*</patterns>\n\
+             \t<directories/>\n\
+             \t<video-files/>\n\
+             </action>\n",
+            action.icon,
+            action.name,
+            action.id,
+            xml_escape(&command),
+        ));
+    }
+
+    actions
+}
+
+/// Removes the actions added by a previous install from a `uca.xml`.
+fn remove_thunar_actions(xml: &str) -> String {
+    let mut out = String::with_capacity(xml.len());
+    let mut rest = xml;
+    while let Some(start) = rest.find("<action>") {
+        let Some(len) = rest[start..].find("</action>") else {
+            break;
+        };
+        let end = start + len + "</action>".len();
+
+        let action = &rest[start..end];
+        out.push_str(&rest[..start]);
+        rest = &rest[end..];
+
+        match action.contains(&format!("<unique-id>{THUNAR_ID_PREFIX}")) {
+            // Without leaving a blank line where it was
+            true => rest = rest.strip_prefix('\n').unwrap_or(rest),
+            false => out.push_str(action),
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Adds actions to the end of a `uca.xml`, creating it if it's empty.
+fn insert_thunar_actions(xml: &str, actions: &str) -> String {
+    match xml.rfind("</actions>") {
+        Some(end) => format!("{}{actions}{}", &xml[..end], &xml[end..]),
+        None => {
+            format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<actions>\n{actions}</actions>\n")
+        }
+    }
+}
+
+fn command(exe: &str, action: &MenuAction, quote: fn(&str) -> String) -> String {
+    let mut command = quote(exe);
+    for arg in action.args {
+        command.push(' ');
+        command.push_str(arg);
+    }
+
+    command
+}
+
+/// Quotes an argument for `sh`, which is also how Thunar splits commands.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Quotes an argument for a Thunar command, where percent signs are field
+/// codes like in desktop entries.
+fn thunar_quote(arg: &str) -> String {
+    shell_quote(arg).replace('%', "%%")
+}
+
+/// Quotes an argument for the `Exec` key of a desktop entry.
+fn desktop_quote(arg: &str) -> String {
+    let mut quoted = String::from('"');
+    for char in arg.chars() {
+        if matches!(char, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(char);
+    }
+    quoted.push('"');
+
+    // Desktop entries unescape backslashes once more when they are read, and
+    // percent signs are field codes
+    quoted.replace('\\', r"\\").replace('%', "%%")
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes a file that file managers run, creating its directory if needed.
+fn write_executable(path: &Path, contents: &str) -> Result<()> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, contents)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use common::test_util::library;
+
+    use super::*;
+
+    const EXE: &str = r#"/opt/It's "my"/$HOME/100%/c\li"#;
+
+    /// Runs a quoted command with `sh`, getting back the arguments it saw.
+    fn sh_args(command: &str) -> Vec<String> {
+        let script = format!("for x in {command}; do printf '%s\\n' \"$x\"; done");
+        let output = Command::new("sh").args(["-c", &script]).output().unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+        output.lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn parses_emblems() {
+        let info = "uri: file:///media/Show.S01E01.mkv\n\
+                    attributes:\n  \
+                    standard::type: 1\n  \
+                    metadata::emblems: [emblem-default, emblem-important]\n";
+        assert_eq!(parse_emblems(info), ["emblem-default", "emblem-important"]);
+
+        assert_eq!(
+            parse_emblems("  metadata::emblems: [emblem-default]\n"),
+            ["emblem-default"]
+        );
+        assert!(parse_emblems("  metadata::emblems: []\n").is_empty());
+        assert!(parse_emblems("attributes:\n  standard::type: 1\n").is_empty());
+    }
+
+    #[test]
+    fn quotes_for_sh() {
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(sh_args(&shell_quote(EXE)), [EXE]);
+        assert_eq!(
+            sh_args(&command(EXE, &ACTIONS[1], shell_quote)),
+            [EXE, "watched", "--up-to"]
+        );
+
+        // Thunar takes the percent signs out again before running it
+        assert_eq!(thunar_quote("100%"), "'100%%'");
+    }
+
+    #[test]
+    fn quotes_for_desktop_entries() {
+        assert_eq!(desktop_quote("/usr/bin/cli"), r#""/usr/bin/cli""#);
+        assert_eq!(
+            desktop_quote(EXE),
+            r#""/opt/It's \\"my\\"/\\$HOME/100%%/c\\\\li""#
+        );
+        assert_eq!(desktop_quote("`date`"), r#""\\`date\\`""#);
+    }
+
+    #[test]
+    fn makes_a_dolphin_service_menu() {
+        let menu = dolphin_service_menu("/usr/bin/cli");
+        let lines = menu.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "[Desktop Entry]");
+        assert!(lines.contains(&"MimeType=video/*;inode/directory;"));
+        assert!(lines.contains(&"Actions=watched;watched-up-to;unwatched;"));
+        assert!(lines.contains(&"X-KDE-Submenu=Last Watched"));
+        assert!(lines.contains(&"[Desktop Action watched-up-to]"));
+        assert!(lines.contains(&r#"Exec="/usr/bin/cli" watched -- %F"#));
+        assert!(lines.contains(&r#"Exec="/usr/bin/cli" watched --up-to -- %f"#));
+        assert!(lines.contains(&r#"Exec="/usr/bin/cli" unwatched -- %F"#));
+        assert_eq!(lines.iter().filter(|x| x.starts_with("Name=")).count(), 3);
+    }
+
+    #[test]
+    fn single_file_scripts_check_the_selection() {
+        let script = nautilus_script("/usr/bin/cli", &ACTIONS[1]);
+        assert_eq!(
+            script,
+            "#!/bin/sh\n[ \"$#\" -eq 1 ] || exit 0\nexec '/usr/bin/cli' watched --up-to -- \"$@\"\n"
+        );
+
+        let script = nautilus_script("/usr/bin/cli", &ACTIONS[0]);
+        assert_eq!(script, "#!/bin/sh\nexec '/usr/bin/cli' watched -- \"$@\"\n");
+    }
+
+    #[test]
+    fn only_touches_our_thunar_actions() {
+        let foreign = "<action>\n\
+                       \t<icon>utilities-terminal</icon>\n\
+                       \t<name>Open Terminal Here</name>\n\
+                       \t<unique-id>1234567890-1</unique-id>\n\
+                       \t<command>exo-open --working-directory %f --launch TerminalEmulator</command>\n\
+                       </action>\n";
+        let xml =
+            format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<actions>\n{foreign}</actions>\n");
+
+        let installed = insert_thunar_actions(&xml, &thunar_actions(EXE));
+        assert!(installed.starts_with(&format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<actions>\n{foreign}<action>"
+        )));
+        assert_eq!(installed.matches("<action>").count(), 4);
+        assert!(installed.contains(
+            "<command>'/opt/It'\\''s \"my\"/$HOME/100%%/c\\li' watched --up-to -- %f</command>"
+        ));
+
+        // Installing again replaces them, and uninstalling leaves it as it was
+        let reinstalled =
+            insert_thunar_actions(&remove_thunar_actions(&installed), &thunar_actions(EXE));
+        assert_eq!(reinstalled, installed);
+        assert_eq!(remove_thunar_actions(&installed), xml);
+
+        let empty = insert_thunar_actions("", &thunar_actions(EXE));
+        assert_eq!(
+            remove_thunar_actions(&empty),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<actions>\n</actions>\n"
+        );
+    }
+
+    #[test]
+    fn installs_and_uninstalls() {
+        let _library = library(&[]);
+        let thunar = thunar_path().unwrap();
+        let foreign = "<action>\n\t<name>Open Terminal Here</name>\n\t<unique-id>1-1</unique-id>\n</action>\n";
+        let xml = format!("<actions>\n{foreign}</actions>\n");
+        fs::create_dir_all(thunar.parent().unwrap()).unwrap();
+        fs::write(&thunar, &xml).unwrap();
+
+        install().unwrap();
+        assert!(dolphin_path().unwrap().is_file());
+        assert_eq!(
+            fs::read_dir(nautilus_dir().unwrap()).unwrap().count(),
+            ACTIONS.len()
+        );
+        let installed = fs::read_to_string(&thunar).unwrap();
+        assert!(installed.contains(foreign));
+        assert_eq!(installed.matches("<action>").count(), 4);
+
+        uninstall().unwrap();
+        assert!(!dolphin_path().unwrap().exists());
+        assert!(!nautilus_dir().unwrap().exists());
+        assert_eq!(fs::read_to_string(&thunar).unwrap(), xml);
+    }
+}
@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use common::library::Video;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Item<'a> {
+    path: &'a Path,
+    watched: bool,
+    watched_at: Option<DateTime<Utc>>,
+    count: u32,
+    progress: Option<Progress>,
+    rating: Option<u8>,
+    favourite: bool,
+    note: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Progress {
+    position: u64,
+    duration: u64,
+}
+
+/// Lists every video that has anything stored about it.
+pub fn export(videos: &[Video]) -> Result<String> {
+    let items = videos
+        .iter()
+        .filter_map(|video| {
+            let entry = video.entry.as_ref()?;
+            Some(Item {
+                path: &video.path,
+                watched: entry.watched,
+                watched_at: entry.watched_at,
+                count: entry.count,
+                progress: entry.progress.map(|x| Progress {
+                    position: x.position,
+                    duration: x.duration,
+                }),
+                rating: entry.rating,
+                favourite: entry.favourite,
+                note: entry.note.as_deref(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(serde_json::to_string_pretty(&items)?)
+}
@@ -0,0 +1,47 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use common::{config::Config, library};
+
+mod json;
+mod trakt;
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Format to export in.
+    #[arg(long, value_enum)]
+    format: ExportFormat,
+    /// Library directory to export the videos of.
+    root: PathBuf,
+    /// File to write the export to, instead of printing it.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    /// Body for Trakt's `/sync/history` endpoint.
+    Trakt,
+    /// Body for Trakt's `/sync/ratings` endpoint, with the rated videos.
+    TraktRatings,
+    /// Everything stored about each video, including ratings, favourites and notes.
+    Json,
+}
+
+pub fn run(args: ExportArgs) -> Result<()> {
+    let config = Config::for_path(&args.root)?;
+    let videos = library::scan(&config, &args.root)?;
+    let export = match args.format {
+        ExportFormat::Trakt => trakt::export(&videos)?,
+        ExportFormat::TraktRatings => trakt::export_ratings(&videos)?,
+        ExportFormat::Json => json::export(&videos)?,
+    };
+
+    match args.output {
+        Some(path) => fs::write(path, export)?,
+        None => println!("{export}"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use common::{episode::Episode, library::Video, sidecar::Entry};
+
+use crate::trakt::{movie_title, Movie, Season, SeasonEpisode, Show, SyncHistory};
+
+/// Builds a Trakt history of the watched videos, working out the show and
+/// episode from their file names. Anything that isn't an episode is exported
+/// as a movie.
+pub fn export(videos: &[Video]) -> Result<String> {
+    build(videos, |entry| {
+        entry.watched.then_some((entry.watched_at, None))
+    })
+}
+
+/// Builds a body for Trakt's ratings endpoint from the rated videos, like
+/// [`export`].
+pub fn export_ratings(videos: &[Video]) -> Result<String> {
+    build(videos, |entry| entry.rating.map(|x| (None, Some(x))))
+}
+
+/// Builds a Trakt body from the videos that `item` returns a watched time and
+/// rating for.
+fn build(
+    videos: &[Video],
+    item: impl Fn(&Entry) -> Option<(Option<DateTime<Utc>>, Option<u8>)>,
+) -> Result<String> {
+    let mut history = SyncHistory::default();
+    let mut shows = BTreeMap::<String, BTreeMap<u32, Vec<SeasonEpisode>>>::new();
+
+    for video in videos {
+        let Some((watched_at, rating)) = video.entry.as_ref().and_then(&item) else {
+            continue;
+        };
+
+        match Episode::parse(&video.path) {
+            Some(episode) => shows
+                .entry(episode.show)
+                .or_default()
+                .entry(episode.season)
+                .or_default()
+                .push(SeasonEpisode {
+                    number: episode.episode,
+                    watched_at,
+                    rating,
+                }),
+            None => {
+                let (title, year) = movie_title(&video.path);
+                history.movies.push(Movie {
+                    title,
+                    year,
+                    watched_at,
+                    rating,
+                });
+            }
+        }
+    }
+
+    history.shows = shows
+        .into_iter()
+        .map(|(title, seasons)| Show {
+            title,
+            seasons: seasons
+                .into_iter()
+                .map(|(number, episodes)| Season { number, episodes })
+                .collect(),
+        })
+        .collect();
+
+    Ok(serde_json::to_string_pretty(&history)?)
+}
@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use clap::Args;
+use common::{config::Config, episode::Episode, history::read_library};
+
+#[derive(Args)]
+pub struct HistoryArgs {
+    /// Library directory to show the history of.
+    #[arg(default_value = ".")]
+    root: PathBuf,
+    /// Only show events on or after a date (YYYY-MM-DD) or time (RFC 3339).
+    #[arg(long, value_parser = parse_since)]
+    since: Option<DateTime<Utc>>,
+    /// Only show episodes of shows with a name containing this.
+    #[arg(long)]
+    show: Option<String>,
+}
+
+pub fn run(args: HistoryArgs) -> Result<()> {
+    let config = Config::for_path(&args.root)?;
+    let show = args.show.map(|x| x.to_lowercase());
+
+    for event in read_library(&config, &args.root)? {
+        if args.since.is_some_and(|since| event.time < since) {
+            continue;
+        }
+
+        if let Some(show) = &show {
+            let episode = Episode::parse(&event.path);
+            if !episode.is_some_and(|x| x.show.to_lowercase().contains(show)) {
+                continue;
+            }
+        }
+
+        println!(
+            "{}  {:<7}  {:<9}  {}",
+            event.time.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+            event.source.to_string(),
+            event.action.to_string(),
+            event.path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Dates are taken as the start of that day in the local timezone.
+fn parse_since(since: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        return date
+            .and_hms_opt(0, 0, 0)
+            .and_then(|x| x.and_local_timezone(Local).earliest())
+            .map(|x| x.to_utc())
+            .context("Invalid date");
+    }
+
+    Ok(DateTime::parse_from_rfc3339(since)?.to_utc())
+}
@@ -0,0 +1,86 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{Args, ValueEnum};
+use common::{history::Source, journal::Operation};
+
+use crate::{mark_watched_at, rewrite::Rewrite};
+
+mod plex;
+mod trakt;
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// Format of the file being imported.
+    #[arg(long, value_enum)]
+    format: ImportFormat,
+    /// File to import.
+    file: PathBuf,
+    /// Maps paths in the import to local paths, as SERVER=LOCAL. Can be repeated.
+    #[arg(long = "rewrite")]
+    rewrites: Vec<Rewrite>,
+    /// Library directory to match imported shows and movies against, for
+    /// formats that don't include file paths.
+    #[arg(long)]
+    root: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ImportFormat {
+    /// Library listing from Plex's API, requested as JSON.
+    Plex,
+    /// Trakt history, from `/sync/history` or an export made with this tool.
+    Trakt,
+}
+
+/// Videos found in an import, along with descriptions of the items that
+/// couldn't be matched to a local file.
+#[derive(Default)]
+struct Import {
+    watched: Vec<Watched>,
+    unmatched: Vec<String>,
+}
+
+struct Watched {
+    path: PathBuf,
+    time: Option<DateTime<Utc>>,
+}
+
+pub fn run(args: ImportArgs) -> Result<()> {
+    let data = fs::read(&args.file)?;
+    let import = match args.format {
+        ImportFormat::Plex => plex::parse(&data, &args.rewrites)?,
+        ImportFormat::Trakt => trakt::parse(&data, root(&args)?)?,
+    };
+
+    for item in &import.unmatched {
+        println!("? unmatched {item}");
+    }
+
+    let mut operation = Operation::new(format!("import {}", args.file.display()));
+    for Watched { path, time } in &import.watched {
+        mark_watched_at(
+            path,
+            time.unwrap_or_else(Utc::now),
+            Source::Cli,
+            &mut operation,
+        )?;
+    }
+
+    println!(
+        "Imported {} watched videos, {} could not be matched",
+        import.watched.len(),
+        import.unmatched.len()
+    );
+    Ok(())
+}
+
+fn root(args: &ImportArgs) -> Result<&Path> {
+    args.root
+        .as_deref()
+        .context("This format needs --root to find local files")
+}
@@ -0,0 +1,35 @@
+use anyhow::Result;
+use chrono::DateTime;
+
+use super::{Import, Watched};
+use crate::{
+    plex::MediaContainer,
+    rewrite::{rewrite_path, Rewrite},
+};
+
+/// Gets the watched files from a Plex library listing, such as the response
+/// from `/library/sections/<id>/allLeaves` with `Accept: application/json`.
+pub fn parse(data: &[u8], rewrites: &[Rewrite]) -> Result<Import> {
+    let library = serde_json::from_slice::<MediaContainer>(data)?;
+
+    let mut import = Import::default();
+    for item in library.container.metadata.iter() {
+        if item.view_count == 0 {
+            continue;
+        }
+
+        let time = item
+            .last_viewed_at
+            .and_then(|x| DateTime::from_timestamp(x, 0));
+        for file in item.files() {
+            let path = rewrite_path(rewrites, file);
+            if path.is_file() {
+                import.watched.push(Watched { path, time });
+            } else {
+                import.unmatched.push(path.display().to_string());
+            }
+        }
+    }
+
+    Ok(import)
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use common::{config::Config, episode::Episode, library};
+use serde::Deserialize;
+
+use super::{Import, Watched};
+use crate::trakt::{movie_title, HistoryItem, SyncHistory};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum History {
+    Items(Vec<HistoryItem>),
+    Sync(SyncHistory),
+}
+
+enum Item {
+    Episode(Episode),
+    Movie { title: String, year: Option<u32> },
+}
+
+/// Local videos, grouped so imported items can be matched against them.
+#[derive(Default)]
+struct Library {
+    shows: HashMap<String, Vec<(Episode, PathBuf)>>,
+    movies: Vec<(String, Option<u32>, PathBuf)>,
+}
+
+/// Matches the entries of a Trakt history onto files under `root` by their
+/// show, season and episode, or by title for movies.
+pub fn parse(data: &[u8], root: &Path) -> Result<Import> {
+    let library = Library::load(root)?;
+
+    let mut import = Import::default();
+    for (item, time) in items(serde_json::from_slice(data)?) {
+        match library.find(&item) {
+            Some(path) => import.watched.push(Watched {
+                path: path.to_owned(),
+                time,
+            }),
+            None => import.unmatched.push(item.to_string()),
+        }
+    }
+
+    Ok(import)
+}
+
+fn items(history: History) -> Vec<(Item, Option<DateTime<Utc>>)> {
+    let mut items = Vec::new();
+    match history {
+        History::Items(history) => {
+            for entry in history {
+                let item = match (entry.show, entry.episode, entry.movie) {
+                    (Some(show), Some(episode), _) => Item::Episode(Episode {
+                        show: show.title,
+                        season: episode.season,
+                        episode: episode.number,
+                    }),
+                    (_, _, Some(movie)) => Item::Movie {
+                        title: movie.title,
+                        year: movie.year,
+                    },
+                    _ => continue,
+                };
+                items.push((item, entry.watched_at));
+            }
+        }
+        History::Sync(history) => {
+            for movie in history.movies {
+                let item = Item::Movie {
+                    title: movie.title,
+                    year: movie.year,
+                };
+                items.push((item, movie.watched_at));
+            }
+
+            for show in history.shows {
+                for season in show.seasons {
+                    for episode in season.episodes {
+                        let item = Item::Episode(Episode {
+                            show: show.title.clone(),
+                            season: season.number,
+                            episode: episode.number,
+                        });
+                        items.push((item, episode.watched_at));
+                    }
+                }
+            }
+        }
+    }
+
+    items
+}
+
+impl Library {
+    fn load(root: &Path) -> Result<Self> {
+        let mut library = Self::default();
+        let config = Config::for_path(root)?;
+        for path in library::walk(&config, root)? {
+            match Episode::parse(&path) {
+                Some(episode) => library
+                    .shows
+                    .entry(normalize(&episode.show))
+                    .or_default()
+                    .push((episode, path)),
+                None => {
+                    let (title, year) = movie_title(&path);
+                    library.movies.push((normalize(&title), year, path));
+                }
+            }
+        }
+
+        Ok(library)
+    }
+
+    fn find(&self, item: &Item) -> Option<&Path> {
+        match item {
+            Item::Episode(episode) => {
+                let episodes = self.shows.get(&normalize(&episode.show))?;
+                episodes
+                    .iter()
+                    .find(|(x, _)| x.season == episode.season && x.episode == episode.episode)
+                    .map(|(_, path)| path.as_path())
+            }
+            Item::Movie { title, year } => {
+                // Only use the year to rule out remakes when both sides have one
+                let candidates = self
+                    .movies
+                    .iter()
+                    .filter(|(_, local_year, _)| year.zip(*local_year).is_none_or(|(a, b)| a == b));
+                best_match(title, candidates, |(title, _, _)| title)
+                    .map(|(_, _, path)| path.as_path())
+            }
+        }
+    }
+}
+
+/// Finds the candidate with the same title as `title`, once both are
+/// normalized. Titles aren't matched by similarity, as similar titles are
+/// often different shows, like "American Dad" and "American Gods", and the
+/// same episodes of the wrong one would be marked.
+fn best_match<T>(
+    title: &str,
+    candidates: impl IntoIterator<Item = T>,
+    get_title: impl Fn(&T) -> &String,
+) -> Option<T> {
+    let title = normalize(title);
+    candidates.into_iter().find(|x| *get_title(x) == title)
+}
+
+/// Normalizes a title for comparison by lowercasing it, removing punctuation,
+/// a leading "the" and a trailing year, and spelling out "&".
+fn normalize(title: &str) -> String {
+    let title = title
+        .to_lowercase()
+        .replace('&', " and ")
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>();
+
+    let mut words = title.split_whitespace().collect::<Vec<_>>();
+    if words.len() > 1 && words[0] == "the" {
+        words.remove(0);
+    }
+
+    let is_year = |x: &&str| x.len() == 4 && x.chars().all(|c| c.is_ascii_digit());
+    if words.len() > 1 && words.last().is_some_and(is_year) {
+        words.pop();
+    }
+
+    words.join(" ")
+}
+
+impl std::fmt::Display for Item {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Item::Episode(episode) => write!(
+                f,
+                "{} S{:02}E{:02}",
+                episode.show, episode.season, episode.episode
+            ),
+            Item::Movie { title, year } => match year {
+                Some(year) => write!(f, "{title} ({year})"),
+                None => f.write_str(title),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::test_util::library;
+
+    use super::*;
+
+    fn titles(titles: &[&str]) -> Vec<String> {
+        titles.iter().map(|x| normalize(x)).collect()
+    }
+
+    #[test]
+    fn normalizes_titles() {
+        assert_eq!(normalize("The Office"), "office");
+        assert_eq!(normalize("Doctor Who (2005)"), "doctor who");
+        assert_eq!(
+            normalize("Marvel's Agents of S.H.I.E.L.D."),
+            "marvel s agents of s h i e l d"
+        );
+        assert_eq!(normalize("Law & Order"), normalize("Law and Order"));
+
+        // Words that are the whole title are kept
+        assert_eq!(normalize("The"), "the");
+        assert_eq!(normalize("1917"), "1917");
+        assert_eq!(normalize("Blade Runner 2049"), "blade runner");
+    }
+
+    #[test]
+    fn only_matches_the_same_title() {
+        let candidates = titles(&[
+            "American Dad",
+            "Star Trek: Picard",
+            "Doctor Who Confidential",
+        ]);
+        let find = |title| best_match(title, &candidates, |x| x).map(String::as_str);
+
+        assert_eq!(find("American Gods"), None);
+        assert_eq!(find("Star Trek: Discovery"), None);
+        assert_eq!(find("Doctor Who"), None);
+
+        assert_eq!(find("American Dad!"), Some("american dad"));
+        assert_eq!(find("Star Trek - Picard (2020)"), Some("star trek picard"));
+        assert_eq!(
+            find("The Doctor Who Confidential"),
+            Some("doctor who confidential")
+        );
+    }
+
+    #[test]
+    fn leaves_similar_shows_unmatched() {
+        let dir = library(&[
+            "American Dad/Season 1/American.Dad.S01E01.mkv",
+            "Movies/Blade.Runner.1982.1080p.mkv",
+        ]);
+        let history = r#"[
+            {"watched_at": "2024-01-01T20:00:00Z", "show": {"title": "American Gods"}, "episode": {"season": 1, "number": 1}},
+            {"watched_at": "2024-01-02T20:00:00Z", "show": {"title": "American Dad!"}, "episode": {"season": 1, "number": 1}},
+            {"watched_at": "2024-01-03T20:00:00Z", "movie": {"title": "Blade Runner", "year": 2017}},
+            {"watched_at": "2024-01-04T20:00:00Z", "movie": {"title": "Blade Runner", "year": 1982}}
+        ]"#;
+
+        let import = parse(history.as_bytes(), dir.path()).unwrap();
+        let watched = import
+            .watched
+            .iter()
+            .map(|x| x.path.strip_prefix(dir.path()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            watched,
+            [
+                Path::new("American Dad/Season 1/American.Dad.S01E01.mkv"),
+                Path::new("Movies/Blade.Runner.1982.1080p.mkv"),
+            ]
+        );
+        assert_eq!(
+            import.unmatched,
+            ["American Gods S01E01", "Blade Runner (2017)"]
+        );
+    }
+}
@@ -0,0 +1,449 @@
+use std::{
+    fmt::{self, Display},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Args, ValueEnum};
+use common::{config::Config, journal::Operation, sidecar::read_entry};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use ureq::Agent;
+
+use crate::{
+    mark_unwatched, mark_watched,
+    rewrite::{rewrite_path, Rewrite},
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Args)]
+pub struct KodiArgs {
+    /// Address of Kodi's web server.
+    #[arg(long, default_value = "http://localhost:8080")]
+    url: String,
+    /// Username for Kodi's web server.
+    #[arg(long, default_value = "kodi")]
+    username: String,
+    /// Password for Kodi's web server, if one is set.
+    #[arg(long)]
+    password: Option<String>,
+    /// Maps Kodi paths to local paths, as SERVER=LOCAL. Can be repeated.
+    #[arg(long = "rewrite")]
+    rewrites: Vec<Rewrite>,
+    /// Which side wins when Kodi and the sidecar files disagree.
+    #[arg(long, value_enum, default_value_t = Prefer::Watched)]
+    prefer: Prefer,
+    /// Only print the changes that would be made.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Prefer {
+    /// If either side has an episode marked as watched, mark it on both.
+    Watched,
+    /// Make the sidecar files match Kodi.
+    Kodi,
+    /// Make Kodi match the sidecar files.
+    Local,
+}
+
+enum Change<'a> {
+    MarkLocal(PathBuf),
+    UnmarkLocal(PathBuf),
+    MarkKodi(&'a Episode),
+    UnmarkKodi(&'a Episode),
+}
+
+struct Client {
+    agent: Agent,
+    endpoint: String,
+    authorization: String,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct Episodes {
+    #[serde(default)]
+    episodes: Vec<Episode>,
+}
+
+#[derive(Deserialize)]
+struct Episode {
+    episodeid: u64,
+    label: String,
+    file: String,
+    playcount: u32,
+}
+
+/// Reconciles Kodi's episode playcounts with the sidecar files.
+pub fn run(args: KodiArgs) -> Result<()> {
+    let client = Client::new(&args);
+    let episodes = client
+        .call::<Episodes>(
+            "VideoLibrary.GetEpisodes",
+            json!({ "properties": ["file", "playcount"] }),
+        )?
+        .episodes;
+
+    let mut changes = Vec::new();
+    let mut missing = Vec::new();
+    let mut skipped = Vec::new();
+    for episode in &episodes {
+        let path = rewrite_path(&args.rewrites, &episode.file);
+        if !path.is_file() {
+            missing.push(path);
+            continue;
+        }
+
+        // Kodi plays files the config doesn't count as videos, like `.ts` or
+        // `.iso`, which shouldn't stop the rest from syncing
+        let config = Config::for_path(&path)?;
+        if !config.is_video(&path) {
+            skipped.push(path);
+            continue;
+        }
+
+        let local = read_entry(&config, &path)?.is_some_and(|x| x.watched);
+        let kodi = episode.playcount > 0;
+        if local == kodi {
+            continue;
+        }
+
+        changes.push(match (args.prefer, local) {
+            (Prefer::Watched, false) | (Prefer::Kodi, false) => Change::MarkLocal(path),
+            (Prefer::Kodi, true) => Change::UnmarkLocal(path),
+            (Prefer::Watched, true) | (Prefer::Local, true) => Change::MarkKodi(episode),
+            (Prefer::Local, false) => Change::UnmarkKodi(episode),
+        });
+    }
+
+    for path in &missing {
+        println!("? missing {}", path.display());
+    }
+    for path in &skipped {
+        println!("? not a video {}", path.display());
+    }
+
+    let mut operation = Operation::new("kodi-sync");
+    for change in &changes {
+        println!("{change}");
+        if !args.dry_run {
+            client.apply(change, &mut operation)?;
+        }
+    }
+
+    println!(
+        "{} episodes, {} {}, {} not found locally, {} skipped as not videos",
+        episodes.len(),
+        changes.len(),
+        if args.dry_run { "to change" } else { "changed" },
+        missing.len(),
+        skipped.len()
+    );
+
+    Ok(())
+}
+
+impl Client {
+    fn new(args: &KodiArgs) -> Self {
+        let credentials = format!(
+            "{}:{}",
+            args.username,
+            args.password.as_deref().unwrap_or_default()
+        );
+
+        Self {
+            agent: Agent::config_builder()
+                .timeout_global(Some(REQUEST_TIMEOUT))
+                .build()
+                .into(),
+            endpoint: format!("{}/jsonrpc", args.url.trim_end_matches('/')),
+            authorization: format!("Basic {}", STANDARD.encode(credentials)),
+        }
+    }
+
+    fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+
+        let response = self
+            .agent
+            .post(&self.endpoint)
+            .header("Authorization", &self.authorization)
+            .send_json(request)?
+            .body_mut()
+            .read_json::<Response<T>>()?;
+
+        match response {
+            Response {
+                error: Some(error), ..
+            } => bail!("{method} failed: {} ({})", error.message, error.code),
+            Response {
+                result: Some(result),
+                ..
+            } => Ok(result),
+            _ => bail!("{method} returned no result"),
+        }
+    }
+
+    fn apply(&self, change: &Change, operation: &mut Operation) -> Result<()> {
+        let (episode, playcount) = match change {
+            Change::MarkLocal(path) => return mark_watched(path, operation),
+            Change::UnmarkLocal(path) => return mark_unwatched(path, operation),
+            Change::MarkKodi(episode) => (episode, 1),
+            Change::UnmarkKodi(episode) => (episode, 0),
+        };
+
+        self.call::<Value>(
+            "VideoLibrary.SetEpisodeDetails",
+            json!({ "episodeid": episode.episodeid, "playcount": playcount }),
+        )?;
+        Ok(())
+    }
+}
+
+impl Display for Change<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::MarkLocal(path) => write!(f, "+ local   {}", path.display()),
+            Change::UnmarkLocal(path) => write!(f, "- local   {}", path.display()),
+            Change::MarkKodi(episode) => write!(f, "+ kodi    {}", episode.label),
+            Change::UnmarkKodi(episode) => write!(f, "- kodi    {}", episode.label),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use tiny_http::{Response, Server};
+
+    use super::*;
+    use common::test_util::{library, Library};
+
+    /// Stand-in for Kodi's JSON-RPC endpoint, with a fixed library. Every
+    /// `SetEpisodeDetails` call is kept to be checked afterwards.
+    struct MockKodi {
+        url: String,
+        updates: Arc<Mutex<Vec<(u64, u32)>>>,
+    }
+
+    impl MockKodi {
+        fn start(episodes: Value) -> Self {
+            let server = Server::http("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+            let updates = Arc::new(Mutex::new(Vec::new()));
+
+            let recorded = updates.clone();
+            thread::spawn(move || {
+                for mut request in server.incoming_requests() {
+                    let authorized = request.headers().iter().any(|x| {
+                        x.field.equiv("Authorization") && x.value.as_str() == "Basic a29kaTo="
+                    });
+                    let body: Value = serde_json::from_reader(request.as_reader()).unwrap();
+                    let response = match body["method"].as_str().unwrap() {
+                        _ if !authorized => Response::from_string("").with_status_code(401),
+                        "VideoLibrary.GetEpisodes" => rpc_result(&body, &episodes),
+                        "VideoLibrary.SetEpisodeDetails" => {
+                            let params = &body["params"];
+                            recorded.lock().unwrap().push((
+                                params["episodeid"].as_u64().unwrap(),
+                                params["playcount"].as_u64().unwrap() as u32,
+                            ));
+                            rpc_result(&body, &json!("OK"))
+                        }
+                        _ => Response::from_string(
+                            json!({
+                                "jsonrpc": "2.0",
+                                "id": body["id"],
+                                "error": { "code": -32601, "message": "Method not found." },
+                            })
+                            .to_string(),
+                        ),
+                    };
+                    let _ = request.respond(response);
+                }
+            });
+
+            Self { url, updates }
+        }
+
+        fn updates(&self) -> Vec<(u64, u32)> {
+            self.updates.lock().unwrap().clone()
+        }
+    }
+
+    fn rpc_result(request: &Value, result: &Value) -> Response<std::io::Cursor<Vec<u8>>> {
+        Response::from_string(
+            json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string(),
+        )
+    }
+
+    /// A library where the sidecar and Kodi agree on episodes 1 and 4, and
+    /// disagree on 2 (watched locally) and 3 (watched in Kodi).
+    struct Fixture {
+        dir: Library,
+        kodi: MockKodi,
+    }
+
+    impl Fixture {
+        fn new(extra: &[Value]) -> Self {
+            let dir = library(&[
+                "Show/S01E01.mkv",
+                "Show/S01E02.mkv",
+                "Show/S01E03.mkv",
+                "Show/S01E04.mkv",
+                "Show/S01E05.ts",
+            ]);
+            let mut operation = Operation::new("test");
+            for name in ["S01E01.mkv", "S01E02.mkv"] {
+                mark_watched(&dir.path().join("Show").join(name), &mut operation).unwrap();
+            }
+
+            let episode = |id: u64, name: &str, playcount: u32| {
+                json!({
+                    "episodeid": id,
+                    "label": format!("1x0{id}. Episode {id}"),
+                    "file": format!("smb://nas/TV/Show/{name}"),
+                    "playcount": playcount,
+                })
+            };
+            let mut episodes = vec![
+                episode(1, "S01E01.mkv", 2),
+                episode(2, "S01E02.mkv", 0),
+                episode(3, "S01E03.mkv", 1),
+                episode(4, "S01E04.mkv", 0),
+            ];
+            episodes.extend_from_slice(extra);
+
+            let kodi = MockKodi::start(json!({
+                "episodes": episodes,
+                "limits": { "start": 0, "end": episodes.len(), "total": episodes.len() },
+            }));
+            Self { dir, kodi }
+        }
+
+        fn sync(&self, prefer: Prefer, dry_run: bool) -> Result<()> {
+            run(KodiArgs {
+                url: self.kodi.url.clone(),
+                username: "kodi".to_owned(),
+                password: None,
+                rewrites: vec![format!("smb://nas/TV={}", self.dir.path().display())
+                    .parse()
+                    .unwrap()],
+                prefer,
+                dry_run,
+            })
+        }
+
+        fn watched_locally(&self) -> Vec<u32> {
+            (1..=4)
+                .filter(|x| {
+                    let video = self.dir.path().join(format!("Show/S01E0{x}.mkv"));
+                    let config = Config::for_path(&video).unwrap();
+                    read_entry(&config, &video)
+                        .unwrap()
+                        .is_some_and(|x| x.watched)
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn prefer_watched_marks_both_sides() {
+        let fixture = Fixture::new(&[]);
+        fixture.sync(Prefer::Watched, false).unwrap();
+        assert_eq!(fixture.kodi.updates(), [(2, 1)]);
+        assert_eq!(fixture.watched_locally(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn prefer_kodi_matches_kodi() {
+        let fixture = Fixture::new(&[]);
+        fixture.sync(Prefer::Kodi, false).unwrap();
+        assert_eq!(fixture.kodi.updates(), []);
+        assert_eq!(fixture.watched_locally(), [1, 3]);
+    }
+
+    #[test]
+    fn prefer_local_matches_sidecars() {
+        let fixture = Fixture::new(&[]);
+        fixture.sync(Prefer::Local, false).unwrap();
+        assert_eq!(fixture.kodi.updates(), [(2, 1), (3, 0)]);
+        assert_eq!(fixture.watched_locally(), [1, 2]);
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        for prefer in [Prefer::Watched, Prefer::Kodi, Prefer::Local] {
+            let fixture = Fixture::new(&[]);
+            fixture.sync(prefer, true).unwrap();
+            assert_eq!(fixture.kodi.updates(), []);
+            assert_eq!(fixture.watched_locally(), [1, 2]);
+        }
+    }
+
+    #[test]
+    fn skips_missing_and_unsupported_episodes() {
+        let fixture = Fixture::new(&[
+            json!({
+                "episodeid": 5,
+                "label": "1x05. Episode 5",
+                "file": "smb://nas/TV/Show/S01E05.ts",
+                "playcount": 1,
+            }),
+            json!({
+                "episodeid": 6,
+                "label": "1x06. Episode 6",
+                "file": "smb://nas/TV/Show/S01E06.mkv",
+                "playcount": 1,
+            }),
+        ]);
+        fixture.sync(Prefer::Watched, false).unwrap();
+        assert_eq!(fixture.kodi.updates(), [(2, 1)]);
+        assert_eq!(fixture.watched_locally(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn reports_rpc_errors() {
+        let fixture = Fixture::new(&[]);
+        let client = Client::new(&KodiArgs {
+            url: fixture.kodi.url.clone(),
+            username: "kodi".to_owned(),
+            password: None,
+            rewrites: Vec::new(),
+            prefer: Prefer::Watched,
+            dry_run: false,
+        });
+        let err = client
+            .call::<Value>("VideoLibrary.Scan", json!({}))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "VideoLibrary.Scan failed: Method not found. (-32601)"
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Args;
+use common::{config::Config, library};
+
+#[derive(Args)]
+pub struct ListArgs {
+    /// Library directory to list the videos of.
+    #[arg(default_value = ".")]
+    root: PathBuf,
+    /// Only list watched videos.
+    #[arg(long, conflicts_with = "unwatched")]
+    watched: bool,
+    /// Only list videos that haven't been watched.
+    #[arg(long)]
+    unwatched: bool,
+}
+
+/// Prints each video with how many times it has been watched (`x2`), or how
+/// far into it playback got if it hasn't been finished, followed by a `*` for
+/// favourites, its rating and its note.
+pub fn run(args: ListArgs) -> Result<()> {
+    let config = Config::for_path(&args.root)?;
+
+    for video in library::scan(&config, &args.root)? {
+        let entry = video.entry.as_ref();
+        let watched = entry.is_some_and(|x| x.watched);
+        if (args.watched && !watched) || (args.unwatched && watched) {
+            continue;
+        }
+
+        let status = match entry {
+            Some(entry) if entry.watched => format!("x{}", entry.count),
+            Some(entry) => entry
+                .progress
+                .filter(|x| x.duration > 0)
+                .map(|x| format!("{}%", x.position * 100 / x.duration))
+                .unwrap_or_default(),
+            None => String::new(),
+        };
+
+        let favourite = match entry.is_some_and(|x| x.favourite) {
+            true => "*",
+            false => " ",
+        };
+        let rating = entry
+            .and_then(|x| x.rating)
+            .map(|x| format!("{x}/10"))
+            .unwrap_or_default();
+        let note = entry
+            .and_then(|x| x.note.as_deref())
+            .map(|x| format!("  # {x}"))
+            .unwrap_or_default();
+
+        let path = video.path.strip_prefix(&args.root).unwrap_or(&video.path);
+        println!(
+            "{status:>4} {favourite} {rating:>5}  {}{note}",
+            path.display()
+        );
+    }
+
+    Ok(())
+}
@@ -1,67 +1,378 @@
 use std::{
-    borrow::Borrow,
+    env,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use anyhow::{bail, Context, Result};
-use clap::Parser;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
 use common::{
-    sidecar::{open_or_create_sidecar, open_sidecar, Sidecar},
-    VIDEO_EXTENSIONS,
+    batch,
+    config::{Config, USER_ENV},
+    history::{record, Action, Event, Source},
+    journal::Operation,
+    sidecar::{open_or_create_sidecar, open_sidecar, read_entry, Entry, Sidecar},
 };
 
+mod desktop;
+mod export;
+mod history;
+mod import;
+mod kodi;
+mod list;
+mod mpc;
+mod plex;
+mod rewrite;
+mod stats;
+mod trakt;
+mod undo;
+mod webhooks;
+
 #[derive(Parser)]
-pub enum Cli {
-    /// Mark a video file as watched
+pub struct Cli {
+    /// Use the watched state of this user, instead of the one from the
+    /// config or LAST_WATCHED_USER.
+    #[arg(long, global = true)]
+    user: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Mark video files as watched, or as watched again if they already are
     Watched {
-        /// File to mark. Must be a video file.
-        file: PathBuf,
+        /// Video files to mark, or directories to mark every unwatched video in.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Set how many times the videos have been watched instead of adding one.
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "reset_count")]
+        count: Option<u32>,
+        /// Set the videos back to being watched once.
+        #[arg(long)]
+        reset_count: bool,
+        /// Also mark every unwatched episode before the video, like "Mark all
+        /// up to here as watched" in the context menu.
+        #[arg(long)]
+        up_to: bool,
     },
-    /// Mark a video file as unwatched
+    /// Mark video files as unwatched
     Unwatched {
-        /// File to mark. Must be a video file.
+        /// Video files to mark, or directories to mark every video in.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Rate a video out of 10
+    Rate {
+        /// Video file to rate.
+        file: PathBuf,
+        /// Rating from 1 to 10.
+        #[arg(
+            value_parser = clap::value_parser!(u8).range(1..=10),
+            required_unless_present = "clear",
+            conflicts_with = "clear"
+        )]
+        rating: Option<u8>,
+        /// Remove the video's rating instead.
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Mark a video as a favourite
+    Fav {
+        /// Video file to mark.
         file: PathBuf,
+        /// Stop it being a favourite instead.
+        #[arg(long)]
+        remove: bool,
     },
+    /// Add a short note to a video, or remove it if no note is given
+    Note {
+        /// Video file to add the note to.
+        file: PathBuf,
+        /// The note. Tabs and newlines are replaced with spaces.
+        note: Option<String>,
+    },
+    /// Mark videos played in MPC-HC / MPC-BE as watched, using its web interface
+    Mpc {
+        /// Address of the player's web interface.
+        #[arg(long, default_value = "http://localhost:13579")]
+        url: String,
+        /// Seconds to wait between polls.
+        #[arg(long, default_value_t = 1)]
+        interval: u64,
+    },
+    /// Sync watched episodes with Kodi's library in both directions
+    KodiSync(kodi::KodiArgs),
+    /// Listen for playback webhooks from Jellyfin, Emby or Plex and mark completed videos as watched
+    ServeWebhooks(webhooks::WebhookArgs),
+    /// Import watch history exported from another program
+    Import(import::ImportArgs),
+    /// Export the watched videos in a library for use in another program
+    Export(export::ExportArgs),
+    /// List the videos in a library with how many times they have been watched
+    List(list::ListArgs),
+    /// Show statistics about what has been watched in a library
+    Stats(stats::StatsArgs),
+    /// Show when videos in a library were marked, unmarked or had their progress saved
+    History(history::HistoryArgs),
+    /// Reverse the most recent changes made by the cli
+    Undo(undo::UndoArgs),
+    /// Add actions to Linux file managers and badge watched videos in them
+    DesktopIntegration(desktop::DesktopArgs),
 }
 
 fn main() -> Result<()> {
     let args = Cli::parse();
 
-    match args {
-        Cli::Watched { file } => {
-            let file_name = get_file_name(&file)?;
-            let sidecar = open_or_create_sidecar(&file)?;
-            let mut sidecar = Sidecar::new(sidecar)?;
-            sidecar.add(&file_name)?;
-        }
-        Cli::Unwatched { file } => {
-            let file_name = get_file_name(&file)?;
-            let Some(sidecar) = open_sidecar(&file) else {
-                return Ok(());
+    // Every config is loaded through the environment, so this reaches all of them
+    if let Some(user) = &args.user {
+        env::set_var(USER_ENV, user);
+    }
+
+    match args.command {
+        Command::Watched {
+            files,
+            count,
+            reset_count,
+            up_to,
+        } => {
+            let description = match up_to {
+                true => "watched up to",
+                false => "watched",
             };
+            let mut operation = Operation::new(describe(description, &files));
+            for file in videos(&files, up_to, false)? {
+                match count.or(reset_count.then_some(1)) {
+                    Some(count) => set_watch_count(&file, count, &mut operation)?,
+                    None => mark_watched(&file, &mut operation)?,
+                }
+            }
+        }
+        Command::Unwatched { files } => {
+            let mut operation = Operation::new(describe("unwatched", &files));
+            for file in videos(&files, false, true)? {
+                mark_unwatched(&file, &mut operation)?
+            }
+        }
+        Command::Rate { file, rating, .. } => {
+            let mut operation = Operation::new(format!("rate {}", file.display()));
+            update_entry(&file, &mut operation, |entry| entry.rating = rating)?
+        }
+        Command::Fav { file, remove } => {
+            let mut operation = Operation::new(format!("fav {}", file.display()));
+            update_entry(&file, &mut operation, |entry| entry.favourite = !remove)?
+        }
+        Command::Note { file, note } => {
+            let mut operation = Operation::new(format!("note {}", file.display()));
+            let note = note
+                .map(|x| x.replace(['\t', '\r', '\n'], " ").trim().to_owned())
+                .filter(|x| !x.is_empty());
+            update_entry(&file, &mut operation, |entry| entry.note = note)?
+        }
+        Command::Mpc { url, interval } => mpc::run(&url, Duration::from_secs(interval))?,
+        Command::KodiSync(args) => kodi::run(args)?,
+        Command::ServeWebhooks(args) => webhooks::run(args)?,
+        Command::Import(args) => import::run(args)?,
+        Command::Export(args) => export::run(args)?,
+        Command::List(args) => list::run(args)?,
+        Command::Stats(args) => stats::run(args)?,
+        Command::History(args) => history::run(args)?,
+        Command::Undo(args) => undo::run(args)?,
+        Command::DesktopIntegration(args) => desktop::run(args)?,
+    }
+
+    Ok(())
+}
 
-            let mut sidecar = Sidecar::new(sidecar?)?;
-            sidecar.remove(&file_name)?;
+/// Finds the videos to mark for the files given to a command, the same way
+/// the context menu does. Files have to be videos and are always marked, but
+/// videos found in directories or by `up_to` are skipped if they already are
+/// (or aren't, for unmarking) `watched`.
+fn videos(files: &[PathBuf], up_to: bool, watched: bool) -> Result<Vec<PathBuf>> {
+    for file in files.iter().filter(|x| !x.is_dir()) {
+        if !file.exists() {
+            bail!("{} does not exist", file.display());
         }
+        entry_name(&Config::for_path(file)?, file)?;
     }
 
+    let videos = match (up_to, files) {
+        (true, [file]) if !file.is_dir() => batch::up_to(file)?,
+        (true, _) => bail!("--up-to needs a single video file"),
+        (false, _) => batch::expand(files)?,
+    };
+
+    let mut marked = Vec::new();
+    for video in videos {
+        let config = Config::for_path(&video)?;
+        if (!up_to && files.contains(&video))
+            || read_entry(&config, &video)?.is_some_and(|x| x.watched) == watched
+        {
+            marked.push(video);
+        }
+    }
+
+    Ok(marked)
+}
+
+/// Describes a command for the journal by what it was given.
+fn describe(command: &str, files: &[PathBuf]) -> String {
+    let files = files
+        .iter()
+        .map(|x| x.display().to_string())
+        .collect::<Vec<_>>();
+    format!("{command} {}", files.join(" "))
+}
+
+fn mark_watched(file: &Path, operation: &mut Operation) -> Result<()> {
+    mark_watched_at(file, Utc::now(), Source::Cli, operation)
+}
+
+fn mark_watched_at(
+    file: &Path,
+    time: DateTime<Utc>,
+    source: Source,
+    operation: &mut Operation,
+) -> Result<()> {
+    let config = modify(file, operation, |sidecar, name| sidecar.add_at(name, time))?;
+    record(
+        &config,
+        &Event {
+            time,
+            ..Event::new(source, Action::Watched, file)
+        },
+    )
+}
+
+fn set_watch_count(file: &Path, count: u32, operation: &mut Operation) -> Result<()> {
+    let config = modify(file, operation, |sidecar, name| {
+        sidecar.set_count(name, count)
+    })?;
+    record(&config, &Event::new(Source::Cli, Action::Watched, file))
+}
+
+/// Changes the rating, favourite or note of a video.
+fn update_entry(
+    file: &Path,
+    operation: &mut Operation,
+    change: impl FnOnce(&mut Entry),
+) -> Result<()> {
+    modify(file, operation, |sidecar, name| {
+        sidecar.update(name, change)
+    })?;
     Ok(())
 }
 
-fn get_file_name(file: &Path) -> Result<String> {
-    let ext = file
-        .extension()
-        .context("Provided path has no extension")?
-        .to_string_lossy();
+/// Changes the entry of a video, creating its sidecar if needed, and adds the
+/// change to the journal. Returns the config used for the video.
+fn modify(
+    file: &Path,
+    operation: &mut Operation,
+    change: impl FnOnce(&mut Sidecar, &str) -> Result<()>,
+) -> Result<Config> {
+    let config = Config::for_path(file)?;
+    let name = entry_name(&config, file)?;
+    let sidecar = open_or_create_sidecar(&config, file)?;
+    let mut sidecar = Sidecar::new(sidecar)?;
+
+    let before = sidecar.get(&name).cloned();
+    let was_watched = sidecar.is_watched(&name);
+    change(&mut sidecar, &name)?;
+    journal(&config, file, &sidecar, &name, before, operation)?;
+
+    if sidecar.is_watched(&name) != was_watched {
+        desktop::sync(&config, file, !was_watched);
+    }
+    Ok(config)
+}
+
+fn mark_unwatched(file: &Path, operation: &mut Operation) -> Result<()> {
+    let config = Config::for_path(file)?;
+    let name = entry_name(&config, file)?;
+    let Some(sidecar) = open_sidecar(&config, file) else {
+        return Ok(());
+    };
+
+    let mut sidecar = Sidecar::new(sidecar?)?;
+    let before = sidecar.get(&name).cloned();
+    sidecar.remove(&name)?;
+    journal(&config, file, &sidecar, &name, before, operation)?;
+    desktop::sync(&config, file, false);
+
+    record(&config, &Event::new(Source::Cli, Action::Unwatched, file))
+}
+
+/// Adds the change to an entry to the journal, so it can be undone.
+fn journal(
+    config: &Config,
+    file: &Path,
+    sidecar: &Sidecar,
+    name: &str,
+    before: Option<Entry>,
+    operation: &mut Operation,
+) -> Result<()> {
+    let path = config
+        .sidecar_path(file)
+        .context("Can't open sidecar for root directory")?;
+    operation.record(&path, name, before.as_ref(), sidecar.get(name))
+}
+
+/// Gets the name a video is stored under in its sidecar, making sure it's a
+/// video file.
+fn entry_name(config: &Config, file: &Path) -> Result<String> {
+    if !config.is_video(file) {
+        match file.extension() {
+            Some(_) => bail!("Provided file is not a video file"),
+            None => bail!("Provided path has no extension"),
+        }
+    }
+
+    config
+        .entry_name(file)
+        .context("Provided path is not a file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::test_util::library;
 
-    if !VIDEO_EXTENSIONS.contains(&ext.borrow()) {
-        bail!("Provided file is not a video file");
+    #[test]
+    fn rating_and_clear_conflict() {
+        let rate = |args: &[&str]| Cli::try_parse_from(["last-watched", "rate"].iter().chain(args));
+        assert!(rate(&["f.mkv", "5"]).is_ok());
+        assert!(rate(&["f.mkv", "--clear"]).is_ok());
+        assert!(rate(&["f.mkv"]).is_err());
+        assert!(rate(&["f.mkv", "5", "--clear"]).is_err());
     }
 
-    let file_name = file
-        .file_name()
-        .context("Provided path is not a file")?
-        .to_string_lossy();
+    #[test]
+    fn rejects_missing_files() {
+        let library = library(&["Show/Show.S01E01.mkv"]);
+        let missing = library.path().join("Show/Typo.S01E09.mkv");
+        let files = [library.path().join("Show/Show.S01E01.mkv"), missing.clone()];
 
-    Ok(file_name.into_owned())
+        let err = videos(&files, false, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{} does not exist", missing.display())
+        );
+        assert!(videos(&[missing], true, false).is_err());
+    }
+
+    #[test]
+    fn named_videos_are_always_marked() {
+        let library = library(&["Show/Show.S01E01.mkv", "Show/Show.S01E02.mkv"]);
+        let first = library.path().join("Show/Show.S01E01.mkv");
+        mark_watched(&first, &mut Operation::new("test".to_owned())).unwrap();
+
+        let dir = library.path().join("Show");
+        let unwatched = videos(std::slice::from_ref(&dir), false, false).unwrap();
+        assert_eq!(unwatched, [library.path().join("Show/Show.S01E02.mkv")]);
+        assert_eq!(
+            videos(std::slice::from_ref(&first), false, false).unwrap(),
+            [first]
+        );
+    }
 }
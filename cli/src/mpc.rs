@@ -0,0 +1,355 @@
+use std::{
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use common::{
+    config::Config,
+    journal::Operation,
+    progress::{is_watched, WATCHED_THRESHOLD},
+};
+use ureq::Agent;
+
+use crate::mark_watched;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Player state as reported by MPC-HC / MPC-BE in `variables.html`.
+struct Variables {
+    file: Option<PathBuf>,
+    position: Duration,
+    duration: Duration,
+}
+
+/// The file currently open in the player and whether it has been marked yet.
+struct Session {
+    file: PathBuf,
+    threshold: f64,
+    marked: bool,
+}
+
+/// Connection to the web interface of MPC-HC / MPC-BE.
+struct Player {
+    agent: Agent,
+    url: String,
+    endpoint: String,
+    connected: bool,
+    session: Option<Session>,
+}
+
+/// Polls the web interface of MPC-HC / MPC-BE and marks files as watched once
+/// playback passes the watched threshold. Runs until the process is killed.
+pub fn run(url: &str, interval: Duration) -> Result<()> {
+    let mut player = Player::new(url);
+
+    loop {
+        player.poll();
+        thread::sleep(if player.connected {
+            interval
+        } else {
+            RECONNECT_INTERVAL
+        });
+    }
+}
+
+impl Player {
+    fn new(url: &str) -> Self {
+        Self {
+            agent: Agent::config_builder()
+                .timeout_global(Some(REQUEST_TIMEOUT))
+                .build()
+                .into(),
+            url: url.to_owned(),
+            endpoint: format!("{}/variables.html", url.trim_end_matches('/')),
+            connected: false,
+            session: None,
+        }
+    }
+
+    /// Fetches the player's state once, marking the open file if it's far
+    /// enough in. The session is forgotten when the connection is lost.
+    fn poll(&mut self) {
+        match fetch(&self.agent, &self.endpoint) {
+            Ok(variables) => {
+                if !self.connected {
+                    println!("Connected to player at {}", self.url);
+                    self.connected = true;
+                }
+
+                update(&variables, &mut self.session);
+            }
+            Err(err) => {
+                if self.connected {
+                    println!("Lost connection to player: {err:#}");
+                    println!("Waiting for it to come back...");
+                    self.connected = false;
+                    self.session = None;
+                }
+            }
+        }
+    }
+}
+
+fn fetch(agent: &Agent, endpoint: &str) -> Result<Variables> {
+    let html = agent.get(endpoint).call()?.body_mut().read_to_string()?;
+    Variables::parse(&html)
+}
+
+fn update(variables: &Variables, session: &mut Option<Session>) {
+    let Some(file) = &variables.file else {
+        *session = None;
+        return;
+    };
+
+    let session = match session {
+        Some(session) if &session.file == file => session,
+        _ => session.insert(Session {
+            file: file.clone(),
+            threshold: threshold(file),
+            marked: false,
+        }),
+    };
+
+    if session.marked || !is_watched(variables.position, variables.duration, session.threshold) {
+        return;
+    }
+
+    // Only try once per session, so unsupported files don't spam errors every poll
+    session.marked = true;
+    let mut operation = Operation::new(format!("mpc {}", file.display()));
+    match mark_watched(file, &mut operation) {
+        Ok(()) => println!("Marked {} as watched", file.display()),
+        Err(err) => println!("Failed to mark {}: {err:#}", file.display()),
+    }
+}
+
+fn threshold(file: &Path) -> f64 {
+    match Config::for_path(file) {
+        Ok(config) => config.watched_threshold,
+        Err(err) => {
+            println!("Failed to load config: {err:#}");
+            WATCHED_THRESHOLD
+        }
+    }
+}
+
+impl Variables {
+    fn parse(html: &str) -> Result<Self> {
+        let file = variable(html, "filepath").context("Missing filepath variable")?;
+        let position = variable(html, "position").context("Missing position variable")?;
+        let duration = variable(html, "duration").context("Missing duration variable")?;
+
+        Ok(Self {
+            file: (!file.is_empty()).then(|| PathBuf::from(unescape(file))),
+            position: Duration::from_millis(position.parse()?),
+            duration: Duration::from_millis(duration.parse()?),
+        })
+    }
+}
+
+/// Gets the contents of the `<p id="...">` element with the given id.
+fn variable<'a>(html: &'a str, id: &str) -> Option<&'a str> {
+    let start = format!("id=\"{id}\">");
+    let value = &html[html.find(&start)? + start.len()..];
+    Some(value[..value.find("</p>")?].trim())
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(idx) = rest.find('&') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+
+        let decoded = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity
+                .strip_prefix('#')
+                .and_then(|code| match code.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => code.parse().ok(),
+                })
+                .and_then(char::from_u32),
+        };
+
+        match decoded {
+            Some(chr) => {
+                out.push(chr);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use common::sidecar::read_entry;
+    use tiny_http::{Response, Server};
+
+    use super::*;
+    use common::test_util::library;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/mpc-variables.html");
+
+    /// Stand-in for the player's web interface, serving `page` or failing
+    /// while it's none.
+    struct StubPlayer {
+        url: String,
+        page: Arc<Mutex<Option<String>>>,
+    }
+
+    impl StubPlayer {
+        fn start() -> Self {
+            let server = Server::http("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+            let page = Arc::new(Mutex::new(None::<String>));
+
+            let serving = page.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let response = match (request.url(), &*serving.lock().unwrap()) {
+                        ("/variables.html", Some(page)) => Response::from_string(page.clone()),
+                        _ => Response::from_string("").with_status_code(503),
+                    };
+                    let _ = request.respond(response);
+                }
+            });
+
+            Self { url, page }
+        }
+
+        /// Serves the fixture playing `file` at `position` milliseconds.
+        fn play(&self, file: &Path, position: u64) {
+            let page = set_variable(FIXTURE, "filepath", &file.to_string_lossy());
+            let page = set_variable(&page, "position", &position.to_string());
+            *self.page.lock().unwrap() = Some(page);
+        }
+
+        fn stop(&self) {
+            *self.page.lock().unwrap() = None;
+        }
+    }
+
+    fn set_variable(html: &str, id: &str, value: &str) -> String {
+        let start = format!("id=\"{id}\">");
+        let from = html.find(&start).unwrap() + start.len();
+        let to = from + html[from..].find("</p>").unwrap();
+        let value = value.replace('&', "&amp;").replace('<', "&lt;");
+        format!("{}{value}{}", &html[..from], &html[to..])
+    }
+
+    fn watch_count(video: &Path) -> u32 {
+        let config = Config::for_path(video).unwrap();
+        read_entry(&config, video)
+            .unwrap()
+            .filter(|x| x.watched)
+            .map_or(0, |x| x.count)
+    }
+
+    #[test]
+    fn parses_fixture() {
+        let variables = Variables::parse(FIXTURE).unwrap();
+        assert_eq!(
+            variables.file,
+            Some(PathBuf::from(r"C:\Videos\Tom & Jerry – S01E01.mkv"))
+        );
+        assert_eq!(variables.position, Duration::from_millis(1_263_000));
+        assert_eq!(variables.duration, Duration::from_millis(1_403_000));
+    }
+
+    #[test]
+    fn parses_stopped_player() {
+        let html = set_variable(FIXTURE, "filepath", "");
+        assert_eq!(Variables::parse(&html).unwrap().file, None);
+    }
+
+    #[test]
+    fn rejects_incomplete_page() {
+        assert!(Variables::parse("<html><body></body></html>").is_err());
+        let html = set_variable(FIXTURE, "duration", "unknown");
+        assert!(Variables::parse(&html).is_err());
+    }
+
+    #[test]
+    fn unescapes_entities() {
+        assert_eq!(unescape("Tom &amp; Jerry"), "Tom & Jerry");
+        assert_eq!(unescape("&lt;&gt;&quot;&apos;"), "<>\"'");
+        assert_eq!(unescape("&#233;&#xE9;&#XE9;"), "ééé");
+        assert_eq!(unescape("a &unknown; b"), "a &unknown; b");
+        assert_eq!(unescape("&#xFFFFFFFF;"), "&#xFFFFFFFF;");
+        assert_eq!(unescape("R&D"), "R&D");
+        assert_eq!(unescape("trailing &"), "trailing &");
+    }
+
+    #[test]
+    fn marks_at_threshold() {
+        let dir = library(&["Episode 1.mkv"]);
+        let video = dir.path().join("Episode 1.mkv");
+        let stub = StubPlayer::start();
+        let mut player = Player::new(&stub.url);
+
+        // The fixture is 1403 seconds long, and the default threshold is 90%
+        stub.play(&video, 1_262_000);
+        player.poll();
+        assert!(player.connected);
+        assert_eq!(watch_count(&video), 0);
+
+        stub.play(&video, 1_262_700);
+        player.poll();
+        assert_eq!(watch_count(&video), 1);
+
+        // Only marked once while the same file stays open
+        stub.play(&video, 1_400_000);
+        player.poll();
+        assert_eq!(watch_count(&video), 1);
+    }
+
+    #[test]
+    fn reconnects_after_losing_player() {
+        let dir = library(&["Episode 1.mkv"]);
+        let video = dir.path().join("Episode 1.mkv");
+        let stub = StubPlayer::start();
+        let mut player = Player::new(&stub.url);
+
+        player.poll();
+        assert!(!player.connected);
+
+        stub.play(&video, 1_300_000);
+        player.poll();
+        assert!(player.connected);
+        assert!(player.session.as_ref().is_some_and(|x| x.marked));
+        assert_eq!(watch_count(&video), 1);
+
+        stub.stop();
+        player.poll();
+        assert!(!player.connected);
+        assert!(player.session.is_none());
+
+        // Coming back starts a new session, as the file may have been reopened
+        stub.play(&video, 1_300_000);
+        player.poll();
+        assert!(player.connected);
+        assert_eq!(watch_count(&video), 2);
+    }
+}
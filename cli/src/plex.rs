@@ -0,0 +1,47 @@
+use serde::Deserialize;
+
+/// Response from Plex's library endpoints when requested as JSON, which is
+/// also the format library exports are expected in.
+#[derive(Deserialize)]
+pub struct MediaContainer {
+    #[serde(rename = "MediaContainer")]
+    pub container: Container,
+}
+
+#[derive(Deserialize)]
+pub struct Container {
+    #[serde(rename = "Metadata", default)]
+    pub metadata: Vec<Metadata>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    pub rating_key: Option<String>,
+    #[serde(default)]
+    pub view_count: u32,
+    pub last_viewed_at: Option<i64>,
+    #[serde(rename = "Media", default)]
+    pub media: Vec<Media>,
+}
+
+#[derive(Deserialize)]
+pub struct Media {
+    #[serde(rename = "Part", default)]
+    pub part: Vec<Part>,
+}
+
+#[derive(Deserialize)]
+pub struct Part {
+    pub file: String,
+}
+
+impl Metadata {
+    /// Paths on the Plex server of all the files that make up this item.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.media
+            .iter()
+            .flat_map(|media| &media.part)
+            .map(|part| part.file.as_str())
+    }
+}
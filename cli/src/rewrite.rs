@@ -0,0 +1,51 @@
+use std::{path::PathBuf, str::FromStr};
+
+use anyhow::{Context, Error, Result};
+
+/// Maps a path prefix used by a media server onto a local path, written as
+/// `SERVER=LOCAL`. For example `/media/tv=D:\TV` or `smb://nas/tv=/mnt/tv`.
+#[derive(Clone, Debug)]
+pub struct Rewrite {
+    from: String,
+    to: PathBuf,
+}
+
+impl Rewrite {
+    fn apply(&self, path: &str) -> Option<PathBuf> {
+        let from = self.from.trim_end_matches(['/', '\\']);
+        let rest = path.strip_prefix(from)?;
+
+        // Only match on whole path components
+        if !rest.is_empty() && !rest.starts_with(['/', '\\']) {
+            return None;
+        }
+
+        let mut out = self.to.clone();
+        out.extend(rest.split(['/', '\\']).filter(|x| !x.is_empty()));
+        Some(out)
+    }
+}
+
+/// Converts a path reported by a media server into a local path using the
+/// first matching rewrite, falling back to the path unchanged.
+pub fn rewrite_path(rewrites: &[Rewrite], path: &str) -> PathBuf {
+    rewrites
+        .iter()
+        .find_map(|rewrite| rewrite.apply(path))
+        .unwrap_or_else(|| PathBuf::from(path))
+}
+
+impl FromStr for Rewrite {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (from, to) = s
+            .split_once('=')
+            .context("Rewrites must be in the form SERVER=LOCAL")?;
+
+        Ok(Self {
+            from: from.to_owned(),
+            to: PathBuf::from(to),
+        })
+    }
+}
@@ -0,0 +1,427 @@
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeDelta, Utc, Weekday};
+use clap::Args;
+use common::{config::Config, episode::Episode, library, probe::probe};
+use serde::Serialize;
+
+/// Shows with unwatched episodes that haven't been watched in this long are
+/// considered abandoned.
+const ABANDONED_AFTER: TimeDelta = TimeDelta::days(90);
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Library directory to show the statistics of.
+    root: PathBuf,
+    /// Print the statistics as JSON instead of tables.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    videos: usize,
+    watched: usize,
+    /// Watched videos without a known time, which are left out of the
+    /// monthly, weekday and streak breakdowns.
+    unknown_time: usize,
+    /// Watched videos that were watched more than once. Only their last
+    /// watch has a time, so the hours of every watch are counted in the
+    /// month and weekday of the last one.
+    rewatched: usize,
+    hours: f64,
+    completion: f64,
+    longest_streak: Option<Streak>,
+    shows: Vec<ShowStats>,
+    months: Vec<Period>,
+    weekdays: Vec<Period>,
+}
+
+#[derive(Serialize)]
+struct ShowStats {
+    title: String,
+    episodes: usize,
+    watched: usize,
+    hours: f64,
+    completion: f64,
+    last_watched: Option<DateTime<Utc>>,
+    abandoned: bool,
+}
+
+#[derive(Default, Serialize)]
+struct Period {
+    name: String,
+    watched: usize,
+    hours: f64,
+}
+
+/// The most consecutive days with something watched on each of them.
+#[derive(Serialize)]
+struct Streak {
+    days: usize,
+    start: NaiveDate,
+    end: NaiveDate,
+}
+
+/// A watched video with the time it was last watched, and how long all of
+/// its watches took.
+struct Watch {
+    time: Option<DateTime<Utc>>,
+    count: u32,
+    hours: f64,
+}
+
+#[derive(Default)]
+struct Show {
+    episodes: usize,
+    watches: Vec<Watch>,
+}
+
+pub fn run(args: StatsArgs) -> Result<()> {
+    let config = Config::for_path(&args.root)?;
+    let videos = library::scan(&config, &args.root)?;
+
+    let mut watches = Vec::new();
+    let mut shows = BTreeMap::<String, Show>::new();
+    let mut hours = 0.0;
+
+    for video in &videos {
+        let episode = Episode::parse(&video.path);
+        if let Some(episode) = &episode {
+            shows.entry(episode.show.clone()).or_default().episodes += 1;
+        }
+
+        let Some(entry) = &video.entry else {
+            continue;
+        };
+
+        if !entry.watched {
+            // Count how far into unfinished videos playback got
+            hours += entry.progress.map_or(0.0, |x| x.position as f64 / 3600.0);
+            continue;
+        }
+
+        // Fall back to the length saved with the progress if it can't be probed
+        let duration = probe(&video.path)
+            .ok()
+            .and_then(|x| x.duration)
+            .or_else(|| entry.progress.map(|x| Duration::from_secs(x.duration)))
+            .unwrap_or_default();
+
+        // Rewatches count towards the hours watched
+        let watch = Watch {
+            time: entry.watched_at,
+            count: entry.count,
+            hours: duration.as_secs_f64() / 3600.0 * f64::from(entry.count),
+        };
+        hours += watch.hours;
+
+        match episode {
+            Some(episode) => shows.entry(episode.show).or_default().watches.push(watch),
+            None => watches.push(watch),
+        }
+    }
+
+    let shows = shows
+        .into_iter()
+        .map(|(title, show)| {
+            let last_watched = show.watches.iter().filter_map(|x| x.time).max();
+            let watched = show.watches.len();
+            let stats = ShowStats {
+                title,
+                episodes: show.episodes,
+                watched,
+                hours: show.watches.iter().map(|x| x.hours).sum(),
+                completion: ratio(watched, show.episodes),
+                last_watched,
+                abandoned: watched > 0
+                    && watched < show.episodes
+                    && last_watched.is_some_and(|x| Utc::now() - x > ABANDONED_AFTER),
+            };
+
+            watches.extend(show.watches);
+            stats
+        })
+        .collect::<Vec<_>>();
+
+    let (months, weekdays, days) = periods(&watches);
+    let watched = watches.len();
+    let stats = Stats {
+        videos: videos.len(),
+        watched,
+        unknown_time: watched - days.len(),
+        rewatched: watches.iter().filter(|x| x.count > 1).count(),
+        hours,
+        completion: ratio(watched, videos.len()),
+        longest_streak: longest_streak(days),
+        shows,
+        months,
+        weekdays,
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+    } else {
+        print_stats(&stats);
+    }
+
+    Ok(())
+}
+
+/// Splits watches with a known time into months and weekdays, and finds the
+/// days they were on. Each video counts once, at the time of its last watch.
+fn periods(watches: &[Watch]) -> (Vec<Period>, Vec<Period>, Vec<NaiveDate>) {
+    let mut months = BTreeMap::<String, Period>::new();
+    let mut weekdays = WEEKDAYS.map(|x| Period {
+        name: x.to_string(),
+        ..Period::default()
+    });
+    let mut days = Vec::new();
+
+    for watch in watches {
+        let Some(time) = watch.time else {
+            continue;
+        };
+
+        let time = time.with_timezone(&Local);
+        let month = time.format("%Y-%m").to_string();
+        let periods = [
+            months.entry(month.clone()).or_insert_with(|| Period {
+                name: month,
+                ..Period::default()
+            }),
+            &mut weekdays[time.weekday().num_days_from_monday() as usize],
+        ];
+
+        for period in periods {
+            period.watched += 1;
+            period.hours += watch.hours;
+        }
+
+        days.push(time.date_naive());
+    }
+
+    (months.into_values().collect(), weekdays.into(), days)
+}
+
+fn longest_streak(mut days: Vec<NaiveDate>) -> Option<Streak> {
+    days.sort();
+    days.dedup();
+
+    let mut longest: Option<Streak> = None;
+    let mut start = 0;
+    for idx in 0..days.len() {
+        if idx > 0 && days[idx - 1].succ_opt() != Some(days[idx]) {
+            start = idx;
+        }
+
+        let len = idx - start + 1;
+        if longest.as_ref().is_none_or(|x| len > x.days) {
+            longest = Some(Streak {
+                days: len,
+                start: days[start],
+                end: days[idx],
+            });
+        }
+    }
+
+    longest
+}
+
+fn ratio(part: usize, total: usize) -> f64 {
+    match total {
+        0 => 0.0,
+        total => part as f64 / total as f64,
+    }
+}
+
+fn print_stats(stats: &Stats) {
+    println!(
+        "{} of {} videos watched ({:.0}%), {:.1} hours",
+        stats.watched,
+        stats.videos,
+        stats.completion * 100.0,
+        stats.hours
+    );
+
+    if stats.unknown_time > 0 {
+        println!("{} watched at an unknown time", stats.unknown_time);
+    }
+
+    if stats.rewatched > 0 {
+        println!(
+            "{} watched more than once, with every watch counted in the month and weekday of the last",
+            stats.rewatched
+        );
+    }
+
+    if let Some(streak) = &stats.longest_streak {
+        println!(
+            "Longest streak: {} days ({} to {})",
+            streak.days, streak.start, streak.end
+        );
+    }
+
+    if !stats.shows.is_empty() {
+        println!();
+        print_table(
+            &["Show", "Watched", "Hours", "Complete", "Last watched"],
+            stats.shows.iter().map(|show| {
+                vec![
+                    match show.abandoned {
+                        true => format!("{} (abandoned)", show.title),
+                        false => show.title.clone(),
+                    },
+                    format!("{}/{}", show.watched, show.episodes),
+                    format!("{:.1}", show.hours),
+                    format!("{:.0}%", show.completion * 100.0),
+                    show.last_watched
+                        .map(|x| x.with_timezone(&Local).date_naive().to_string())
+                        .unwrap_or_default(),
+                ]
+            }),
+        );
+    }
+
+    for (name, periods) in [("Month", &stats.months), ("Weekday", &stats.weekdays)] {
+        if periods.iter().all(|x| x.watched == 0) {
+            continue;
+        }
+
+        println!();
+        print_table(
+            &[name, "Watched", "Hours"],
+            periods.iter().map(|period| {
+                vec![
+                    period.name.clone(),
+                    period.watched.to_string(),
+                    format!("{:.1}", period.hours),
+                ]
+            }),
+        );
+    }
+}
+
+/// Prints rows with each column padded to its widest cell. Everything but
+/// the first column is right aligned.
+fn print_table(header: &[&str], rows: impl Iterator<Item = Vec<String>>) {
+    let rows = rows.collect::<Vec<_>>();
+    let widths = (0..header.len())
+        .map(|col| {
+            rows.iter()
+                .map(|row| row[col].chars().count())
+                .chain([header[col].len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let header = header.iter().map(|x| x.to_string()).collect();
+    for row in [header].iter().chain(&rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(col, (cell, &width))| match col {
+                0 => format!("{cell:<width$}"),
+                _ => format!("{cell:>width$}"),
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: &str) -> NaiveDate {
+        day.parse().unwrap()
+    }
+
+    fn watch(time: Option<&str>, count: u32, hours: f64) -> Watch {
+        Watch {
+            time: time.map(|x| format!("{x}T12:00:00Z").parse().unwrap()),
+            count,
+            hours,
+        }
+    }
+
+    #[test]
+    fn finds_the_longest_streak() {
+        assert!(longest_streak(Vec::new()).is_none());
+
+        let days = [
+            "2024-08-30",
+            "2024-08-02",
+            "2024-08-31",
+            "2024-08-01",
+            "2024-09-01",
+            "2024-08-31",
+            "2024-08-03",
+            "2024-08-10",
+        ];
+        let streak = longest_streak(days.map(day).into()).unwrap();
+        assert_eq!(
+            (streak.days, streak.start, streak.end),
+            (3, day("2024-08-01"), day("2024-08-03"))
+        );
+
+        // Across the end of a year, with watches on the same day only counted once
+        let days = [
+            "2023-12-31",
+            "2024-01-01",
+            "2023-12-30",
+            "2024-01-01",
+            "2024-03-01",
+        ];
+        let streak = longest_streak(days.map(day).into()).unwrap();
+        assert_eq!(
+            (streak.days, streak.start, streak.end),
+            (3, day("2023-12-30"), day("2024-01-01"))
+        );
+
+        let streak = longest_streak(vec![day("2024-02-29")]).unwrap();
+        assert_eq!(
+            (streak.days, streak.start, streak.end),
+            (1, day("2024-02-29"), day("2024-02-29"))
+        );
+    }
+
+    #[test]
+    fn counts_rewatches_when_last_watched() {
+        let watches = [
+            watch(Some("2024-08-05"), 1, 0.5),
+            watch(Some("2024-09-02"), 3, 1.5),
+            watch(Some("2024-09-03"), 1, 0.5),
+            watch(None, 1, 0.5),
+        ];
+        let (months, weekdays, days) = periods(&watches);
+
+        let months = months
+            .iter()
+            .map(|x| (x.name.as_str(), x.watched, x.hours))
+            .collect::<Vec<_>>();
+        assert_eq!(months, [("2024-08", 1, 0.5), ("2024-09", 2, 2.0)]);
+        assert_eq!((weekdays[0].watched, weekdays[0].hours), (2, 2.0));
+        assert_eq!((weekdays[1].watched, weekdays[1].hours), (1, 0.5));
+        assert_eq!(days.len(), 3);
+    }
+
+    #[test]
+    fn ratio_of_nothing_is_zero() {
+        assert_eq!(ratio(0, 0), 0.0);
+        assert_eq!(ratio(1, 4), 0.25);
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use common::episode::clean_title;
+use serde::{Deserialize, Serialize};
+
+/// Body of Trakt's `/sync/history` and `/sync/ratings` endpoints, used for exports.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SyncHistory {
+    #[serde(default)]
+    pub movies: Vec<Movie>,
+    #[serde(default)]
+    pub shows: Vec<Show>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Movie {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watched_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Show {
+    pub title: String,
+    #[serde(default)]
+    pub seasons: Vec<Season>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Season {
+    pub number: u32,
+    #[serde(default)]
+    pub episodes: Vec<SeasonEpisode>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SeasonEpisode {
+    pub number: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watched_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+}
+
+/// Item of the list returned by Trakt's `GET /sync/history`.
+#[derive(Deserialize)]
+pub struct HistoryItem {
+    pub watched_at: Option<DateTime<Utc>>,
+    pub movie: Option<HistoryTitle>,
+    pub show: Option<HistoryTitle>,
+    pub episode: Option<HistoryEpisode>,
+}
+
+#[derive(Deserialize)]
+pub struct HistoryTitle {
+    pub title: String,
+    pub year: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct HistoryEpisode {
+    pub season: u32,
+    pub number: u32,
+}
+
+/// Gets a movie's title and release year from a file name like
+/// `Movie.Name.2010.1080p.mkv` or `Movie Name (2010).mkv`.
+pub fn movie_title(path: &Path) -> (String, Option<u32>) {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let bytes = stem.as_bytes();
+
+    // Look for a standalone four digit number after the start of the title
+    let year = (1..bytes.len().saturating_sub(3)).find_map(|idx| {
+        let word = &bytes[idx..idx + 4];
+        let bounded = !bytes[idx - 1].is_ascii_alphanumeric()
+            && bytes
+                .get(idx + 4)
+                .is_none_or(|x| !x.is_ascii_alphanumeric());
+        if !bounded || !word.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+
+        let year = stem[idx..idx + 4].parse::<u32>().ok()?;
+        (1900..2100).contains(&year).then_some((idx, year))
+    });
+
+    match year {
+        Some((idx, year)) => (clean_title(&stem[..idx]), Some(year)),
+        None => (clean_title(&stem), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_movie_titles_and_years() {
+        let title = |name| movie_title(Path::new(name));
+        assert_eq!(
+            title("Movie.Name.2010.1080p.mkv"),
+            ("Movie Name".to_owned(), Some(2010))
+        );
+        assert_eq!(
+            title("Movie Name (2010).mkv"),
+            ("Movie Name".to_owned(), Some(2010))
+        );
+        assert_eq!(title("Movie_Name.mkv"), ("Movie Name".to_owned(), None));
+
+        // Only the first standalone year after the start of the title counts
+        assert_eq!(title("1917.2019.mkv"), ("1917".to_owned(), Some(2019)));
+        assert_eq!(
+            title("Blade.Runner.2049.2017.mkv"),
+            ("Blade Runner".to_owned(), Some(2049))
+        );
+        assert_eq!(
+            title("Movie.1080p.x2640.mkv"),
+            ("Movie 1080p x2640".to_owned(), None)
+        );
+        assert_eq!(
+            title("Space.Odyssey.3001.mkv"),
+            ("Space Odyssey 3001".to_owned(), None)
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{bail, Result};
+use chrono::Local;
+use clap::Args;
+use common::{
+    config::Config,
+    history::{record, Action, Event, Source},
+    journal::{self, read_journal, Change},
+    sidecar::read_sidecar,
+};
+
+use crate::desktop;
+
+#[derive(Args)]
+pub struct UndoArgs {
+    /// Number of operations to undo, newest first.
+    #[arg(default_value_t = 1, conflicts_with = "id")]
+    count: usize,
+    /// Undo a specific operation instead, by its ID from --list.
+    #[arg(long)]
+    id: Option<u64>,
+    /// List the operations that can be undone instead of undoing any.
+    #[arg(long)]
+    list: bool,
+}
+
+pub fn run(args: UndoArgs) -> Result<()> {
+    let mut records = read_journal()?;
+
+    if args.list {
+        for record in records.iter().rev() {
+            println!(
+                "#{:<4} {}  {} ({} changes)",
+                record.id,
+                record.time.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                record.description,
+                record.changes.len()
+            );
+        }
+        return Ok(());
+    }
+
+    let undo = match args.id {
+        Some(id) => {
+            let Some(idx) = records.iter().position(|x| x.id == id) else {
+                bail!("No operation with ID {id}");
+            };
+            vec![records.remove(idx)]
+        }
+        None => {
+            if records.is_empty() {
+                bail!("Nothing to undo");
+            }
+            records.split_off(records.len().saturating_sub(args.count))
+        }
+    };
+
+    for operation in undo.iter().rev() {
+        let was_watched = operation
+            .changes
+            .iter()
+            .map(is_watched)
+            .collect::<Result<Vec<_>>>()?;
+
+        operation.undo()?;
+        journal::remove(&[operation.id])?;
+        println!("Undid #{} {}", operation.id, operation.description);
+
+        // Undoing is recorded in the history like any other change, but only
+        // when it marks or unmarks the video, not for ratings or rewatches
+        for (change, was_watched) in operation.changes.iter().zip(was_watched) {
+            let watched = change.before.as_ref().is_some_and(|x| x.watched);
+            if watched == was_watched {
+                continue;
+            }
+
+            let video = change.video();
+            let config = Config::for_path(&video)?;
+            let action = match watched {
+                true => Action::Watched,
+                false => Action::Unwatched,
+            };
+            record(&config, &Event::new(Source::Cli, action, &video))?;
+            desktop::sync(&config, &video, watched);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the entry a change is for is currently watched.
+fn is_watched(change: &Change) -> Result<bool> {
+    Ok(read_sidecar(&change.sidecar)?
+        .iter()
+        .any(|x| x.name == change.name && x.watched))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use common::{history::read_history, journal::Operation, test_util::library};
+
+    use super::*;
+    use crate::{mark_watched, update_entry};
+
+    /// Undoes the operation with the given description, which the tests make
+    /// unique as they share the journal.
+    fn undo(description: &str) {
+        let id = read_journal()
+            .unwrap()
+            .iter()
+            .find(|x| x.description == description)
+            .unwrap()
+            .id;
+        run(UndoArgs {
+            count: 1,
+            id: Some(id),
+            list: false,
+        })
+        .unwrap();
+    }
+
+    fn actions(video: &Path) -> Vec<Action> {
+        let config = Config::for_path(video).unwrap();
+        let history = config.history_path(video).unwrap();
+        read_history(&history, video.parent().unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|x| x.action)
+            .collect()
+    }
+
+    #[test]
+    fn records_unmarking_when_undoing_mark() {
+        let dir = library(&["Episode 1.mkv"]);
+        let video = dir.path().join("Episode 1.mkv");
+        let description = format!("watched {}", video.display());
+        mark_watched(&video, &mut Operation::new(&description)).unwrap();
+
+        undo(&description);
+        assert_eq!(actions(&video), [Action::Watched, Action::Unwatched]);
+    }
+
+    #[test]
+    fn ignores_changes_that_keep_watched_state() {
+        let dir = library(&["Episode 1.mkv", "Episode 2.mkv"]);
+        let rated = dir.path().join("Episode 1.mkv");
+        let description = format!("rate {}", rated.display());
+        update_entry(&rated, &mut Operation::new(&description), |x| {
+            x.rating = Some(8)
+        })
+        .unwrap();
+        undo(&description);
+        assert_eq!(actions(&rated), []);
+
+        let rewatched = dir.path().join("Episode 2.mkv");
+        mark_watched(&rewatched, &mut Operation::new("first watch")).unwrap();
+        let description = format!("rewatch {}", rewatched.display());
+        mark_watched(&rewatched, &mut Operation::new(&description)).unwrap();
+        undo(&description);
+        assert_eq!(actions(&rewatched), [Action::Watched, Action::Watched]);
+    }
+
+    #[test]
+    fn undoes_only_own_changes() {
+        let dir = library(&["Episode 1.mkv", "Episode 2.mkv"]);
+        let shared = dir.path().join("Episode 1.mkv");
+        let own = dir.path().join("Episode 2.mkv");
+        mark_watched(&shared, &mut Operation::new("shared")).unwrap();
+
+        dir.set_user(Some("bob"));
+
+        mark_watched(&own, &mut Operation::new("own")).unwrap();
+        let descriptions = read_journal()
+            .unwrap()
+            .into_iter()
+            .map(|x| x.description)
+            .collect::<Vec<_>>();
+        assert_eq!(descriptions, ["own"]);
+
+        run(UndoArgs {
+            count: 2,
+            id: None,
+            list: false,
+        })
+        .unwrap();
+        assert_eq!(actions(&own), [Action::Watched, Action::Unwatched]);
+        dir.set_user(None);
+
+        // The shared state's journal is left as it was
+        assert_eq!(actions(&shared), [Action::Watched]);
+        assert!(read_journal()
+            .unwrap()
+            .iter()
+            .any(|x| x.description == "shared"));
+    }
+}
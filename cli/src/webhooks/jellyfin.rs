@@ -0,0 +1,200 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use ureq::Agent;
+
+use super::{Played, WebhookArgs};
+
+/// Payload of the Jellyfin webhook plugin's generic destination.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct JellyfinPayload {
+    notification_type: String,
+    #[serde(default)]
+    played_to_completion: bool,
+    item_id: Option<String>,
+    item_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmbyPayload {
+    event: String,
+    item: Option<Item>,
+    playback_info: Option<PlaybackInfo>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PlaybackInfo {
+    #[serde(default)]
+    played_to_completion: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Items {
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Item {
+    id: Option<String>,
+    path: Option<String>,
+}
+
+/// Gets the server path of the item from a completed `PlaybackStop` notification.
+pub fn jellyfin(args: &WebhookArgs, agent: &Agent, body: &[u8]) -> Result<Option<Played>> {
+    let payload = serde_json::from_slice::<JellyfinPayload>(body)?;
+    if payload.notification_type != "PlaybackStop" || !payload.played_to_completion {
+        return Ok(None);
+    }
+
+    let path = match (payload.item_path, payload.item_id) {
+        (Some(path), _) => path,
+        (None, Some(id)) => lookup_path(args, agent, &id)?,
+        (None, None) => return Ok(None),
+    };
+
+    Ok(Some(Played { path, time: None }))
+}
+
+/// Gets the server path of the item from a completed `playback.stop` event.
+pub fn emby(args: &WebhookArgs, agent: &Agent, body: &[u8]) -> Result<Option<Played>> {
+    let payload = serde_json::from_slice::<EmbyPayload>(body)?;
+    let completed = payload
+        .playback_info
+        .is_some_and(|x| x.played_to_completion);
+    if payload.event != "playback.stop" || !completed {
+        return Ok(None);
+    }
+
+    let path = match payload.item {
+        Some(Item {
+            path: Some(path), ..
+        }) => path,
+        Some(Item { id: Some(id), .. }) => lookup_path(args, agent, &id)?,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Played { path, time: None }))
+}
+
+/// Asks the server for the path of an item, as the Jellyfin webhook plugin
+/// doesn't include it in its default templates.
+fn lookup_path(args: &WebhookArgs, agent: &Agent, id: &str) -> Result<String> {
+    let (Some(url), Some(api_key)) = (&args.jellyfin_url, &args.jellyfin_api_key) else {
+        bail!("Webhook has no item path, and no server to look it up on was given");
+    };
+
+    let items = agent
+        .get(format!("{}/Items", url.trim_end_matches('/')))
+        .query("Ids", id)
+        .query("Fields", "Path")
+        .header("X-Emby-Token", api_key)
+        .call()?
+        .body_mut()
+        .read_json::<Items>()?;
+
+    items
+        .items
+        .into_iter()
+        .find_map(|item| item.path)
+        .context("Server returned no path for item")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the Jellyfin webhook plugin's generic destination sends, with
+    /// `ItemPath` added to the template.
+    const JELLYFIN: &str = r#"{"ServerId":"1f9e2d3c","ServerName":"media","ServerVersion":"10.9.11","NotificationType":"PlaybackStop","Timestamp":"2024-08-22T21:30:00.0000000+02:00","UtcTimestamp":"2024-08-22T19:30:00.0000000Z","Name":"Pilot","ItemId":"a1b2c3d4e5f6","ItemType":"Episode","SeriesName":"Show","SeasonNumber":1,"EpisodeNumber":1,"ItemPath":"/media/Show/Show.S01E01.mkv","PlaybackPositionTicks":25830000000,"PlayedToCompletion":true,"NotificationUsername":"alice","UserId":"9f8e7d"}"#;
+
+    /// What Emby sends for its `playback.stop` webhook event.
+    const EMBY: &str = r#"{"Title":"alice has finished playing Show - Pilot","Date":"2024-08-22T19:30:00.0000000Z","Event":"playback.stop","User":{"Name":"alice","Id":"9f8e7d"},"Item":{"Name":"Pilot","ServerId":"1f9e2d3c","Id":"4821","Path":"/media/Show/Show.S01E01.mkv","Type":"Episode","SeriesName":"Show","IndexNumber":1,"ParentIndexNumber":1},"PlaybackInfo":{"PlayedToCompletion":true,"PositionTicks":25830000000,"PlaylistIndex":0,"PlaylistLength":1}}"#;
+
+    fn args() -> WebhookArgs {
+        WebhookArgs {
+            bind: String::new(),
+            rewrites: Vec::new(),
+            jellyfin_url: None,
+            jellyfin_api_key: None,
+            plex_url: None,
+            plex_token: None,
+            plex_user: None,
+        }
+    }
+
+    fn path(played: Result<Option<Played>>) -> Option<String> {
+        played.unwrap().map(|x| x.path)
+    }
+
+    #[test]
+    fn reads_completed_jellyfin_playback() {
+        let agent = Agent::new_with_defaults();
+        let played = jellyfin(&args(), &agent, JELLYFIN.as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(played.path, "/media/Show/Show.S01E01.mkv");
+        assert_eq!(played.time, None);
+
+        let stopped = JELLYFIN.replace(
+            r#""PlayedToCompletion":true"#,
+            r#""PlayedToCompletion":false"#,
+        );
+        assert_eq!(path(jellyfin(&args(), &agent, stopped.as_bytes())), None);
+        for event in ["PlaybackStart", "PlaybackProgress", "ItemAdded"] {
+            let json = JELLYFIN.replace("PlaybackStop", event);
+            assert_eq!(path(jellyfin(&args(), &agent, json.as_bytes())), None);
+        }
+    }
+
+    #[test]
+    fn reads_completed_emby_playback() {
+        let agent = Agent::new_with_defaults();
+        let played = path(emby(&args(), &agent, EMBY.as_bytes()));
+        assert_eq!(played.as_deref(), Some("/media/Show/Show.S01E01.mkv"));
+
+        let stopped = EMBY.replace(
+            r#""PlayedToCompletion":true"#,
+            r#""PlayedToCompletion":false"#,
+        );
+        assert_eq!(path(emby(&args(), &agent, stopped.as_bytes())), None);
+        let json = EMBY.replace("playback.stop", "playback.start");
+        assert_eq!(path(emby(&args(), &agent, json.as_bytes())), None);
+
+        // Emby leaves out the playback info for events other than playback
+        let json =
+            r#"{"Title":"Show - Pilot was added","Event":"library.new","Item":{"Id":"4821"}}"#;
+        assert_eq!(path(emby(&args(), &agent, json.as_bytes())), None);
+    }
+
+    #[test]
+    fn needs_a_server_for_items_without_paths() {
+        let agent = Agent::new_with_defaults();
+        let json = JELLYFIN.replace(r#""ItemPath":"/media/Show/Show.S01E01.mkv","#, "");
+        let err = jellyfin(&args(), &agent, json.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Webhook has no item path, and no server to look it up on was given"
+        );
+
+        let json = EMBY.replace(r#""Path":"/media/Show/Show.S01E01.mkv","#, "");
+        assert!(emby(&args(), &agent, json.as_bytes()).is_err());
+
+        // Nothing to look up either
+        let json = json.replace(r#""Id":"4821","#, "");
+        assert_eq!(path(emby(&args(), &agent, json.as_bytes())), None);
+    }
+
+    #[test]
+    fn rejects_bodies_that_are_not_payloads() {
+        let agent = Agent::new_with_defaults();
+        let truncated = &JELLYFIN.as_bytes()[..JELLYFIN.len() / 2];
+        assert!(jellyfin(&args(), &agent, truncated).is_err());
+        assert!(jellyfin(&args(), &agent, b"").is_err());
+        assert!(emby(&args(), &agent, b"event=playback.stop").is_err());
+        assert!(emby(&args(), &agent, JELLYFIN.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::Args;
+use common::{config::Config, history::Source, journal::Operation, sidecar::read_entry};
+use tiny_http::{Request, Response, Server};
+use ureq::Agent;
+
+use crate::{
+    mark_watched_at,
+    rewrite::{rewrite_path, Rewrite},
+};
+
+mod jellyfin;
+mod plex;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Playback reported again within this long of a video being marked is
+/// treated as a repeat of the same event. Jellyfin and Emby don't say when
+/// playback stopped, so their events are marked at the time they arrive.
+const REPEAT_WINDOW: TimeDelta = TimeDelta::minutes(5);
+
+#[derive(Args)]
+pub struct WebhookArgs {
+    /// Address to listen for webhooks on.
+    #[arg(long, default_value = "127.0.0.1:8765")]
+    bind: String,
+    /// Maps server paths to local paths, as SERVER=LOCAL. Can be repeated.
+    #[arg(long = "rewrite")]
+    rewrites: Vec<Rewrite>,
+    /// Address of the Jellyfin / Emby server, used to look up the path of
+    /// items when the webhook payload doesn't include it.
+    #[arg(long)]
+    jellyfin_url: Option<String>,
+    /// API key for the Jellyfin / Emby server.
+    #[arg(long)]
+    jellyfin_api_key: Option<String>,
+    /// Address of the Plex server, used to look up the files of items as
+    /// Plex webhooks don't include them.
+    #[arg(long)]
+    plex_url: Option<String>,
+    /// Authentication token for the Plex server.
+    #[arg(long)]
+    plex_token: Option<String>,
+    /// Only accept Plex events from this account.
+    #[arg(long)]
+    plex_user: Option<String>,
+}
+
+/// A video that a server reported as played to completion.
+#[derive(Debug)]
+struct Played {
+    path: String,
+    time: Option<DateTime<Utc>>,
+}
+
+/// Listens for playback webhooks from media servers and marks the played
+/// files as watched. Runs until the process is killed.
+pub fn run(args: WebhookArgs) -> Result<()> {
+    let server =
+        Server::http(&args.bind).map_err(|err| anyhow!("Failed to bind {}: {err}", args.bind))?;
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(REQUEST_TIMEOUT))
+        .build()
+        .into();
+    println!("Listening for webhooks on http://{}", args.bind);
+
+    for mut request in server.incoming_requests() {
+        let status = match handle(&args, &agent, &mut request) {
+            Ok(()) => 200,
+            Err(err) => {
+                println!("Failed to handle webhook on {}: {err:#}", request.url());
+                500
+            }
+        };
+
+        let _ = request.respond(Response::empty(status));
+    }
+
+    Ok(())
+}
+
+fn handle(args: &WebhookArgs, agent: &Agent, request: &mut Request) -> Result<()> {
+    let mut body = Vec::new();
+    request.as_reader().read_to_end(&mut body)?;
+
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|x| x.field.equiv("Content-Type"))
+        .map(|x| x.value.as_str())
+        .unwrap_or_default();
+
+    // Events other than completed playback are ignored
+    let endpoint = request.url().split('?').next().unwrap_or_default();
+    let played = match endpoint {
+        "/jellyfin" => jellyfin::jellyfin(args, agent, &body)?
+            .into_iter()
+            .collect(),
+        "/emby" => jellyfin::emby(args, agent, &body)?.into_iter().collect(),
+        "/plex" => plex::plex(args, agent, content_type, &body)?,
+        _ => bail!("Unknown webhook endpoint"),
+    };
+
+    let mut operation = Operation::new(format!("webhook {endpoint}"));
+    mark(&args.rewrites, played, &mut operation)
+}
+
+/// Marks played videos as watched. Anyone who can reach the server can send
+/// webhooks, so paths that aren't files here are skipped rather than having
+/// sidecars made for them.
+fn mark(rewrites: &[Rewrite], played: Vec<Played>, operation: &mut Operation) -> Result<()> {
+    for Played { path, time } in played {
+        let path = rewrite_path(rewrites, &path);
+        if !path.is_file() {
+            println!("Skipped {}, as it isn't a file", path.display());
+            continue;
+        }
+
+        let time = time.unwrap_or_else(Utc::now);
+        let last = read_entry(&Config::for_path(&path)?, &path)?
+            .filter(|x| x.watched)
+            .and_then(|x| x.watched_at);
+        if last.is_some_and(|x| (time - x).abs() < REPEAT_WINDOW) {
+            println!("Skipped {}, as it was just marked", path.display());
+            continue;
+        }
+
+        mark_watched_at(&path, time, Source::Webhook, operation)?;
+        println!("Marked {} as watched", path.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use common::test_util::{library, Library};
+
+    use super::*;
+
+    fn rewrites(library: &Library) -> Vec<Rewrite> {
+        vec![format!("/media={}", library.path().display())
+            .parse()
+            .unwrap()]
+    }
+
+    fn played(path: &str, time: Option<DateTime<Utc>>) -> Played {
+        Played {
+            path: path.to_owned(),
+            time,
+        }
+    }
+
+    fn count(library: &Library, video: &str) -> u32 {
+        let video = library.path().join(video);
+        let config = Config::for_path(&video).unwrap();
+        read_entry(&config, &video).unwrap().map_or(0, |x| x.count)
+    }
+
+    #[test]
+    fn skips_paths_that_are_not_files() {
+        let dir = library(&["Show/Show.S01E01.mkv"]);
+        let played = vec![
+            played("/media/Made Up/Show.S01E01.mkv", None),
+            played("/media/Show", None),
+            played("/elsewhere/Show.S01E01.mkv", None),
+        ];
+
+        mark(&rewrites(&dir), played, &mut Operation::new("test")).unwrap();
+        assert!(!dir.path().join("Made Up").exists());
+        assert!(!dir.path().join("Show/.watched").exists());
+        assert!(!dir.path().join(".watched").exists());
+    }
+
+    #[test]
+    fn ignores_repeated_events() {
+        let dir = library(&["Show/Show.S01E01.mkv"]);
+        let rewrites = rewrites(&dir);
+        let path = "/media/Show/Show.S01E01.mkv";
+        let mut operation = Operation::new("test");
+
+        for _ in 0..2 {
+            mark(&rewrites, vec![played(path, None)], &mut operation).unwrap();
+        }
+        assert_eq!(count(&dir, "Show/Show.S01E01.mkv"), 1);
+
+        // Plex says when it was watched, so a later viewing counts
+        let later = Utc::now() + TimeDelta::hours(1);
+        for _ in 0..2 {
+            mark(&rewrites, vec![played(path, Some(later))], &mut operation).unwrap();
+        }
+        assert_eq!(count(&dir, "Show/Show.S01E01.mkv"), 2);
+    }
+}
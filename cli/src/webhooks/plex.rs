@@ -0,0 +1,308 @@
+use anyhow::{bail, Context, Result};
+use chrono::DateTime;
+use serde::Deserialize;
+use ureq::Agent;
+
+use super::{Played, WebhookArgs};
+use crate::plex::{MediaContainer, Metadata};
+
+#[derive(Deserialize)]
+struct Payload {
+    event: String,
+    #[serde(rename = "Account")]
+    account: Option<Account>,
+    #[serde(rename = "Metadata")]
+    metadata: Metadata,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    title: String,
+}
+
+/// Gets the files of the item from a `media.scrobble` event, which Plex sends
+/// once an item has been played past its watched threshold.
+pub fn plex(
+    args: &WebhookArgs,
+    agent: &Agent,
+    content_type: &str,
+    body: &[u8],
+) -> Result<Vec<Played>> {
+    let boundary = content_type
+        .split(';')
+        .find_map(|x| x.trim().strip_prefix("boundary="))
+        .context("Plex webhooks must be multipart")?
+        .trim_matches('"');
+    let payload = multipart_field(body, boundary, "payload").context("Webhook has no payload")?;
+    let payload = serde_json::from_slice::<Payload>(payload)?;
+
+    if payload.event != "media.scrobble" {
+        return Ok(Vec::new());
+    }
+
+    // Plex sends events for every account on the server
+    if let (Some(user), Some(account)) = (&args.plex_user, &payload.account) {
+        if &account.title != user {
+            return Ok(Vec::new());
+        }
+    }
+
+    let mut files = payload
+        .metadata
+        .files()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        let key = payload
+            .metadata
+            .rating_key
+            .context("Webhook has no rating key")?;
+        files = lookup_files(args, agent, &key)?;
+    }
+
+    // Falls back to the time the webhook arrived if Plex leaves it out
+    let time = payload
+        .metadata
+        .last_viewed_at
+        .and_then(|x| DateTime::from_timestamp(x, 0));
+    Ok(files
+        .into_iter()
+        .map(|path| Played { path, time })
+        .collect())
+}
+
+/// Asks the server for the files of an item, as webhook payloads don't include
+/// the item's media.
+fn lookup_files(args: &WebhookArgs, agent: &Agent, key: &str) -> Result<Vec<String>> {
+    let (Some(url), Some(token)) = (&args.plex_url, &args.plex_token) else {
+        bail!("Webhook has no file path, and no server to look it up on was given");
+    };
+
+    let items = agent
+        .get(format!(
+            "{}/library/metadata/{key}",
+            url.trim_end_matches('/')
+        ))
+        .header("Accept", "application/json")
+        .header("X-Plex-Token", token)
+        .call()?
+        .body_mut()
+        .read_json::<MediaContainer>()?;
+
+    Ok(items
+        .container
+        .metadata
+        .iter()
+        .flat_map(Metadata::files)
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Finds the value of a field in a `multipart/form-data` body. Delimiters
+/// start on a new line, so the boundary can show up anywhere else in values,
+/// and a field cut off before the next delimiter isn't found.
+fn multipart_field<'a>(body: &'a [u8], boundary: &str, name: &str) -> Option<&'a [u8]> {
+    let delimiter = format!("\r\n--{boundary}");
+    let disposition = format!("name=\"{name}\"");
+
+    // The last part is whatever comes after the closing delimiter
+    let parts = split(body, delimiter.as_bytes());
+    parts[..parts.len() - 1].iter().find_map(|part| {
+        let header_end = find(part, b"\r\n\r\n")?;
+        let headers = String::from_utf8_lossy(&part[..header_end]);
+        let is_field = headers.lines().any(|line| {
+            line.to_ascii_lowercase()
+                .starts_with("content-disposition:")
+                && line.split(';').any(|x| x.trim() == disposition)
+        });
+
+        is_field.then(|| &part[header_end + 4..])
+    })
+}
+
+fn split<'a>(mut data: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+    while let Some(idx) = find(data, delimiter) {
+        parts.push(&data[..idx]);
+        data = &data[idx + delimiter.len()..];
+    }
+
+    parts.push(data);
+    parts
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "------------------------a7f2c9e4d1b8";
+
+    /// A scrobble from Plex Media Server, with the thumbnail it sends along.
+    const SCROBBLE: &str = r#"{"event":"media.scrobble","user":true,"owner":true,"Account":{"id":1,"thumb":"https://plex.tv/users/1a2b/avatar","title":"alice"},"Server":{"title":"Living Room","uuid":"8c4e5f"},"Player":{"local":true,"publicAddress":"203.0.113.7","title":"Chrome","uuid":"x9y8z7"},"Metadata":{"librarySectionType":"show","ratingKey":"4821","key":"/library/metadata/4821","grandparentTitle":"Show","title":"Pilot","type":"episode","lastViewedAt":1724355000,"Media":[{"id":5120,"Part":[{"id":6231,"file":"/media/Show/Show.S01E01.mkv"}]}]}}"#;
+
+    fn args(plex_user: Option<&str>) -> WebhookArgs {
+        WebhookArgs {
+            bind: String::new(),
+            rewrites: Vec::new(),
+            jellyfin_url: None,
+            jellyfin_api_key: None,
+            plex_url: None,
+            plex_token: None,
+            plex_user: plex_user.map(str::to_owned),
+        }
+    }
+
+    fn content_type() -> String {
+        format!("multipart/form-data; boundary={BOUNDARY}")
+    }
+
+    /// Builds a body like Plex sends, from the fields' headers and values.
+    fn multipart(fields: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (headers, value) in fields {
+            body.extend(format!("--{BOUNDARY}\r\n{headers}\r\n\r\n").as_bytes());
+            body.extend(*value);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    fn payload(json: &str) -> (&'static str, &[u8]) {
+        (
+            "Content-Disposition: form-data; name=\"payload\"\r\nContent-Type: application/json",
+            json.as_bytes(),
+        )
+    }
+
+    fn thumb(data: &[u8]) -> (&'static str, &[u8]) {
+        (
+            "Content-Disposition: form-data; name=\"thumb\"; filename=\"image.jpg\"\r\nContent-Type: image/jpeg",
+            data,
+        )
+    }
+
+    fn paths(played: Result<Vec<Played>>) -> Vec<String> {
+        played.unwrap().into_iter().map(|x| x.path).collect()
+    }
+
+    #[test]
+    fn reads_scrobbles() {
+        let body = multipart(&[
+            payload(SCROBBLE),
+            thumb(b"\xff\xd8\xff\xe0\r\n\r\n\xff\xd9"),
+        ]);
+        let played = plex(
+            &args(None),
+            &Agent::new_with_defaults(),
+            &content_type(),
+            &body,
+        );
+
+        let played = played.unwrap();
+        assert_eq!(played.len(), 1);
+        assert_eq!(played[0].path, "/media/Show/Show.S01E01.mkv");
+        assert_eq!(played[0].time, DateTime::from_timestamp(1_724_355_000, 0));
+
+        // The thumbnail can come first, and the boundary can be quoted
+        let body = multipart(&[thumb(b"\xff\xd8\xff\xd9"), payload(SCROBBLE)]);
+        let content_type = format!("multipart/form-data; boundary=\"{BOUNDARY}\"");
+        let played = plex(
+            &args(None),
+            &Agent::new_with_defaults(),
+            &content_type,
+            &body,
+        );
+        assert_eq!(paths(played), ["/media/Show/Show.S01E01.mkv"]);
+    }
+
+    #[test]
+    fn finds_the_boundary_only_at_the_start_of_lines() {
+        let json = SCROBBLE.replace("Pilot", &format!("--{BOUNDARY}"));
+        let data = [b"\xff\xd8--", BOUNDARY.as_bytes(), b"\xff\xd9"].concat();
+        let body = multipart(&[thumb(&data), payload(&json)]);
+
+        let played = plex(
+            &args(None),
+            &Agent::new_with_defaults(),
+            &content_type(),
+            &body,
+        );
+        assert_eq!(paths(played), ["/media/Show/Show.S01E01.mkv"]);
+    }
+
+    #[test]
+    fn rejects_bodies_without_a_payload() {
+        let agent = Agent::new_with_defaults();
+        let body = multipart(&[thumb(b"\xff\xd8\xff\xd9")]);
+        let err = plex(&args(None), &agent, &content_type(), &body).unwrap_err();
+        assert_eq!(err.to_string(), "Webhook has no payload");
+
+        let err = plex(&args(None), &agent, "application/json", SCROBBLE.as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "Plex webhooks must be multipart");
+    }
+
+    #[test]
+    fn rejects_truncated_bodies() {
+        let agent = Agent::new_with_defaults();
+        let body = multipart(&[payload(SCROBBLE)]);
+        for len in [10, 80, body.len() / 2, body.len() - BOUNDARY.len() - 8] {
+            assert!(plex(&args(None), &agent, &content_type(), &body[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn ignores_other_events() {
+        let agent = Agent::new_with_defaults();
+        for event in [
+            "media.play",
+            "media.pause",
+            "media.resume",
+            "media.stop",
+            "media.rate",
+            "library.new",
+        ] {
+            let json = SCROBBLE.replace("media.scrobble", event);
+            let body = multipart(&[payload(&json)]);
+            assert!(paths(plex(&args(None), &agent, &content_type(), &body)).is_empty());
+        }
+    }
+
+    #[test]
+    fn ignores_other_accounts() {
+        let agent = Agent::new_with_defaults();
+        let body = multipart(&[payload(SCROBBLE)]);
+
+        let played = plex(&args(Some("bob")), &agent, &content_type(), &body);
+        assert!(paths(played).is_empty());
+        let played = plex(&args(Some("alice")), &agent, &content_type(), &body);
+        assert_eq!(paths(played), ["/media/Show/Show.S01E01.mkv"]);
+    }
+
+    #[test]
+    fn needs_a_server_for_items_without_files() {
+        let json = SCROBBLE.replace(
+            r#","Media":[{"id":5120,"Part":[{"id":6231,"file":"/media/Show/Show.S01E01.mkv"}]}]"#,
+            "",
+        );
+        let body = multipart(&[payload(&json)]);
+
+        let err = plex(
+            &args(None),
+            &Agent::new_with_defaults(),
+            &content_type(),
+            &body,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Webhook has no file path, and no server to look it up on was given"
+        );
+    }
+}
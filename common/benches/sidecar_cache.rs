@@ -0,0 +1,116 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use common::{
+    cache::{ConfigCache, SidecarCache},
+    config::{Config, LIBRARY_CONFIG},
+    sidecar::read_entry,
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// Number of videos in the benchmark's directory, half of which are watched.
+const VIDEOS: usize = 1000;
+
+/// A directory of episodes with a sidecar, like a long running show, a few
+/// directories into a library with its own config.
+struct Library {
+    root: PathBuf,
+    dir: PathBuf,
+    videos: Vec<PathBuf>,
+}
+
+impl Library {
+    fn new() -> Self {
+        let root = std::env::temp_dir().join(format!("last-watched-bench-{}", process::id()));
+        let dir = root.join("Shows/Show/Season 1");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(root.join(LIBRARY_CONFIG), "include_hidden = false\n").unwrap();
+
+        let videos = (0..VIDEOS)
+            .map(|idx| dir.join(format!("Show.S{:02}E{:03}.mkv", idx / 100 + 1, idx % 100)))
+            .collect::<Vec<_>>();
+        let sidecar = videos
+            .iter()
+            .step_by(2)
+            .map(|x| {
+                let name = x.file_name().unwrap().to_string_lossy();
+                format!("{name}\twatched=2024-08-22T19:30:00Z\n")
+            })
+            .collect::<String>();
+        fs::write(dir.join(".watched"), sidecar).unwrap();
+
+        Self { root, dir, videos }
+    }
+
+    fn video(&self, idx: usize) -> &Path {
+        &self.videos[idx % self.videos.len()]
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn lookups(c: &mut Criterion) {
+    let library = Library::new();
+    let mut group = c.benchmark_group("lookup");
+
+    // What the overlay did before there was a cache, loading the config for
+    // every video
+    let mut idx = 0;
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            idx += 1;
+            let config = Config::for_path(library.video(idx)).unwrap();
+            read_entry(&config, library.video(idx)).unwrap()
+        })
+    });
+
+    // The first lookup in a directory, which loads the config and reads the
+    // whole sidecar
+    let mut idx = 0;
+    group.bench_function("cold", |b| {
+        b.iter(|| {
+            idx += 1;
+            let mut configs = ConfigCache::new(16, Duration::MAX);
+            let mut cache = SidecarCache::new(16, Duration::MAX);
+            let config = configs.config(&library.dir).unwrap();
+            cache.entry(&config, library.video(idx)).unwrap()
+        })
+    });
+
+    // Checking if the configs and sidecar changed on every lookup
+    let mut configs = ConfigCache::new(16, Duration::ZERO);
+    let mut cache = SidecarCache::new(16, Duration::ZERO);
+    let mut idx = 0;
+    group.bench_function("rechecked", |b| {
+        b.iter(|| {
+            idx += 1;
+            let config = configs.config(&library.dir).unwrap();
+            cache.entry(&config, library.video(idx)).unwrap()
+        })
+    });
+
+    // Lookups within the recheck time, like Explorer drawing a directory
+    let mut configs = ConfigCache::new(16, Duration::MAX);
+    let mut cache = SidecarCache::new(16, Duration::MAX);
+    let mut idx = 0;
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            idx += 1;
+            let config = configs.config(&library.dir).unwrap();
+            cache.entry(&config, library.video(idx)).unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, lookups);
+criterion_main!(benches);
@@ -0,0 +1,92 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    config::Config,
+    episode::{clean_title, is_season_folder, Episode},
+    library,
+};
+
+/// Turns a selection of files and directories into the videos in it, in
+/// episode order. Directories are searched recursively and files that aren't
+/// videos are left out.
+pub fn expand(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut videos = Vec::new();
+    for path in paths {
+        let config = Config::for_path(path)?;
+        if path.is_dir() {
+            videos.extend(library::walk(&config, path)?);
+        } else if path.is_file() && config.is_video(path) {
+            videos.push(path.to_owned());
+        }
+    }
+
+    sort_episodes(&mut videos);
+    videos.dedup();
+    Ok(videos)
+}
+
+/// Finds the videos that come before a video, including itself, in episode
+/// order. For episodes that's every earlier episode of the show, including
+/// ones in the other season folders. Anything else only goes with the videos
+/// before it by name in the same directory.
+pub fn up_to(video: &Path) -> Result<Vec<PathBuf>> {
+    let config = Config::for_path(video)?;
+    let dir = video.parent().context("Video is not in a directory")?;
+
+    let mut videos = match Episode::parse(video) {
+        Some(target) => {
+            // Season folders are next to each other in the show's folder
+            let in_season_folder = dir
+                .file_name()
+                .is_some_and(|x| is_season_folder(&clean_title(&x.to_string_lossy())));
+            let root = match in_season_folder {
+                true => dir.parent().unwrap_or(dir),
+                false => dir,
+            };
+
+            library::walk(&config, root)?
+                .into_iter()
+                .filter(|path| {
+                    Episode::parse(path).is_some_and(|x| {
+                        x.show.eq_ignore_ascii_case(&target.show)
+                            && (x.season, x.episode) <= (target.season, target.episode)
+                    })
+                })
+                .collect()
+        }
+        None => {
+            let name = video.file_name().context("Video is not a file")?;
+            let mut videos = Vec::new();
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if entry.file_name() <= name
+                    && (config.include_hidden || !hidden)
+                    && entry.file_type()?.is_file()
+                    && config.is_video(&path)
+                {
+                    videos.push(path);
+                }
+            }
+            videos
+        }
+    };
+
+    sort_episodes(&mut videos);
+    Ok(videos)
+}
+
+/// Sorts videos by show, season and episode, with videos that aren't
+/// episodes sorted by path after them.
+pub fn sort_episodes(videos: &mut [PathBuf]) {
+    videos.sort_by_cached_key(|path| {
+        let episode = Episode::parse(path).map(|x| (x.show.to_lowercase(), x.season, x.episode));
+        (episode.is_none(), episode, path.clone())
+    });
+}
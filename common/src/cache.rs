@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Result;
+
+use crate::{
+    config::{config_path, library_configs, Config},
+    sidecar::{ensure_hidden, parse_entries, Entry},
+    state::{FolderState, Listing},
+};
+
+/// How many sidecars the shared cache keeps, which is one per directory
+/// unless storage is central.
+pub const SHARED_CAPACITY: usize = 256;
+
+/// How long the shared cache trusts a sidecar without checking if it changed.
+/// Explorer asks about every icon in a directory at once, so this makes that
+/// one check instead of one per file.
+pub const SHARED_RECHECK: Duration = Duration::from_secs(2);
+
+/// How many directories the shared config cache keeps configs for.
+pub const SHARED_CONFIGS: usize = 1024;
+
+/// Parsed sidecars kept in memory, for looking up lots of videos quickly.
+///
+/// Sidecars are re-read when their modification time or size changes, or
+/// when they are invalidated. Only the most recently used `capacity`
+/// sidecars are kept, and as many folder states.
+pub struct SidecarCache {
+    capacity: usize,
+    recheck: Duration,
+    sidecars: HashMap<PathBuf, Cached>,
+    folders: HashMap<PathBuf, CachedFolder>,
+    /// Incremented on every lookup, to find the least recently used sidecar
+    /// or folder.
+    clock: u64,
+}
+
+struct Cached {
+    /// None if the sidecar doesn't exist.
+    stamp: Option<Stamp>,
+    checked: Instant,
+    used: u64,
+    entries: HashMap<String, Entry>,
+}
+
+struct CachedFolder {
+    listing: Arc<Listing>,
+    state: Option<FolderState>,
+    /// None once a sidecar is invalidated, as it could be for a video in it.
+    checked: Option<Instant>,
+    used: u64,
+}
+
+/// Configs resolved for directories, for looking up the config of lots of
+/// files quickly.
+///
+/// A directory's config is resolved again when the user config or a library
+/// config that applies to it is added, removed or changed, which is checked
+/// like sidecars are. Only the most recently used `capacity` directories are
+/// kept.
+pub struct ConfigCache {
+    capacity: usize,
+    recheck: Duration,
+    configs: HashMap<PathBuf, CachedConfig>,
+    clock: u64,
+}
+
+struct CachedConfig {
+    config: Arc<Config>,
+    /// The files the config was read from, and the library configs checked
+    /// for on the way that didn't exist.
+    files: Vec<(PathBuf, Option<Stamp>)>,
+    checked: Instant,
+    used: u64,
+}
+
+/// What the cache knows about a folder, see [`SidecarCache::folder`].
+pub enum CachedFolderState {
+    /// The state was worked out within the recheck time.
+    Fresh(Option<FolderState>),
+    /// The state has to be worked out again, from this listing if it's
+    /// still current.
+    Stale(Arc<Listing>),
+}
+
+/// What is compared to tell if a sidecar changed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl SidecarCache {
+    /// Creates a cache of up to `capacity` sidecars, which are trusted for
+    /// `recheck` after being checked for changes.
+    pub fn new(capacity: usize, recheck: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            recheck,
+            sidecars: HashMap::new(),
+            folders: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Looks up the entry of a video in its sidecar.
+    pub fn entry(&mut self, config: &Config, video: &Path) -> Result<Option<Entry>> {
+        let (Some(sidecar), Some(name)) = (config.sidecar_path(video), config.entry_name(video))
+        else {
+            return Ok(None);
+        };
+
+        let cached = self.sidecar(config, &sidecar)?;
+        Ok(cached.entries.get(&name).cloned())
+    }
+
+    /// Checks if a video is watched, see [`Self::entry`].
+    pub fn is_watched(&mut self, config: &Config, video: &Path) -> Result<bool> {
+        let (Some(sidecar), Some(name)) = (config.sidecar_path(video), config.entry_name(video))
+        else {
+            return Ok(false);
+        };
+
+        let cached = self.sidecar(config, &sidecar)?;
+        Ok(cached.entries.get(&name).is_some_and(|x| x.watched))
+    }
+
+    /// Looks up the state of a folder worked out before. Folders are kept
+    /// separately from sidecars, as listing the videos in them is what's
+    /// slow.
+    pub fn folder(&mut self, dir: &Path) -> Option<CachedFolderState> {
+        self.clock += 1;
+        let cached = self.folders.get_mut(dir)?;
+        cached.used = self.clock;
+
+        Some(match cached.checked {
+            Some(checked) if checked.elapsed() < self.recheck => {
+                CachedFolderState::Fresh(cached.state)
+            }
+            _ => CachedFolderState::Stale(cached.listing.clone()),
+        })
+    }
+
+    /// Keeps the state of a folder, worked out from a current listing.
+    pub fn insert_folder(&mut self, dir: &Path, listing: Arc<Listing>, state: Option<FolderState>) {
+        if !self.folders.contains_key(dir) && self.folders.len() >= self.capacity {
+            evict(&mut self.folders, |x| x.used);
+        }
+
+        let cached = CachedFolder {
+            listing,
+            state,
+            checked: Some(Instant::now()),
+            used: self.clock,
+        };
+        self.folders.insert(dir.to_owned(), cached);
+    }
+
+    /// Forgets a sidecar, so it is read again next time. For when it's known
+    /// to have changed, like from a change notification.
+    pub fn invalidate(&mut self, sidecar: &Path) {
+        self.sidecars.remove(sidecar);
+        for folder in self.folders.values_mut() {
+            folder.checked = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sidecars.clear();
+        self.folders.clear();
+    }
+
+    /// Gets a sidecar, reading it again if it changed. Sidecars written by
+    /// something that doesn't hide them are hidden when they are read.
+    fn sidecar(&mut self, config: &Config, path: &Path) -> Result<&Cached> {
+        self.clock += 1;
+        let now = Instant::now();
+
+        let fresh = match self.sidecars.get_mut(path) {
+            Some(cached) if now.duration_since(cached.checked) < self.recheck => true,
+            Some(cached) => {
+                let fresh = cached.stamp == stamp(path)?;
+                cached.checked = now;
+                fresh
+            }
+            None => false,
+        };
+
+        if !fresh {
+            let cached = read(path, now)?;
+            if config.hide_sidecar && cached.stamp.is_some() {
+                let _ = ensure_hidden(path);
+            }
+
+            if !self.sidecars.contains_key(path) && self.sidecars.len() >= self.capacity {
+                evict(&mut self.sidecars, |x| x.used);
+            }
+            self.sidecars.insert(path.to_owned(), cached);
+        }
+
+        let cached = self.sidecars.get_mut(path).unwrap();
+        cached.used = self.clock;
+        Ok(cached)
+    }
+}
+
+/// Drops the least recently used sidecar, folder or config.
+fn evict<T>(cached: &mut HashMap<PathBuf, T>, used: impl Fn(&T) -> u64) {
+    let oldest = cached
+        .iter()
+        .min_by_key(|(_, x)| used(x))
+        .map(|(path, _)| path.clone());
+    if let Some(oldest) = oldest {
+        cached.remove(&oldest);
+    }
+}
+
+impl ConfigCache {
+    /// Creates a cache of configs for up to `capacity` directories, which are
+    /// trusted for `recheck` after being checked for changes.
+    pub fn new(capacity: usize, recheck: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            recheck,
+            configs: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Gets the config for the files in a directory, see [`Config::for_path`].
+    pub fn config(&mut self, dir: &Path) -> Result<Arc<Config>> {
+        self.clock += 1;
+        let now = Instant::now();
+
+        let fresh = match self.configs.get_mut(dir) {
+            Some(cached) if now.duration_since(cached.checked) < self.recheck => true,
+            Some(cached) => {
+                let mut fresh = true;
+                for (file, old) in &cached.files {
+                    fresh &= stamp(file)? == *old;
+                }
+                cached.checked = now;
+                fresh
+            }
+            None => false,
+        };
+
+        if !fresh {
+            // Stamped before reading, like sidecars
+            let mut files = Vec::new();
+            if let Some(path) = config_path() {
+                files.push((path.clone(), stamp(&path)?));
+            }
+            for path in library_configs(dir) {
+                let stamp = stamp(&path)?;
+                files.push((path, stamp));
+                if stamp.is_some() {
+                    break;
+                }
+            }
+
+            let cached = CachedConfig {
+                config: Arc::new(Config::for_path(dir)?),
+                files,
+                checked: now,
+                used: 0,
+            };
+            if !self.configs.contains_key(dir) && self.configs.len() >= self.capacity {
+                evict(&mut self.configs, |x| x.used);
+            }
+            self.configs.insert(dir.to_owned(), cached);
+        }
+
+        let cached = self.configs.get_mut(dir).unwrap();
+        cached.used = self.clock;
+        Ok(cached.config.clone())
+    }
+
+    pub fn clear(&mut self) {
+        self.configs.clear();
+    }
+}
+
+/// The cache shared by everything in the process, like all the icons the
+/// shell extension is asked about.
+pub fn shared() -> MutexGuard<'static, SidecarCache> {
+    static CACHE: OnceLock<Mutex<SidecarCache>> = OnceLock::new();
+    let cache =
+        CACHE.get_or_init(|| Mutex::new(SidecarCache::new(SHARED_CAPACITY, SHARED_RECHECK)));
+
+    // Nothing is left half updated if a lookup panics
+    cache.lock().unwrap_or_else(|x| x.into_inner())
+}
+
+/// The config cache shared by everything in the process, see [`shared`].
+pub fn shared_configs() -> MutexGuard<'static, ConfigCache> {
+    static CACHE: OnceLock<Mutex<ConfigCache>> = OnceLock::new();
+    let cache = CACHE.get_or_init(|| Mutex::new(ConfigCache::new(SHARED_CONFIGS, SHARED_RECHECK)));
+    cache.lock().unwrap_or_else(|x| x.into_inner())
+}
+
+fn read(path: &Path, now: Instant) -> Result<Cached> {
+    // Stamped before reading, so a change while reading is picked up next time
+    let stamp = stamp(path)?;
+    let data = match stamp {
+        Some(_) => match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        },
+        None => String::new(),
+    };
+
+    let entries = parse_entries(&data)
+        .into_iter()
+        .map(|x| (x.name.clone(), x))
+        .collect();
+
+    Ok(Cached {
+        stamp,
+        checked: now,
+        used: 0,
+        entries,
+    })
+}
+
+fn stamp(path: &Path) -> Result<Option<Stamp>> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(Stamp {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+        })),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::{config::LIBRARY_CONFIG, test_util::library};
+
+    fn write_sidecar(dir: &Path, videos: &[&str]) {
+        let lines = videos.iter().map(|x| format!("{x}\n"));
+        fs::write(dir.join(".watched"), lines.collect::<String>()).unwrap();
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_sidecar() {
+        let library = library(&["A/A.mkv", "B/B.mkv", "C/C.mkv"]);
+        let config = Config::default();
+        let video = |name: &str| library.path().join(format!("{name}/{name}.mkv"));
+        for name in ["A", "B", "C"] {
+            write_sidecar(&library.path().join(name), &[]);
+        }
+
+        let mut cache = SidecarCache::new(2, Duration::MAX);
+        assert!(!cache.is_watched(&config, &video("A")).unwrap());
+        assert!(!cache.is_watched(&config, &video("B")).unwrap());
+        assert!(!cache.is_watched(&config, &video("A")).unwrap());
+        for name in ["A", "B"] {
+            write_sidecar(&library.path().join(name), &[&format!("{name}.mkv")]);
+        }
+
+        // B was used longest ago, so it's the one dropped for C
+        assert!(!cache.is_watched(&config, &video("C")).unwrap());
+        assert!(!cache.is_watched(&config, &video("A")).unwrap());
+        assert!(cache.is_watched(&config, &video("B")).unwrap());
+    }
+
+    #[test]
+    fn rereads_sidecars_that_changed() {
+        let library = library(&["Show.S01E01.mkv", "Show.S01E02.mkv"]);
+        let config = Config::default();
+        let first = library.path().join("Show.S01E01.mkv");
+        let second = library.path().join("Show.S01E02.mkv");
+        let sidecar = library.path().join(".watched");
+        write_sidecar(library.path(), &["Show.S01E01.mkv"]);
+
+        let mut cache = SidecarCache::new(16, Duration::ZERO);
+        assert!(cache.is_watched(&config, &first).unwrap());
+
+        // A different size
+        write_sidecar(library.path(), &["Show.S01E01.mkv", "Show.S01E02.mkv"]);
+        assert!(cache.is_watched(&config, &second).unwrap());
+
+        // The same size, but modified later
+        let modified = fs::metadata(&sidecar).unwrap().modified().unwrap();
+        write_sidecar(library.path(), &["Show.S01E01.mkv", "Show.S01E03.mkv"]);
+        let file = File::options().write(true).open(&sidecar).unwrap();
+        file.set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        assert!(!cache.is_watched(&config, &second).unwrap());
+
+        // Unchanged as far as the cache can tell
+        write_sidecar(library.path(), &["Show.S01E02.mkv", "Show.S01E03.mkv"]);
+        file.set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        assert!(cache.is_watched(&config, &first).unwrap());
+
+        fs::remove_file(&sidecar).unwrap();
+        assert!(!cache.is_watched(&config, &first).unwrap());
+    }
+
+    #[test]
+    fn trusts_sidecars_until_rechecked_or_invalidated() {
+        let library = library(&["Show.S01E01.mkv"]);
+        let config = Config::default();
+        let video = library.path().join("Show.S01E01.mkv");
+
+        let mut cache = SidecarCache::new(16, Duration::MAX);
+        assert!(!cache.is_watched(&config, &video).unwrap());
+        write_sidecar(library.path(), &["Show.S01E01.mkv"]);
+        assert!(!cache.is_watched(&config, &video).unwrap());
+
+        cache.invalidate(&library.path().join(".watched"));
+        assert!(cache.is_watched(&config, &video).unwrap());
+    }
+
+    #[test]
+    fn resolves_configs_again_when_library_configs_change() {
+        let library = library(&["Show/Season 1/Show.S01E01.mkv"]);
+        let dir = library.path().join("Show/Season 1");
+        let sidecar_name =
+            |cache: &mut ConfigCache| cache.config(&dir).unwrap().sidecar_name.clone();
+
+        let mut trusted = ConfigCache::new(16, Duration::MAX);
+        let mut cache = ConfigCache::new(16, Duration::ZERO);
+        assert_eq!(sidecar_name(&mut trusted), ".watched");
+        assert_eq!(sidecar_name(&mut cache), ".watched");
+
+        let show = library.path().join("Show").join(LIBRARY_CONFIG);
+        fs::write(&show, "sidecar_name = \".show\"\n").unwrap();
+        assert_eq!(sidecar_name(&mut trusted), ".watched");
+        assert_eq!(sidecar_name(&mut cache), ".show");
+
+        // A closer one takes over, and the further one no longer matters
+        let season = dir.join(LIBRARY_CONFIG);
+        fs::write(&season, "sidecar_name = \".season\"\n").unwrap();
+        assert_eq!(sidecar_name(&mut cache), ".season");
+        fs::remove_file(&show).unwrap();
+        assert_eq!(sidecar_name(&mut cache), ".season");
+
+        fs::remove_file(&season).unwrap();
+        assert_eq!(sidecar_name(&mut cache), ".watched");
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{self, Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use toml::Table;
+
+use crate::{progress::WATCHED_THRESHOLD, sniff::sniff_file, VIDEO_EXTENSIONS};
+
+/// Name of the config file that can be put in the root of a library to
+/// override the user's config for everything under it.
+pub const LIBRARY_CONFIG: &str = ".last-watched.toml";
+
+/// Environment variable that overrides the user set in the config.
+pub const USER_ENV: &str = "LAST_WATCHED_USER";
+
+/// Settings shared by every component, loaded from `last-watched/config.toml`
+/// in the user's config directory and optionally overridden per library.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Extensions (without the dot) of files that are treated as videos.
+    pub video_extensions: Vec<String>,
+    /// Name of the sidecar file kept in each directory.
+    pub sidecar_name: String,
+    /// Where watched state is stored.
+    pub storage: Storage,
+    /// Fraction of a video that has to be played before it is considered watched.
+    pub watched_threshold: f64,
+    /// Whether to set the hidden attribute on sidecar files on Windows.
+    pub hide_sidecar: bool,
+    /// Whether hidden files and directories are included when scanning a library.
+    pub include_hidden: bool,
+    /// Whether to recognise videos by their contents when they don't have
+    /// one of the video extensions.
+    pub sniff_content: bool,
+    /// Whether to keep a log of when videos were marked, unmarked or had
+    /// their progress saved.
+    pub history: bool,
+    /// Whether the cli sets the GIO `metadata::emblems` of videos it marks,
+    /// so file managers like Nautilus and Nemo draw an emblem on them.
+    pub emblems: bool,
+    /// Whether to put a watched badge on the freedesktop thumbnails of
+    /// watched videos, which most Linux file managers show.
+    pub thumbnails: bool,
+    /// Whose watched state to use. Each user gets their own sidecars, named
+    /// after the default one with the user added, like `.watched.alice`.
+    pub user: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    /// A sidecar file next to the videos in each directory.
+    Sidecar,
+    /// A single file in the user's data directory, keyed by absolute path.
+    /// Useful for read-only libraries.
+    Central,
+}
+
+impl Config {
+    /// Loads the user's config, falling back to the defaults if there isn't one.
+    pub fn load() -> Result<Self> {
+        finish(user_config()?)
+    }
+
+    /// Loads the user's config, overridden by the closest library config in
+    /// or above `path` (or the directory containing it, for files).
+    pub fn for_path(path: &Path) -> Result<Self> {
+        let mut config = user_config()?;
+
+        let dir = match path.is_dir() {
+            true => path,
+            false => path.parent().unwrap_or(path),
+        };
+        let library = library_configs(dir).find(|file| file.is_file());
+        if let Some(library) = library {
+            config.extend(read_table(&library)?);
+        }
+
+        finish(config)
+    }
+
+    /// Checks if a file is a video by its extension or, if enabled, by its contents.
+    pub fn is_video(&self, path: &Path) -> bool {
+        self.has_video_extension(path)
+            || (self.sniff_content && sniff_file(path).is_ok_and(|x| x.is_some()))
+    }
+
+    /// Checks if a file is a video only by its extension, for places that
+    /// can't afford to open every file.
+    pub fn has_video_extension(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|ext| {
+            let ext = ext.to_string_lossy();
+            self.video_extensions
+                .iter()
+                .any(|x| x.eq_ignore_ascii_case(&ext))
+        })
+    }
+
+    /// Path of the file that stores the watched state of a video.
+    pub fn sidecar_path(&self, video: &Path) -> Option<PathBuf> {
+        match self.storage {
+            Storage::Sidecar => Some(video.parent()?.join(self.user_file(&self.sidecar_name))),
+            Storage::Central => Some(data_dir()?.join(self.user_file("watched"))),
+        }
+    }
+
+    /// Path of the file that stores the history of a video.
+    pub fn history_path(&self, video: &Path) -> Option<PathBuf> {
+        match self.storage {
+            Storage::Sidecar => Some(video.parent()?.join(self.history_name())),
+            Storage::Central => Some(data_dir()?.join(self.user_file("history"))),
+        }
+    }
+
+    /// Name of the history file kept next to each sidecar.
+    pub fn history_name(&self) -> String {
+        format!("{}.history", self.user_file(&self.sidecar_name))
+    }
+
+    /// Path of the journal of changes that can be undone. Each user has their
+    /// own, so undoing never reverts someone else's changes.
+    pub fn journal_path(&self) -> Option<PathBuf> {
+        Some(data_dir()?.join(self.user_file("journal")))
+    }
+
+    /// Adds the current user, if there is one, to the name of a file.
+    fn user_file(&self, name: &str) -> String {
+        match &self.user {
+            Some(user) => format!("{name}.{user}"),
+            None => name.to_owned(),
+        }
+    }
+
+    /// Name a video is stored under in its sidecar.
+    pub fn entry_name(&self, video: &Path) -> Option<String> {
+        match self.storage {
+            Storage::Sidecar => Some(video.file_name()?.to_string_lossy().into_owned()),
+            Storage::Central => Some(path::absolute(video).ok()?.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            video_extensions: VIDEO_EXTENSIONS.iter().map(|x| x.to_string()).collect(),
+            sidecar_name: ".watched".into(),
+            storage: Storage::Sidecar,
+            watched_threshold: WATCHED_THRESHOLD,
+            hide_sidecar: true,
+            include_hidden: false,
+            sniff_content: false,
+            history: true,
+            emblems: false,
+            thumbnails: false,
+            user: None,
+        }
+    }
+}
+
+/// Directory for files the project manages itself, like central storage.
+pub fn data_dir() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("last-watched"))
+}
+
+/// Paths a library config could be at for a directory, closest first.
+pub(crate) fn library_configs(dir: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    dir.ancestors().map(|dir| dir.join(LIBRARY_CONFIG))
+}
+
+/// Path of the user's config file, whether or not it exists.
+pub fn config_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("last-watched").join("config.toml"))
+}
+
+/// Turns the merged config tables into a config, with the user from the
+/// environment taking priority.
+fn finish(table: Table) -> Result<Config> {
+    let mut config: Config = table.try_into().context("Invalid config")?;
+    if let Some(user) = env::var_os(USER_ENV) {
+        config.user = Some(user.to_string_lossy().into_owned());
+    }
+
+    // Empty means the shared state, so a library can opt back out of users
+    config.user = config.user.filter(|x| !x.is_empty());
+    if let Some(user) = &config.user {
+        if !user
+            .chars()
+            .all(|x| x.is_alphanumeric() || x == '-' || x == '_')
+        {
+            bail!("Invalid user `{user}`, only letters, numbers, - and _ are allowed");
+        }
+    }
+
+    Ok(config)
+}
+
+fn user_config() -> Result<Table> {
+    match config_path() {
+        Some(path) => read_table(&path),
+        None => Ok(Table::new()),
+    }
+}
+
+fn read_table(path: &Path) -> Result<Table> {
+    match fs::read_to_string(path) {
+        Ok(data) => toml::from_str(&data).with_context(|| format!("Invalid config {path:?}")),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Table::new()),
+        Err(err) => Err(err.into()),
+    }
+}
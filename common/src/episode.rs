@@ -0,0 +1,162 @@
+use std::path::Path;
+
+/// Show, season and episode number parsed from a video's file name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Episode {
+    pub show: String,
+    pub season: u32,
+    pub episode: u32,
+}
+
+impl Episode {
+    /// Parses file names like `Show.Name.S01E02.Title.mkv` or `Show Name - 1x02.mkv`.
+    /// If the file name doesn't include the show, it is taken from the closest
+    /// parent directory that isn't a season folder.
+    pub fn parse(path: &Path) -> Option<Self> {
+        let stem = path.file_stem()?.to_string_lossy();
+        let (idx, season, episode) = find_episode(&stem)?;
+
+        let mut show = clean_title(&stem[..idx]);
+        if show.is_empty() {
+            show = path
+                .ancestors()
+                .skip(1)
+                .filter_map(|x| x.file_name())
+                .map(|x| clean_title(&x.to_string_lossy()))
+                .find(|x| !x.is_empty() && !is_season_folder(x))?;
+        }
+
+        Some(Self {
+            show,
+            season,
+            episode,
+        })
+    }
+}
+
+/// Turns the separators used in file names into spaces and trims off any
+/// trailing punctuation, so `Show.Name.-.` becomes `Show Name`.
+pub fn clean_title(title: &str) -> String {
+    let title = title
+        .chars()
+        .map(|c| if matches!(c, '.' | '_') { ' ' } else { c })
+        .collect::<String>();
+
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches([' ', '-', '[', '('])
+        .to_owned()
+}
+
+pub(crate) fn is_season_folder(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name == "specials"
+        || name
+            .strip_prefix("season")
+            .is_some_and(|x| x.trim().chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Finds the first `S01E02` or `1x02` style marker, returning where it starts
+/// along with the season and episode numbers.
+fn find_episode(name: &str) -> Option<(usize, u32, u32)> {
+    let bytes = name.as_bytes();
+    name.char_indices().find_map(|(idx, _)| {
+        // Markers must not be in the middle of a word or number
+        if idx > 0 && bytes[idx - 1].is_ascii_alphanumeric() {
+            return None;
+        }
+
+        let rest = &name[idx..];
+        let (season, episode) = match rest.as_bytes()[0] {
+            b'S' | b's' => {
+                let (season, rest) = number(&rest[1..])?;
+                let rest = rest.strip_prefix(['E', 'e'])?;
+                (season, number(rest)?.0)
+            }
+            b'0'..=b'9' => {
+                let (season, after_season) = number(rest)?;
+                let after_x = after_season.strip_prefix(['x', 'X'])?;
+                let (episode, after_episode) = number(after_x)?;
+
+                // Don't confuse resolutions like 1920x1080 with episodes
+                let season_digits = rest.len() - after_season.len();
+                let episode_digits = after_x.len() - after_episode.len();
+                if season_digits > 2
+                    || episode_digits > 3
+                    || after_episode.starts_with(|c: char| c.is_ascii_alphanumeric())
+                {
+                    return None;
+                }
+                (season, episode)
+            }
+            _ => return None,
+        };
+
+        Some((idx, season, episode))
+    })
+}
+
+fn number(s: &str) -> Option<(u32, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(path: &str) -> Option<(String, u32, u32)> {
+        Episode::parse(Path::new(path)).map(|x| (x.show, x.season, x.episode))
+    }
+
+    fn episode(show: &str, season: u32, episode: u32) -> Option<(String, u32, u32)> {
+        Some((show.to_owned(), season, episode))
+    }
+
+    #[test]
+    fn parses_episode_markers() {
+        assert_eq!(
+            parse("Show.Name.S01E02.Title.mkv"),
+            episode("Show Name", 1, 2)
+        );
+        assert_eq!(
+            parse("show_name_s10e100.mkv"),
+            episode("show name", 10, 100)
+        );
+        assert_eq!(parse("Show Name - 1x02.mkv"), episode("Show Name", 1, 2));
+        assert_eq!(
+            parse("Show Name - 1x02 - Title.mkv"),
+            episode("Show Name", 1, 2)
+        );
+    }
+
+    #[test]
+    fn takes_the_show_from_folders() {
+        assert_eq!(
+            parse("TV/Show Name/Season 1/S01E02.mkv"),
+            episode("Show Name", 1, 2)
+        );
+        assert_eq!(
+            parse("TV/Show.Name/Specials/S00E01.mkv"),
+            episode("Show Name", 0, 1)
+        );
+        assert_eq!(parse("TV/Show Name/1x02.mkv"), episode("Show Name", 1, 2));
+        assert_eq!(parse("Season 1/S01E02.mkv"), None);
+    }
+
+    #[test]
+    fn ignores_things_that_look_like_markers() {
+        assert_eq!(parse("Movie.1920x1080.mkv"), None);
+        assert_eq!(parse("Movie.2x2160p.mkv"), None);
+        assert_eq!(parse("Classics01E02.mkv"), None);
+        assert_eq!(parse("Movie.2010.mkv"), None);
+
+        // The first real marker counts
+        assert_eq!(
+            parse("Show.1920x1080.S02E03.mkv"),
+            episode("Show 1920x1080", 2, 3)
+        );
+    }
+}
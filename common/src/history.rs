@@ -0,0 +1,195 @@
+use std::{
+    fmt::{self, Display},
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{self, Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{
+    config::{Config, Storage},
+    library,
+    sidecar::ensure_hidden,
+};
+
+/// Something that happened to a video, as stored in a history file.
+///
+/// History files are only ever appended to, with each event on its own line
+/// as the time, source, action and entry name separated by tabs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub time: DateTime<Utc>,
+    pub source: Source,
+    pub action: Action,
+    pub path: PathBuf,
+}
+
+/// What caused an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Cli,
+    Shell,
+    Mpv,
+    Webhook,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Watched,
+    Unwatched,
+    /// Playback was stopped before the end and its position saved.
+    Progress,
+}
+
+impl Event {
+    /// Creates an event that happened now.
+    pub fn new(source: Source, action: Action, path: &Path) -> Self {
+        Self {
+            time: Utc::now(),
+            source,
+            action,
+            path: path.to_owned(),
+        }
+    }
+
+    /// Parses a line of a history file, where the entry name is relative to
+    /// `dir` unless it is absolute.
+    fn parse(line: &str, dir: &Path) -> Option<Self> {
+        let mut fields = line.splitn(4, '\t');
+        Some(Self {
+            time: DateTime::parse_from_rfc3339(fields.next()?).ok()?.to_utc(),
+            source: fields.next()?.parse().ok()?,
+            action: fields.next()?.parse().ok()?,
+            path: dir.join(fields.next()?),
+        })
+    }
+}
+
+/// Appends an event to the history file of its video, unless history is
+/// turned off in the config.
+pub fn record(config: &Config, event: &Event) -> Result<()> {
+    if !config.history {
+        return Ok(());
+    }
+
+    let history = config
+        .history_path(&event.path)
+        .context("Can't record history for root directory")?;
+    let name = config
+        .entry_name(&event.path)
+        .context("Can't record history for root directory")?;
+
+    // Like sidecars, see `open_or_create_sidecar`
+    if let (Storage::Central, Some(parent)) = (config.storage, history.parent()) {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&history)?;
+    writeln!(
+        file,
+        "{}\t{}\t{}\t{name}",
+        event.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        event.source,
+        event.action
+    )?;
+
+    if config.hide_sidecar {
+        let _ = ensure_hidden(&history);
+    }
+
+    Ok(())
+}
+
+/// Reads the events of every video under a directory, oldest first.
+pub fn read_library(config: &Config, root: &Path) -> Result<Vec<Event>> {
+    let mut events = match config.storage {
+        Storage::Sidecar => {
+            let mut events = Vec::new();
+            for dir in library::directories(config, root)? {
+                let history = dir.join(config.history_name());
+                events.extend(read_history(&history, &dir)?);
+            }
+            events
+        }
+        Storage::Central => {
+            let root = path::absolute(root)?;
+            let history = config
+                .history_path(&root)
+                .context("Failed to find history file")?;
+
+            let mut events = read_history(&history, &root)?;
+            events.retain(|x| x.path.starts_with(&root));
+            events
+        }
+    };
+
+    events.sort_by_key(|x| x.time);
+    Ok(events)
+}
+
+/// Reads the events in a history file, returning none if it doesn't exist.
+/// Lines that can't be parsed are skipped.
+pub fn read_history(history: &Path, dir: &Path) -> Result<Vec<Event>> {
+    match fs::read_to_string(history) {
+        Ok(data) => Ok(data
+            .lines()
+            .filter_map(|line| Event::parse(line, dir))
+            .collect()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Source::Cli => "cli",
+            Source::Shell => "shell",
+            Source::Mpv => "mpv",
+            Source::Webhook => "webhook",
+        })
+    }
+}
+
+impl FromStr for Source {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "cli" => Source::Cli,
+            "shell" => Source::Shell,
+            "mpv" => Source::Mpv,
+            "webhook" => Source::Webhook,
+            _ => bail!("Unknown source `{s}`"),
+        })
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Watched => "watched",
+            Action::Unwatched => "unwatched",
+            Action::Progress => "progress",
+        })
+    }
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "watched" => Action::Watched,
+            "unwatched" => Action::Unwatched,
+            "progress" => Action::Progress,
+            _ => bail!("Unknown action `{s}`"),
+        })
+    }
+}
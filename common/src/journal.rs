@@ -0,0 +1,246 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{self, Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{
+    config::Config,
+    sidecar::{Entry, Sidecar},
+};
+
+/// Only this many of the most recent operations are kept in the journal.
+const MAX_OPERATIONS: usize = 100;
+
+/// A set of changes being made together, like everything done by one command.
+///
+/// The journal is a file in the data directory, with one for each user. Each
+/// operation starts with a `@id`, time and description line, followed by a
+/// line for every entry it changed with the operation ID, the sidecar and what
+/// the entry was before. That is either `had` and the entry's line, or `new`
+/// and its name.
+pub struct Operation {
+    id: Option<u64>,
+    description: String,
+}
+
+/// An operation read back from the journal.
+pub struct Record {
+    pub id: u64,
+    pub time: DateTime<Utc>,
+    pub description: String,
+    pub changes: Vec<Change>,
+}
+
+/// The state of an entry before an operation changed it.
+pub struct Change {
+    pub sidecar: PathBuf,
+    pub name: String,
+    /// None if the entry didn't exist.
+    pub before: Option<Entry>,
+}
+
+impl Operation {
+    pub fn new(description: impl Into<String>) -> Self {
+        Self {
+            id: None,
+            description: description.into(),
+        }
+    }
+
+    /// Journals the change of an entry. Nothing is written if the entry didn't
+    /// actually change, so operations that don't change anything don't show
+    /// up in the journal.
+    pub fn record(
+        &mut self,
+        sidecar: &Path,
+        name: &str,
+        before: Option<&Entry>,
+        after: Option<&Entry>,
+    ) -> Result<()> {
+        if before == after {
+            return Ok(());
+        }
+
+        let path = journal_path()?;
+        let id = match self.id {
+            Some(id) => id,
+            None => self.begin(&path)?,
+        };
+
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        writeln!(
+            file,
+            "{}",
+            change_line(id, &path::absolute(sidecar)?, name, before)
+        )?;
+        Ok(())
+    }
+
+    /// Writes the header of the operation, dropping the oldest operations if
+    /// the journal is full.
+    fn begin(&mut self, path: &Path) -> Result<u64> {
+        let mut records = read_journal()?;
+        let id = records.last().map_or(1, |x| x.id + 1);
+
+        if records.len() >= MAX_OPERATIONS {
+            records.drain(..=records.len() - MAX_OPERATIONS);
+            write_journal(&records)?;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(
+            file,
+            "@{id}\t{}\t{}",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.description.replace(['\t', '\n'], " ")
+        )?;
+
+        self.id = Some(id);
+        Ok(id)
+    }
+}
+
+impl Record {
+    /// Puts every entry the operation changed back how it was, newest change
+    /// first.
+    pub fn undo(&self) -> Result<()> {
+        for change in self.changes.iter().rev() {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .read(true)
+                .write(true)
+                .open(&change.sidecar)?;
+            Sidecar::new(file)?.restore(&change.name, change.before.clone())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Change {
+    /// Path of the video the entry is for. Central storage uses absolute paths
+    /// as names, which [`Path::join`] leaves as they are.
+    pub fn video(&self) -> PathBuf {
+        match self.sidecar.parent() {
+            Some(dir) => dir.join(&self.name),
+            None => PathBuf::from(&self.name),
+        }
+    }
+}
+
+/// Reads every operation in the journal, oldest first.
+pub fn read_journal() -> Result<Vec<Record>> {
+    let path = journal_path()?;
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut records = Vec::<Record>::new();
+    for line in data.lines() {
+        if let Some(header) = line.strip_prefix('@') {
+            let mut fields = header.splitn(3, '\t');
+            let (Some(id), Some(time), Some(description)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let (Ok(id), Ok(time)) = (id.parse(), DateTime::parse_from_rfc3339(time)) else {
+                continue;
+            };
+
+            records.push(Record {
+                id,
+                time: time.to_utc(),
+                description: description.to_owned(),
+                changes: Vec::new(),
+            });
+            continue;
+        }
+
+        let mut fields = line.splitn(4, '\t');
+        let (Some(id), Some(sidecar), Some(kind), Some(entry)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+
+        let Some(record) = records.iter_mut().find(|x| id.parse() == Ok(x.id)) else {
+            continue;
+        };
+
+        let before = match kind {
+            "had" => Some(Entry::parse(entry)),
+            "new" => None,
+            _ => continue,
+        };
+
+        record.changes.push(Change {
+            sidecar: PathBuf::from(sidecar),
+            name: before.as_ref().map_or(entry, |x| &x.name).to_owned(),
+            before,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Removes operations from the journal, after they have been undone.
+pub fn remove(ids: &[u64]) -> Result<()> {
+    let mut records = read_journal()?;
+    records.retain(|x| !ids.contains(&x.id));
+    write_journal(&records)
+}
+
+fn write_journal(records: &[Record]) -> Result<()> {
+    let path = journal_path()?;
+    let mut out = String::new();
+
+    for record in records {
+        out.push_str(&format!(
+            "@{}\t{}\t{}\n",
+            record.id,
+            record.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            record.description
+        ));
+
+        for change in &record.changes {
+            out.push_str(&change_line(
+                record.id,
+                &change.sidecar,
+                &change.name,
+                change.before.as_ref(),
+            ));
+            out.push('\n');
+        }
+    }
+
+    fs::write(path, out)?;
+    Ok(())
+}
+
+fn change_line(id: u64, sidecar: &Path, name: &str, before: Option<&Entry>) -> String {
+    let sidecar = sidecar.to_string_lossy();
+    match before {
+        Some(entry) => format!("{id}\t{sidecar}\thad\t{entry}"),
+        None => format!("{id}\t{sidecar}\tnew\t{name}"),
+    }
+}
+
+/// The journal of the user from the user's config or `--user`, as the journal
+/// isn't tied to any one library.
+fn journal_path() -> Result<PathBuf> {
+    Config::load()?
+        .journal_path()
+        .context("Failed to find journal")
+}
@@ -1,4 +1,23 @@
+/// Default list of video extensions, see [`config::Config::video_extensions`].
 pub const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mkv", "avi", "webm", "flv", "mov", "wmv"];
 
+pub mod batch;
+pub mod cache;
+pub mod config;
+pub mod episode;
+pub mod history;
+pub mod journal;
+pub mod library;
+pub mod probe;
+pub mod progress;
+pub mod properties;
+pub mod registry;
+pub mod selection;
 pub mod sidecar;
+pub mod sniff;
+pub mod state;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod thumbnail;
+#[cfg(windows)]
 pub mod winapi;
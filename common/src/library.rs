@@ -0,0 +1,93 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{
+    config::Config,
+    sidecar::{read_sidecar, Entry},
+};
+
+/// A video in a library along with its sidecar entry, if it has one.
+pub struct Video {
+    pub path: PathBuf,
+    pub entry: Option<Entry>,
+}
+
+/// Finds all the videos under a directory and looks them up in their sidecars.
+pub fn scan(config: &Config, root: &Path) -> Result<Vec<Video>> {
+    let mut sidecars = HashMap::new();
+    let mut videos = Vec::new();
+
+    for path in walk(config, root)? {
+        let (Some(sidecar), Some(name)) = (config.sidecar_path(&path), config.entry_name(&path))
+        else {
+            continue;
+        };
+
+        if !sidecars.contains_key(&sidecar) {
+            let entries = read_sidecar(&sidecar)?;
+            sidecars.insert(sidecar.clone(), entries);
+        }
+
+        let entry = sidecars[&sidecar].iter().find(|x| x.name == name).cloned();
+        videos.push(Video { path, entry });
+    }
+
+    Ok(videos)
+}
+
+/// Recursively finds all the video files under a directory, sorted by path.
+/// Hidden files and directories (starting with a `.`) are skipped unless
+/// enabled in the config.
+pub fn walk(config: &Config, root: &Path) -> Result<Vec<PathBuf>> {
+    let mut videos = Vec::new();
+    walk_into(config, root, &mut videos)?;
+    videos.sort();
+    Ok(videos)
+}
+
+/// Recursively finds all the directories under a directory, including itself,
+/// skipping hidden ones like [`walk`].
+pub fn directories(config: &Config, root: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![root.to_owned()];
+    let mut idx = 0;
+    while let Some(dir) = dirs.get(idx) {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if !config.include_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+            }
+        }
+        idx += 1;
+    }
+
+    dirs.sort();
+    Ok(dirs)
+}
+
+fn walk_into(config: &Config, dir: &Path, videos: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !config.include_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk_into(config, &path, videos)?;
+        } else if file_type.is_file() && config.is_video(&path) {
+            videos.push(path);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,445 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+
+use super::{read_body, scaled, skip, string, Chapter, MediaInfo, MAX_CHAPTERS};
+
+const EBML: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114D9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const TITLE: u32 = 0x7BA9;
+const CHAPTERS: u32 = 0x1043A770;
+const EDITION_ENTRY: u32 = 0x45B9;
+const CHAPTER_ATOM: u32 = 0xB6;
+const CHAPTER_TIME_START: u32 = 0x91;
+const CHAPTER_DISPLAY: u32 = 0x80;
+const CHAP_STRING: u32 = 0x85;
+const CLUSTER: u32 = 0x1F43B675;
+
+/// Timestamps are in nanoseconds unless the file says otherwise.
+const DEFAULT_TIMESTAMP_SCALE: u64 = 1_000_000;
+
+/// The ID and size of an element. Elements that are still being written,
+/// like the segment of a live recording, can have an unknown size.
+struct Header {
+    id: u32,
+    size: Option<u64>,
+}
+
+/// Iterates over the child elements in the body of an element.
+struct Elements<'a>(&'a [u8]);
+
+pub(super) fn probe(file: &mut File) -> Result<MediaInfo> {
+    let ebml = read_header(file)?.context("Empty file")?;
+    if ebml.id != EBML {
+        bail!("Missing EBML header");
+    }
+    skip(file, ebml.size.context("EBML header has unknown size")?)?;
+
+    let segment = read_header(file)?.context("Missing segment")?;
+    if segment.id != SEGMENT {
+        bail!("Missing segment");
+    }
+
+    let segment_start = file.stream_position()?;
+    let segment_end = segment.size.and_then(|x| x.checked_add(segment_start));
+
+    let mut info = MediaInfo::default();
+    let mut seen_info = false;
+    let mut seen_chapters = false;
+    let mut seeks = Vec::new();
+
+    // The info and chapters are usually before the first cluster, but if
+    // they aren't, the seek head says where to find them
+    while !(seen_info && seen_chapters) {
+        if segment_end.is_some_and(|end| file.stream_position().is_ok_and(|x| x >= end)) {
+            break;
+        }
+
+        let Some(element) = read_header(file)? else {
+            break;
+        };
+
+        // Clusters hold the media data, so there is nothing left to find
+        let Some(size) = element.size.filter(|_| element.id != CLUSTER) else {
+            break;
+        };
+
+        match element.id {
+            INFO => {
+                parse_info(&read_body(file, size)?, &mut info);
+                seen_info = true;
+            }
+            CHAPTERS => {
+                info.chapters = parse_chapters(&read_body(file, size)?);
+                seen_chapters = true;
+            }
+            SEEK_HEAD => seeks.extend(parse_seek_head(&read_body(file, size)?)),
+            _ => skip(file, size)?,
+        }
+    }
+
+    for (id, position) in seeks {
+        let seen = match id {
+            INFO => &mut seen_info,
+            CHAPTERS => &mut seen_chapters,
+            _ => continue,
+        };
+
+        let Some(position) = segment_start.checked_add(position) else {
+            continue;
+        };
+        if *seen || position >= file.seek(SeekFrom::End(0))? {
+            continue;
+        }
+
+        file.seek(SeekFrom::Start(position))?;
+        let Some(Header {
+            id: found,
+            size: Some(size),
+        }) = read_header(file)?
+        else {
+            continue;
+        };
+        if found != id {
+            continue;
+        }
+
+        let body = read_body(file, size)?;
+        match id {
+            INFO => parse_info(&body, &mut info),
+            _ => info.chapters = parse_chapters(&body),
+        }
+        *seen = true;
+    }
+
+    Ok(info)
+}
+
+fn parse_info(data: &[u8], info: &mut MediaInfo) {
+    let mut timestamp_scale = DEFAULT_TIMESTAMP_SCALE;
+    let mut duration = None;
+
+    for (id, body) in Elements(data) {
+        match id {
+            TIMESTAMP_SCALE => timestamp_scale = uint(body).unwrap_or(timestamp_scale),
+            DURATION => duration = float(body),
+            TITLE => info.title = string(body),
+            _ => {}
+        }
+    }
+
+    info.duration = duration.and_then(|x| scaled(x * timestamp_scale as f64, 1e9));
+}
+
+/// Reads the chapters of the first edition, ignoring nested chapters.
+fn parse_chapters(data: &[u8]) -> Vec<Chapter> {
+    let Some((_, edition)) = Elements(data).find(|(id, _)| *id == EDITION_ENTRY) else {
+        return Vec::new();
+    };
+
+    Elements(edition)
+        .filter(|(id, _)| *id == CHAPTER_ATOM)
+        .take(MAX_CHAPTERS)
+        .filter_map(|(_, atom)| {
+            let mut start = None;
+            let mut title = None;
+
+            for (id, body) in Elements(atom) {
+                match id {
+                    CHAPTER_TIME_START => start = uint(body),
+                    CHAPTER_DISPLAY if title.is_none() => {
+                        title = Elements(body)
+                            .find(|(id, _)| *id == CHAP_STRING)
+                            .and_then(|(_, x)| string(x))
+                    }
+                    _ => {}
+                }
+            }
+
+            Some(Chapter {
+                start: Duration::from_nanos(start?),
+                title,
+            })
+        })
+        .collect()
+}
+
+/// Gets the IDs of top level elements and their positions relative to the
+/// start of the segment.
+fn parse_seek_head(data: &[u8]) -> Vec<(u32, u64)> {
+    Elements(data)
+        .filter(|(id, _)| *id == SEEK)
+        .filter_map(|(_, seek)| {
+            let mut id = None;
+            let mut position = None;
+            for (child, body) in Elements(seek) {
+                match child {
+                    SEEK_ID => id = uint(body).and_then(|x| u32::try_from(x).ok()),
+                    SEEK_POSITION => position = uint(body),
+                    _ => {}
+                }
+            }
+
+            Some((id?, position?))
+        })
+        .collect()
+}
+
+/// Reads an element header from a file, returning none at the end of it.
+fn read_header(file: &mut File) -> Result<Option<Header>> {
+    let Some((id, id_len)) = read_vint(file)? else {
+        return Ok(None);
+    };
+    let Some((size, size_len)) = read_vint(file)? else {
+        return Ok(None);
+    };
+
+    if id_len > 4 {
+        bail!("Invalid element ID");
+    }
+
+    Ok(Some(Header {
+        id: id as u32,
+        size: size_value(size, size_len),
+    }))
+}
+
+/// Reads a variable length integer, returning it with its length marker
+/// still set along with how many bytes it took up.
+fn read_vint(file: &mut File) -> Result<Option<(u64, usize)>> {
+    let mut buf = [0; 8];
+    match file.read_exact(&mut buf[..1]) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let len = buf[0].leading_zeros() as usize + 1;
+    if len > buf.len() {
+        bail!("Invalid variable length integer");
+    }
+
+    match file.read_exact(&mut buf[1..len]) {
+        Ok(()) => Ok(vint(&buf[..len])),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Parses a variable length integer from the start of the data, see [`read_vint`].
+fn vint(data: &[u8]) -> Option<(u64, usize)> {
+    let len = data.first()?.leading_zeros() as usize + 1;
+    let value = data
+        .get(..len)?
+        .iter()
+        .fold(0, |acc, &x| (acc << 8) | u64::from(x));
+    Some((value, len))
+}
+
+/// Strips the length marker off a size, which is unknown if all of its other
+/// bits are set.
+fn size_value(size: u64, len: usize) -> Option<u64> {
+    let mask = (1 << (7 * len)) - 1;
+    (size & mask != mask).then_some(size & mask)
+}
+
+fn uint(data: &[u8]) -> Option<u64> {
+    (data.len() <= 8).then(|| data.iter().fold(0, |acc, &x| (acc << 8) | u64::from(x)))
+}
+
+fn float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes(data.try_into().ok()?).into()),
+        8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = (u32, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (id, id_len) = vint(self.0).filter(|(_, len)| *len <= 4)?;
+        let rest = &self.0[id_len..];
+        let (size, size_len) = vint(rest).filter(|(_, len)| *len <= 8)?;
+        let rest = &rest[size_len..];
+
+        // Truncated or unknown size children take up the rest of their parent
+        let size = size_value(size, size_len)
+            .and_then(|x| usize::try_from(x).ok())
+            .map_or(rest.len(), |x| x.min(rest.len()));
+
+        let (body, rest) = rest.split_at(size);
+        self.0 = rest;
+        Some((id as u32, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::tests::{assert_never_panics, probe_bytes};
+
+    /// An element with its size written as an eight byte vint, like muxers
+    /// that fill the size in afterwards do.
+    fn element(id: u32, body: &[u8]) -> Vec<u8> {
+        let mut data = id.to_be_bytes().to_vec();
+        data.drain(..data.iter().take_while(|x| **x == 0).count());
+        data.push(0x01);
+        data.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn chapter(start_ns: u64, title: &str) -> Vec<u8> {
+        let display = element(CHAPTER_DISPLAY, &element(CHAP_STRING, title.as_bytes()));
+        let mut atom = element(CHAPTER_TIME_START, &start_ns.to_be_bytes());
+        atom.extend(display);
+        element(CHAPTER_ATOM, &atom)
+    }
+
+    fn info() -> Vec<u8> {
+        let mut info = element(TIMESTAMP_SCALE, &1_000_000u32.to_be_bytes());
+        info.extend(element(DURATION, &1_425_500.0f64.to_be_bytes()));
+        info.extend(element(TITLE, b"Pilot"));
+        element(INFO, &info)
+    }
+
+    fn chapters() -> Vec<u8> {
+        let mut edition = chapter(0, "Intro");
+        edition.extend(chapter(90_000_000_000, "Act 1"));
+        element(CHAPTERS, &element(EDITION_ENTRY, &edition))
+    }
+
+    fn file(segment: &[u8]) -> Vec<u8> {
+        let mut data = element(EBML, &element(0x4282, b"matroska"));
+        data.extend(element(SEGMENT, segment));
+        data
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut segment = info();
+        segment.extend(chapters());
+        segment.extend(element(CLUSTER, &[0xE7, 0x81, 0x00]));
+        file(&segment)
+    }
+
+    fn expected() -> MediaInfo {
+        MediaInfo {
+            duration: Some(Duration::from_millis(1_425_500)),
+            title: Some("Pilot".to_owned()),
+            chapters: vec![
+                Chapter {
+                    start: Duration::ZERO,
+                    title: Some("Intro".to_owned()),
+                },
+                Chapter {
+                    start: Duration::from_secs(90),
+                    title: Some("Act 1".to_owned()),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn reads_sample() {
+        assert_eq!(probe_bytes(&sample()).unwrap(), expected());
+    }
+
+    #[test]
+    fn follows_seek_head_past_clusters() {
+        let mut cluster = element(CLUSTER, &[0xE7, 0x81, 0x00]);
+        let seek = |id: u32, position: usize| {
+            let mut seek = element(SEEK_ID, &id.to_be_bytes());
+            seek.extend(element(SEEK_POSITION, &(position as u64).to_be_bytes()));
+            element(SEEK, &seek)
+        };
+
+        // The seek head's own size doesn't depend on the positions in it
+        let head_len = element(SEEK_HEAD, &[seek(INFO, 0), seek(CHAPTERS, 0)].concat()).len();
+        let info_at = head_len + cluster.len();
+        let chapters_at = info_at + info().len();
+        let mut segment = element(
+            SEEK_HEAD,
+            &[seek(INFO, info_at), seek(CHAPTERS, chapters_at)].concat(),
+        );
+        segment.append(&mut cluster);
+        segment.extend(info());
+        segment.extend(chapters());
+
+        assert_eq!(probe_bytes(&file(&segment)).unwrap(), expected());
+    }
+
+    #[test]
+    fn never_panics_on_damaged_files() {
+        assert_never_panics(&sample());
+    }
+
+    #[test]
+    fn handles_huge_and_unknown_sizes() {
+        // An unknown size segment, like a live recording
+        let mut live = element(EBML, &[]);
+        live.extend(SEGMENT.to_be_bytes());
+        live.extend([0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        live.extend(info());
+        assert_eq!(probe_bytes(&live).unwrap().title, Some("Pilot".to_owned()));
+
+        // The largest known size, for an element that isn't really there
+        let mut huge = element(EBML, &[]);
+        huge.extend(SEGMENT.to_be_bytes());
+        huge.extend([0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]);
+        huge.extend(INFO.to_be_bytes());
+        huge.extend([0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]);
+        assert!(probe_bytes(&huge).is_err());
+
+        // Unknown elements running past the end of the file are skipped, and
+        // ones too large to even seek past are an error
+        let mut skipped = element(EBML, &[]);
+        skipped.extend(element(
+            SEGMENT,
+            &[0xEC, 0x01, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00],
+        ));
+        assert_eq!(probe_bytes(&skipped).unwrap(), MediaInfo::default());
+        let mut unseekable = element(EBML, &[]);
+        unseekable.extend(element(
+            SEGMENT,
+            &[0xEC, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE],
+        ));
+        assert!(probe_bytes(&unseekable).is_err());
+
+        // A vint can't be more than eight bytes long
+        let mut invalid = element(EBML, &[]);
+        invalid.extend([0x18, 0x53, 0x80, 0x67, 0x00, 0x00]);
+        assert!(probe_bytes(&invalid).is_err());
+    }
+
+    #[test]
+    fn child_elements_stay_within_parent() {
+        // A child claiming to be larger than its parent, and one with an
+        // unknown size, both take up the rest of it
+        let children = [0x7B, 0xA9, 0x10, 0x00, 0x01, 0x00, b'P', b'i'];
+        assert_eq!(
+            Elements(&children).collect::<Vec<_>>(),
+            [(TITLE, &b"Pi"[..])]
+        );
+        assert_eq!(
+            Elements(&[0x7B, 0xA9, 0xFF, b'x']).next(),
+            Some((TITLE, &b"x"[..]))
+        );
+        assert_eq!(Elements(&[0x00, 0x81]).next(), None);
+        assert_eq!(Elements(&[0x7B]).next(), None);
+        assert_eq!(uint(&[1; 9]), None);
+        assert_eq!(float(&[0; 3]), None);
+    }
+}
@@ -0,0 +1,158 @@
+//! Reads basic metadata from the headers of video files, without decoding
+//! them or reading the media data.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::sniff::{sniff, Container, SNIFF_LEN};
+
+mod matroska;
+mod mp4;
+
+/// Header elements larger than this are skipped rather than read into memory.
+const MAX_ELEMENT_SIZE: u64 = 64 * 1024 * 1024;
+/// Files with more chapters than this only have the first ones read.
+const MAX_CHAPTERS: usize = 1024;
+
+/// Metadata read from a video's headers. Everything is optional, as it is
+/// only filled in when the file has it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediaInfo {
+    pub duration: Option<Duration>,
+    pub title: Option<String>,
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: Option<String>,
+}
+
+/// Reads the duration, title and chapters of a Matroska / WebM or MP4 / MOV
+/// file. Other containers are an error.
+pub fn probe(path: &Path) -> Result<MediaInfo> {
+    let mut file = File::open(path)?;
+
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    (&mut file)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)?;
+    file.rewind()?;
+
+    match sniff(&header) {
+        Some(Container::Matroska) => matroska::probe(&mut file),
+        Some(Container::Mp4) => mp4::probe(&mut file),
+        Some(container) => bail!("Probing {container:?} files is not supported"),
+        None => bail!("Unknown container"),
+    }
+    .with_context(|| format!("Failed to probe {}", path.display()))
+}
+
+/// Reads an element of the given size from the current position. The result
+/// is shorter than `size` if the file is truncated.
+fn read_body(file: &mut File, size: u64) -> Result<Vec<u8>> {
+    if size > MAX_ELEMENT_SIZE {
+        bail!("Header element is too large");
+    }
+
+    let mut data = Vec::new();
+    file.take(size).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn skip(file: &mut File, size: u64) -> Result<()> {
+    file.seek(SeekFrom::Current(size.try_into()?))?;
+    Ok(())
+}
+
+/// Reads `N` bytes at an offset, if the data is long enough.
+fn bytes<const N: usize>(data: &[u8], at: usize) -> Option<[u8; N]> {
+    data.get(at..at.checked_add(N)?)?.try_into().ok()
+}
+
+/// Converts a duration in units of `1 / timescale` seconds, rejecting ones
+/// that are negative, not finite or too large.
+fn scaled(duration: f64, timescale: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(duration / timescale).ok()
+}
+
+/// Decodes a string, dropping the null padding some muxers add.
+fn string(data: &[u8]) -> Option<String> {
+    let string = String::from_utf8_lossy(data);
+    let string = string.trim_end_matches('\0').trim();
+    (!string.is_empty()).then(|| string.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, panic};
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    /// Probes a file with the given contents.
+    pub(super) fn probe_bytes(data: &[u8]) -> Result<MediaInfo> {
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), data).unwrap();
+        probe(file.path())
+    }
+
+    /// Probes every prefix of a file and every file with one byte replaced,
+    /// which may fail but must never panic or hang.
+    pub(super) fn assert_never_panics(data: &[u8]) {
+        let file = NamedTempFile::new().unwrap();
+        let probe_variant = |variant: &[u8], what: String| {
+            fs::write(file.path(), variant).unwrap();
+            let result = panic::catch_unwind(|| probe(file.path()));
+            assert!(result.is_ok(), "Probing panicked with {what}");
+        };
+
+        for len in 0..data.len() {
+            probe_variant(&data[..len], format!("the file truncated to {len} bytes"));
+        }
+
+        for at in 0..data.len() {
+            for byte in [0x00, 0x01, 0x08, 0x7F, 0x80, 0xFF] {
+                let mut variant = data.to_vec();
+                variant[at] = byte;
+                probe_variant(&variant, format!("byte {at} set to {byte:#04x}"));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_other_containers() {
+        assert!(probe_bytes(b"").is_err());
+        assert!(probe_bytes(b"RIFF\0\0\0\0AVI LIST").is_err());
+        assert!(probe_bytes(&[0x47; 188 * 3]).is_err());
+    }
+
+    #[test]
+    fn reads_element_bodies_up_to_limit() {
+        let mut file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), [1, 2, 3]).unwrap();
+        assert_eq!(read_body(file.as_file_mut(), 16).unwrap(), [1, 2, 3]);
+        assert!(read_body(file.as_file_mut(), MAX_ELEMENT_SIZE + 1).is_err());
+        assert!(skip(file.as_file_mut(), u64::MAX).is_err());
+    }
+
+    #[test]
+    fn helpers_reject_out_of_range_values() {
+        assert_eq!(bytes::<4>(&[1, 2, 3], 0), None);
+        assert_eq!(bytes::<1>(&[1, 2, 3], usize::MAX), None);
+        assert_eq!(scaled(-1.0, 1.0), None);
+        assert_eq!(scaled(1.0, 0.0), None);
+        assert_eq!(scaled(f64::NAN, 1.0), None);
+        assert_eq!(scaled(f64::MAX, 1e-9), None);
+        assert_eq!(string(b"Title\0\0"), Some("Title".to_owned()));
+        assert_eq!(string(b"\0\0"), None);
+    }
+}
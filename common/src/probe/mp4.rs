@@ -0,0 +1,601 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use super::{bytes, read_body, scaled, skip, string, Chapter, MediaInfo, MAX_CHAPTERS};
+
+/// Longest chapter title that is read from a chapter track.
+const MAX_TITLE_LEN: u64 = 1024;
+/// Nero chapter start times are in 100ns units.
+const CHPL_TIMESCALE: f64 = 10_000_000.0;
+
+/// Iterates over the child boxes in the body of a box.
+struct Boxes<'a>(&'a [u8]);
+
+/// A `trak` box and the parts of it needed to find chapters.
+struct Track<'a> {
+    id: u32,
+    chapter_tracks: Vec<u32>,
+    timescale: u32,
+    stbl: &'a [u8],
+}
+
+pub(super) fn probe(file: &mut File) -> Result<MediaInfo> {
+    let moov = read_moov(file)?.context("Missing moov box")?;
+    let mut info = MediaInfo::default();
+
+    let mut tracks = Vec::new();
+    for (kind, body) in Boxes(&moov) {
+        match &kind {
+            b"mvhd" => info.duration = parse_mvhd(body),
+            b"udta" => parse_udta(body, &mut info),
+            b"trak" => tracks.extend(parse_trak(body)),
+            _ => {}
+        }
+    }
+
+    // QuickTime style chapters are a text track referenced by the video track
+    if info.chapters.is_empty() {
+        let chapter_track = tracks
+            .iter()
+            .flat_map(|x| &x.chapter_tracks)
+            .find_map(|id| tracks.iter().find(|x| x.id == *id));
+
+        if let Some(track) = chapter_track {
+            info.chapters = read_chapter_track(file, track)?;
+        }
+    }
+
+    Ok(info)
+}
+
+/// Finds the `moov` box among the top level boxes, skipping over the media
+/// data that can come before it.
+fn read_moov(file: &mut File) -> Result<Option<Vec<u8>>> {
+    loop {
+        let mut header = [0; 8];
+        match file.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+
+        let (size, header_len) = match u32::from_be_bytes(header[..4].try_into()?) {
+            0 => (None, 8),
+            1 => {
+                let mut size = [0; 8];
+                file.read_exact(&mut size)?;
+                (Some(u64::from_be_bytes(size)), 16)
+            }
+            size => (Some(u64::from(size)), 8),
+        };
+
+        let body_size = match size {
+            Some(size) => size.checked_sub(header_len).context("Invalid box size")?,
+            // Boxes without a size run to the end of the file
+            None => {
+                let position = file.stream_position()?;
+                let len = file.seek(SeekFrom::End(0))?;
+                file.seek(SeekFrom::Start(position))?;
+                len - position
+            }
+        };
+
+        if &header[4..] == b"moov" {
+            return Ok(Some(read_body(file, body_size)?));
+        }
+
+        if size.is_none() {
+            return Ok(None);
+        }
+        skip(file, body_size)?;
+    }
+}
+
+fn parse_mvhd(data: &[u8]) -> Option<Duration> {
+    let (timescale, duration) = match data.first()? {
+        0 => (
+            u32::from_be_bytes(bytes(data, 12)?),
+            u64::from(u32::from_be_bytes(bytes(data, 16)?)),
+        ),
+        _ => (
+            u32::from_be_bytes(bytes(data, 20)?),
+            u64::from_be_bytes(bytes(data, 24)?),
+        ),
+    };
+
+    // All bits set means the duration isn't known
+    if timescale == 0 || duration == u64::MAX || duration == u64::from(u32::MAX) {
+        return None;
+    }
+
+    scaled(duration as f64, f64::from(timescale))
+}
+
+fn parse_udta(data: &[u8], info: &mut MediaInfo) {
+    for (kind, body) in Boxes(data) {
+        match &kind {
+            b"chpl" => info.chapters = parse_chpl(body),
+            b"meta" => info.title = parse_meta_title(body).or(info.title.take()),
+            _ => {}
+        }
+    }
+}
+
+/// Reads Nero style chapters.
+fn parse_chpl(data: &[u8]) -> Vec<Chapter> {
+    let mut chapters = Vec::new();
+    let Some(&version) = data.first() else {
+        return chapters;
+    };
+
+    let mut at = if version == 0 { 4 } else { 8 };
+    let Some([count]) = bytes(data, at) else {
+        return chapters;
+    };
+    at += 1;
+
+    for _ in 0..count {
+        let (Some(start), Some([len])) = (bytes(data, at), bytes::<1>(data, at + 8)) else {
+            break;
+        };
+        at += 9;
+
+        let Some(title) = data.get(at..at + usize::from(len)) else {
+            break;
+        };
+        at += usize::from(len);
+
+        let Some(start) = scaled(u64::from_be_bytes(start) as f64, CHPL_TIMESCALE) else {
+            continue;
+        };
+        chapters.push(Chapter {
+            start,
+            title: string(title),
+        });
+    }
+
+    chapters
+}
+
+/// Reads the title from iTunes style `meta/ilst/©nam` metadata.
+fn parse_meta_title(data: &[u8]) -> Option<String> {
+    // In MP4 files the meta box has a version and flags, but not in QuickTime
+    let data = match data.get(4..8) {
+        Some(b"hdlr") => data,
+        _ => data.get(4..)?,
+    };
+
+    let (_, ilst) = Boxes(data).find(|(kind, _)| kind == b"ilst")?;
+    let (_, name) = Boxes(ilst).find(|(kind, _)| kind == b"\xa9nam")?;
+    let (_, value) = Boxes(name).find(|(kind, _)| kind == b"data")?;
+
+    // Skip the type and locale
+    string(value.get(8..)?)
+}
+
+fn parse_trak(data: &[u8]) -> Option<Track<'_>> {
+    let mut track = Track {
+        id: 0,
+        chapter_tracks: Vec::new(),
+        timescale: 0,
+        stbl: &[],
+    };
+
+    for (kind, body) in Boxes(data) {
+        match &kind {
+            b"tkhd" => {
+                let at = if *body.first()? == 0 { 12 } else { 20 };
+                track.id = u32::from_be_bytes(bytes(body, at)?);
+            }
+            b"tref" => {
+                if let Some((_, chap)) = Boxes(body).find(|(kind, _)| kind == b"chap") {
+                    track.chapter_tracks = chap
+                        .chunks_exact(4)
+                        .filter_map(|x| bytes(x, 0))
+                        .map(u32::from_be_bytes)
+                        .collect();
+                }
+            }
+            b"mdia" => {
+                for (kind, body) in Boxes(body) {
+                    match &kind {
+                        b"mdhd" => {
+                            let at = if *body.first()? == 0 { 12 } else { 20 };
+                            track.timescale = u32::from_be_bytes(bytes(body, at)?);
+                        }
+                        b"minf" => {
+                            if let Some((_, stbl)) = Boxes(body).find(|(kind, _)| kind == b"stbl") {
+                                track.stbl = stbl;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(track)
+}
+
+/// Reads chapters from a text track, where each sample is the title of a
+/// chapter and its time is when the chapter starts.
+fn read_chapter_track(file: &mut File, track: &Track) -> Result<Vec<Chapter>> {
+    if track.timescale == 0 {
+        return Ok(Vec::new());
+    }
+
+    let offsets = sample_offsets(track.stbl);
+    let starts = sample_times(track.stbl, offsets.len());
+    let file_len = file.seek(SeekFrom::End(0))?;
+
+    let mut chapters = Vec::new();
+    for (offset, start) in offsets.into_iter().zip(starts) {
+        let Some(start) = scaled(start as f64, f64::from(track.timescale)) else {
+            continue;
+        };
+
+        let mut title = None;
+        if offset < file_len {
+            file.seek(SeekFrom::Start(offset))?;
+
+            let mut len = [0; 2];
+            if file.read_exact(&mut len).is_ok() {
+                let len = u64::from(u16::from_be_bytes(len)).min(MAX_TITLE_LEN);
+                let mut data = Vec::new();
+                (&mut *file).take(len).read_to_end(&mut data)?;
+                title = string(&data);
+            }
+        }
+
+        chapters.push(Chapter { start, title });
+    }
+
+    Ok(chapters)
+}
+
+/// Works out where each sample of a track is stored in the file from its
+/// chunk offsets, samples per chunk and sample sizes.
+fn sample_offsets(stbl: &[u8]) -> Vec<u64> {
+    let mut chunk_offsets = Vec::new();
+    let mut sizes = Vec::new();
+    let mut samples_per_chunk = Vec::new();
+
+    for (kind, body) in Boxes(stbl) {
+        match &kind {
+            b"stco" => {
+                chunk_offsets = table(body, 4, |x, at| {
+                    Some(u32::from_be_bytes(bytes(x, at)?).into())
+                })
+            }
+            b"co64" => {
+                chunk_offsets = table(body, 8, |x, at| Some(u64::from_be_bytes(bytes(x, at)?)))
+            }
+            b"stsc" => {
+                samples_per_chunk = table(body, 12, |x, at| {
+                    Some((
+                        u32::from_be_bytes(bytes(x, at)?),
+                        u32::from_be_bytes(bytes(x, at + 4)?),
+                    ))
+                })
+            }
+            b"stsz" => {
+                let (Some(size), Some(count)) = (bytes(body, 4), bytes(body, 8)) else {
+                    continue;
+                };
+                let (size, count) = (u32::from_be_bytes(size), u32::from_be_bytes(count));
+                sizes = match size {
+                    0 => (0..count as usize)
+                        .map_while(|idx| Some(u32::from_be_bytes(bytes(body, 12 + idx * 4)?)))
+                        .take(MAX_CHAPTERS)
+                        .collect(),
+                    size => vec![size; (count as usize).min(MAX_CHAPTERS)],
+                };
+            }
+            _ => {}
+        }
+    }
+
+    let mut offsets = Vec::new();
+    let mut sizes = sizes.into_iter();
+    for (idx, mut offset) in chunk_offsets.into_iter().enumerate() {
+        let chunk = idx as u64 + 1;
+        let Some(&(_, count)) = samples_per_chunk
+            .iter()
+            .rev()
+            .find(|(first, _)| u64::from(*first) <= chunk)
+        else {
+            break;
+        };
+
+        for _ in 0..count {
+            let Some(size) = sizes.next() else {
+                return offsets;
+            };
+            offsets.push(offset);
+            offset = offset.saturating_add(size.into());
+        }
+    }
+
+    offsets
+}
+
+/// Gets the start time of the first `count` samples of a track.
+fn sample_times(stbl: &[u8], count: usize) -> Vec<u64> {
+    let Some((_, stts)) = Boxes(stbl).find(|(kind, _)| kind == b"stts") else {
+        return Vec::new();
+    };
+
+    let mut times = Vec::new();
+    let mut time = 0u64;
+    let deltas = table(stts, 8, |x, at| {
+        Some((
+            u32::from_be_bytes(bytes(x, at)?),
+            u32::from_be_bytes(bytes(x, at + 4)?),
+        ))
+    });
+
+    for (samples, delta) in deltas {
+        for _ in 0..samples {
+            if times.len() >= count {
+                return times;
+            }
+            times.push(time);
+            time = time.saturating_add(delta.into());
+        }
+    }
+
+    times
+}
+
+/// Reads the entries of a full box holding an entry count followed by a
+/// table of fixed size entries, stopping early if the box is truncated.
+///
+/// Tables are only read for chapter tracks, which never need more entries
+/// than there are chapters, so at most [`MAX_CHAPTERS`] are read. Otherwise
+/// a file with huge `stco` and `stsc` tables would make [`sample_offsets`]
+/// take quadratic time.
+fn table<T>(data: &[u8], entry_len: usize, entry: impl Fn(&[u8], usize) -> Option<T>) -> Vec<T> {
+    let Some(count) = bytes(data, 4).map(u32::from_be_bytes) else {
+        return Vec::new();
+    };
+
+    (0..count as usize)
+        .take(MAX_CHAPTERS)
+        .map_while(|idx| entry(data, 8 + idx * entry_len))
+        .collect()
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = ([u8; 4], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size = u32::from_be_bytes(bytes(self.0, 0)?);
+        let kind = bytes(self.0, 4)?;
+
+        let (size, header_len) = match size {
+            0 => (self.0.len() as u64, 8),
+            1 => (u64::from_be_bytes(bytes(self.0, 8)?), 16),
+            size => (u64::from(size), 8),
+        };
+
+        // Truncated boxes take up the rest of their parent
+        let size = usize::try_from(size).map_or(self.0.len(), |x| x.min(self.0.len()));
+        let body = self.0.get(header_len..size)?;
+        self.0 = &self.0[size..];
+        Some((kind, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::probe::tests::{assert_never_panics, probe_bytes};
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// A full box's version and flags followed by its fields.
+    fn full_box(kind: &[u8; 4], fields: &[u32]) -> Vec<u8> {
+        let body = [0]
+            .iter()
+            .chain(fields)
+            .flat_map(|x| x.to_be_bytes())
+            .collect::<Vec<_>>();
+        mp4_box(kind, &body)
+    }
+
+    fn track(id: u32, chapters: Option<u32>, media: &[u8]) -> Vec<u8> {
+        let mut trak = full_box(b"tkhd", &[0, 0, id]);
+        if let Some(chapters) = chapters {
+            trak.extend(mp4_box(b"tref", &mp4_box(b"chap", &chapters.to_be_bytes())));
+        }
+        trak.extend(mp4_box(b"mdia", media));
+        mp4_box(b"trak", &trak)
+    }
+
+    /// A QuickTime chapter track, with each chapter's title as a sample
+    /// stored at `offset`.
+    fn chapter_track(id: u32, offset: u32, titles: &[&str]) -> Vec<u8> {
+        let count = titles.len() as u32;
+        let mut stbl = full_box(b"stts", &[1, count, 90_000]);
+        stbl.extend(full_box(b"stsc", &[1, 1, count, 1]));
+        let sizes = titles.iter().map(|x| x.len() as u32 + 2);
+        stbl.extend(full_box(
+            b"stsz",
+            &[[0, count].as_slice(), &sizes.collect::<Vec<_>>()].concat(),
+        ));
+        stbl.extend(full_box(b"stco", &[1, offset]));
+
+        let mut media = full_box(b"mdhd", &[0, 0, 1000, 0]);
+        media.extend(mp4_box(b"minf", &mp4_box(b"stbl", &stbl)));
+        track(id, None, &media)
+    }
+
+    fn samples(titles: &[&str]) -> Vec<u8> {
+        titles
+            .iter()
+            .flat_map(|x| [&(x.len() as u16).to_be_bytes(), x.as_bytes()].concat())
+            .collect()
+    }
+
+    fn udta_title(title: &str) -> Vec<u8> {
+        let data = mp4_box(
+            b"data",
+            &[&[0, 0, 0, 1, 0, 0, 0, 0], title.as_bytes()].concat(),
+        );
+        let ilst = mp4_box(b"ilst", &mp4_box(b"\xa9nam", &data));
+        mp4_box(
+            b"udta",
+            &mp4_box(b"meta", &[&[0; 4], ilst.as_slice()].concat()),
+        )
+    }
+
+    /// A file with the moov box first, and the chapter titles in the media
+    /// data after it.
+    fn sample() -> Vec<u8> {
+        let titles = ["Intro", "Act 1"];
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\x02\0isom");
+        let moov = |offset: u32| {
+            let mut moov = full_box(b"mvhd", &[0, 0, 1000, 1_425_500]);
+            moov.extend(udta_title("Pilot"));
+            moov.extend(track(1, Some(2), &full_box(b"mdhd", &[0, 0, 1000, 0])));
+            moov.extend(chapter_track(2, offset, &titles));
+            mp4_box(b"moov", &moov)
+        };
+
+        let offset = ftyp.len() + moov(0).len() + 8;
+        [
+            ftyp,
+            moov(offset as u32),
+            mp4_box(b"mdat", &samples(&titles)),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn reads_sample() {
+        assert_eq!(
+            probe_bytes(&sample()).unwrap(),
+            MediaInfo {
+                duration: Some(Duration::from_millis(1_425_500)),
+                title: Some("Pilot".to_owned()),
+                chapters: vec![
+                    Chapter {
+                        start: Duration::ZERO,
+                        title: Some("Intro".to_owned()),
+                    },
+                    Chapter {
+                        start: Duration::from_secs(90),
+                        title: Some("Act 1".to_owned()),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn never_panics_on_damaged_files() {
+        assert_never_panics(&sample());
+    }
+
+    #[test]
+    fn handles_size_0_and_1_boxes() {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\x02\0");
+        let mvhd = full_box(b"mvhd", &[0, 0, 1000, 60_000]);
+
+        // Size 0 runs to the end of the file
+        let mut to_end = ftyp.clone();
+        to_end.extend([0, 0, 0, 0]);
+        to_end.extend(b"moov");
+        to_end.extend(&mvhd);
+        assert_eq!(
+            probe_bytes(&to_end).unwrap().duration,
+            Some(Duration::from_secs(60))
+        );
+
+        // Nothing can come after a size 0 box
+        let mut mdat_to_end = ftyp.clone();
+        mdat_to_end.extend([0, 0, 0, 0]);
+        mdat_to_end.extend(b"mdat");
+        mdat_to_end.extend(mp4_box(b"moov", &mvhd));
+        assert!(probe_bytes(&mdat_to_end).is_err());
+
+        // Size 1 has a 64 bit size after the type
+        let mut large = ftyp.clone();
+        large.extend([0, 0, 0, 1]);
+        large.extend(b"moov");
+        large.extend((mvhd.len() as u64 + 16).to_be_bytes());
+        large.extend(&mvhd);
+        assert_eq!(
+            probe_bytes(&large).unwrap().duration,
+            Some(Duration::from_secs(60))
+        );
+
+        // A 64 bit size smaller than the header, or too large to seek past
+        for size in [0, 15, u64::MAX] {
+            let mut invalid = ftyp.clone();
+            invalid.extend([0, 0, 0, 1]);
+            invalid.extend(b"free");
+            invalid.extend(size.to_be_bytes());
+            invalid.extend(mp4_box(b"moov", &mvhd));
+            assert!(probe_bytes(&invalid).is_err(), "size {size}");
+        }
+
+        // Inside a box, the same sizes stay within the parent
+        let children = [&[0, 0, 0, 1][..], b"mvhd", &0u64.to_be_bytes()].concat();
+        assert_eq!(Boxes(&children).next(), None);
+        let children = [&[0, 0, 0, 0][..], b"mvhd", &[1, 2]].concat();
+        assert_eq!(Boxes(&children).next(), Some((*b"mvhd", &[1, 2][..])));
+        let children = [&[0, 0, 0, 4][..], b"mvhd"].concat();
+        assert_eq!(Boxes(&children).next(), None);
+    }
+
+    #[test]
+    fn handles_oversized_counts() {
+        // Tables claiming far more entries than they hold
+        let mut stbl = full_box(b"stts", &[u32::MAX, 2, 1000]);
+        stbl.extend(full_box(b"stsc", &[u32::MAX, 1, u32::MAX, 1]));
+        stbl.extend(full_box(b"stsz", &[7, u32::MAX]));
+        stbl.extend(full_box(b"stco", &[u32::MAX, 100]));
+        let offsets = sample_offsets(&stbl);
+        assert_eq!(offsets.len(), MAX_CHAPTERS);
+        assert_eq!(offsets[..2], [100, 107]);
+        assert_eq!(sample_times(&stbl, offsets.len()), [0, 1000]);
+
+        let chpl = [&[1, 0, 0, 0, 0, 0, 0, 0, 0xFF][..], &[0; 9]].concat();
+        assert_eq!(parse_chpl(&chpl).len(), 1);
+    }
+
+    #[test]
+    fn caps_sample_tables() {
+        let entries = 200_000u32;
+        let stco = (0..entries).map(|x| x * 10).collect::<Vec<_>>();
+        let stco = full_box(b"stco", &[[entries].as_slice(), &stco].concat());
+        let table = table(&stco[8..], 4, bytes::<4>);
+        assert_eq!(table.len(), MAX_CHAPTERS);
+
+        // Chunks without samples, each with their own stsc entry, have every
+        // chunk look through every entry without ever running out of sizes
+        let stsc = (1..=entries).flat_map(|x| [x, 0, 1]).collect::<Vec<_>>();
+        let mut stbl = full_box(b"stsc", &[[entries].as_slice(), &stsc].concat());
+        stbl.extend(stco);
+        stbl.extend(full_box(b"stsz", &[10, entries]));
+
+        let started = Instant::now();
+        assert_eq!(sample_offsets(&stbl), []);
+        assert!(started.elapsed().as_secs() < 5);
+    }
+}
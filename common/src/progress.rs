@@ -0,0 +1,14 @@
+use std::time::Duration;
+
+/// Default fraction of a video that has to be played before it is considered
+/// watched, see [`crate::config::Config::watched_threshold`].
+pub const WATCHED_THRESHOLD: f64 = 0.9;
+
+/// Checks if a playback position is far enough into a video to count it as watched.
+pub fn is_watched(position: Duration, duration: Duration, threshold: f64) -> bool {
+    if duration.is_zero() {
+        return false;
+    }
+
+    position.as_secs_f64() / duration.as_secs_f64() >= threshold
+}
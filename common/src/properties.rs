@@ -0,0 +1,276 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    cache,
+    config::Config,
+    history::{record, Action, Event, Source},
+    journal::Operation,
+    sidecar::{open_or_create_sidecar, Entry, Sidecar},
+};
+
+/// Format ID the properties are grouped under, which has to match the one in
+/// `last-watched.propdesc`.
+// {2ce6f2a7-9ba0-41c8-abd1-f2ada5a13800}
+pub const FORMAT_ID: u128 = 0x2ce6f2a7_9ba0_41c8_abd1_f2ada5a13800;
+
+/// Difference between the Windows `FILETIME` epoch (1601) and the Unix epoch,
+/// in seconds.
+const FILETIME_EPOCH_OFFSET: i64 = 11_644_473_600;
+
+/// What the property handler shows about a video, as columns in Explorer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    Watched,
+    WatchCount,
+    LastWatched,
+    /// Out of 10, like in the sidecar.
+    Rating,
+}
+
+pub const PROPERTIES: [Property; 4] = [
+    Property::Watched,
+    Property::WatchCount,
+    Property::LastWatched,
+    Property::Rating,
+];
+
+/// A property value, before it's converted to or after it's converted from
+/// a `PROPVARIANT`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropertyValue {
+    Empty,
+    Bool(bool),
+    UInt(u32),
+    Time(DateTime<Utc>),
+}
+
+/// Changes made to a video's properties, like from the Details pane, which
+/// are only written to its sidecar when committed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PropertyChanges {
+    watched: Option<bool>,
+    /// Some none to remove the rating.
+    rating: Option<Option<u8>>,
+}
+
+impl Property {
+    /// The property's ID within [`FORMAT_ID`]. IDs 0 and 1 are reserved.
+    pub fn id(self) -> u32 {
+        match self {
+            Self::Watched => 2,
+            Self::WatchCount => 3,
+            Self::LastWatched => 4,
+            Self::Rating => 5,
+        }
+    }
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        PROPERTIES.into_iter().find(|x| x.id() == id)
+    }
+
+    /// Whether the property can be changed, see [`PropertyChanges`].
+    pub fn is_writable(self) -> bool {
+        matches!(self, Self::Watched | Self::Rating)
+    }
+
+    /// Name of the property in the schema.
+    pub fn canonical_name(self) -> &'static str {
+        match self {
+            Self::Watched => "LastWatched.Watched",
+            Self::WatchCount => "LastWatched.WatchCount",
+            Self::LastWatched => "LastWatched.LastWatched",
+            Self::Rating => "LastWatched.Rating",
+        }
+    }
+
+    /// Gets the property of a video from its entry, if it has one.
+    pub fn value(self, entry: Option<&Entry>) -> PropertyValue {
+        match self {
+            Self::Watched => PropertyValue::Bool(entry.is_some_and(|x| x.watched)),
+            Self::WatchCount => PropertyValue::UInt(entry.map_or(0, |x| x.count)),
+            Self::LastWatched => match entry.and_then(|x| x.watched_at) {
+                Some(time) => PropertyValue::Time(time),
+                None => PropertyValue::Empty,
+            },
+            Self::Rating => match entry.and_then(|x| x.rating) {
+                Some(rating) => PropertyValue::UInt(rating.into()),
+                None => PropertyValue::Empty,
+            },
+        }
+    }
+}
+
+impl PropertyChanges {
+    /// Changes a property, replacing any earlier change to it. Ratings are
+    /// from 1 to 10, and empty or 0 removes it.
+    pub fn set(&mut self, property: Property, value: PropertyValue) -> Result<()> {
+        match (property, value) {
+            (Property::Watched, PropertyValue::Bool(watched)) => self.watched = Some(watched),
+            (Property::Rating, PropertyValue::Empty | PropertyValue::UInt(0)) => {
+                self.rating = Some(None)
+            }
+            (Property::Rating, PropertyValue::UInt(rating @ 1..=10)) => {
+                self.rating = Some(Some(rating as u8))
+            }
+            (Property::Rating, PropertyValue::UInt(rating)) => {
+                bail!("Rating must be out of 10, not {rating}")
+            }
+            (property, _) if !property.is_writable() => {
+                bail!("{} can't be changed", property.canonical_name())
+            }
+            (property, value) => {
+                bail!("Invalid value for {}: {value:?}", property.canonical_name())
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Writes the changes to a video's sidecar as one operation in the
+    /// journal. Marking it watched or unwatched is recorded in its history
+    /// like from the context menu, and setting it watched when it already is
+    /// doesn't count as watching it again.
+    pub fn commit(&self, config: &Config, video: &Path, source: Source) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let name = config
+            .entry_name(video)
+            .context("Video path is not a file")?;
+        let sidecar_path = config
+            .sidecar_path(video)
+            .context("Can't open sidecar for root directory")?;
+        let mut sidecar = Sidecar::new(open_or_create_sidecar(config, video)?)?;
+        let before = sidecar.get(&name).cloned();
+
+        let action = match self.watched {
+            Some(true) if !sidecar.is_watched(&name) => {
+                sidecar.add(&name)?;
+                Some(Action::Watched)
+            }
+            Some(false) if sidecar.is_watched(&name) => {
+                sidecar.remove(&name)?;
+                Some(Action::Unwatched)
+            }
+            _ => None,
+        };
+        if let Some(rating) = self.rating {
+            sidecar.update(&name, |entry| entry.rating = rating)?;
+        }
+
+        let mut operation = Operation::new(format!("properties {}", video.display()));
+        operation.record(&sidecar_path, &name, before.as_ref(), sidecar.get(&name))?;
+        if let Some(action) = action {
+            record(config, &Event::new(source, action, video))?;
+        }
+
+        cache::shared().invalidate(&sidecar_path);
+        Ok(())
+    }
+}
+
+/// Converts a time to a `FILETIME`, in 100ns intervals since 1601.
+pub fn to_filetime(time: DateTime<Utc>) -> u64 {
+    let seconds = time.timestamp() + FILETIME_EPOCH_OFFSET;
+    let intervals = seconds as i128 * 10_000_000 + time.timestamp_subsec_nanos() as i128 / 100;
+    intervals.clamp(0, u64::MAX as i128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{history::read_library, sidecar::read_entry, test_util::library};
+
+    fn changes(property: Property, value: PropertyValue) -> Result<PropertyChanges> {
+        let mut changes = PropertyChanges::default();
+        changes.set(property, value)?;
+        Ok(changes)
+    }
+
+    fn rating(value: PropertyValue) -> Result<Option<Option<u8>>> {
+        Ok(changes(Property::Rating, value)?.rating)
+    }
+
+    #[test]
+    fn ratings_are_out_of_ten() {
+        assert_eq!(rating(PropertyValue::UInt(1)).unwrap(), Some(Some(1)));
+        assert_eq!(rating(PropertyValue::UInt(10)).unwrap(), Some(Some(10)));
+        assert_eq!(rating(PropertyValue::UInt(0)).unwrap(), Some(None));
+        assert_eq!(rating(PropertyValue::Empty).unwrap(), Some(None));
+        assert_eq!(
+            rating(PropertyValue::UInt(11)).unwrap_err().to_string(),
+            "Rating must be out of 10, not 11"
+        );
+        assert_eq!(
+            rating(PropertyValue::Bool(true)).unwrap_err().to_string(),
+            "Invalid value for LastWatched.Rating: Bool(true)"
+        );
+    }
+
+    #[test]
+    fn only_watched_and_rating_can_be_changed() {
+        let time = PropertyValue::Time(DateTime::UNIX_EPOCH);
+        for (property, value) in [
+            (Property::WatchCount, PropertyValue::UInt(2)),
+            (Property::LastWatched, time),
+            (Property::LastWatched, PropertyValue::Empty),
+        ] {
+            let err = changes(property, value).unwrap_err();
+            let expected = format!("{} can't be changed", property.canonical_name());
+            assert_eq!(err.to_string(), expected);
+        }
+
+        let changes = changes(Property::Watched, PropertyValue::Bool(true)).unwrap();
+        assert_eq!(changes.watched, Some(true));
+        assert!(!changes.is_empty());
+    }
+
+    #[test]
+    fn setting_watched_again_is_not_counted() {
+        let dir = library(&["Movie.mkv"]);
+        let video = dir.path().join("Movie.mkv");
+        let config = Config::for_path(&video).unwrap();
+        let watched = changes(Property::Watched, PropertyValue::Bool(true)).unwrap();
+
+        watched.commit(&config, &video, Source::Shell).unwrap();
+        let entry = read_entry(&config, &video).unwrap().unwrap();
+        assert!(entry.watched);
+        assert_eq!(entry.count, 1);
+
+        // Like when the Details pane saves every property, rating included
+        let mut again = watched;
+        again.set(Property::Rating, PropertyValue::UInt(8)).unwrap();
+        again.commit(&config, &video, Source::Shell).unwrap();
+        let after = read_entry(&config, &video).unwrap().unwrap();
+        assert_eq!(after.count, 1);
+        assert_eq!(after.watched_at, entry.watched_at);
+        assert_eq!(after.rating, Some(8));
+
+        let events = read_library(&config, dir.path()).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action, Action::Watched);
+    }
+
+    #[test]
+    fn converts_to_filetime() {
+        assert_eq!(to_filetime(DateTime::UNIX_EPOCH), 116_444_736_000_000_000);
+
+        let time = DateTime::parse_from_rfc3339("2024-08-22T19:30:00Z").unwrap();
+        assert_eq!(to_filetime(time.to_utc()), 133_688_286_000_000_000);
+
+        let time = DateTime::parse_from_rfc3339("2024-08-22T19:30:00.1234567Z").unwrap();
+        assert_eq!(to_filetime(time.to_utc()), 133_688_286_001_234_567);
+
+        // Times before 1601 can't be represented
+        let time = DateTime::parse_from_rfc3339("1600-12-31T23:59:59Z").unwrap();
+        assert_eq!(to_filetime(time.to_utc()), 0);
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
+
+use anyhow::Result;
+
+use super::{join, Key, Registry, Root, Value};
+
+/// A registry kept in memory, for trying out registration off Windows. It
+/// displays like a `.reg` file, which makes for easy snapshots.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryRegistry {
+    roots: BTreeMap<Root, Key>,
+}
+
+impl MemoryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(&self, root: Root, path: &str) -> Option<&Key> {
+        let mut key = self.roots.get(&root)?;
+        for name in components(path) {
+            key = key.subkey(name)?;
+        }
+
+        Some(key)
+    }
+
+    fn key_mut(&mut self, root: Root, path: &str) -> &mut Key {
+        let mut key = self.roots.entry(root).or_default();
+        for name in components(path) {
+            key = key.subkey_mut(name);
+        }
+
+        key
+    }
+}
+
+impl Key {
+    fn subkey(&self, name: &str) -> Option<&Key> {
+        self.subkeys
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, key)| key)
+    }
+
+    /// Gets a subkey, creating it if it doesn't exist.
+    fn subkey_mut(&mut self, name: &str) -> &mut Key {
+        let idx = match self
+            .subkeys
+            .iter()
+            .position(|(x, _)| x.eq_ignore_ascii_case(name))
+        {
+            Some(idx) => idx,
+            None => {
+                self.subkeys.push((name.to_owned(), Key::default()));
+                self.subkeys.sort_by_key(|(x, _)| x.to_ascii_lowercase());
+                self.subkeys.iter().position(|(x, _)| x == name).unwrap()
+            }
+        };
+
+        &mut self.subkeys[idx].1
+    }
+}
+
+impl Registry for MemoryRegistry {
+    fn key_exists(&self, root: Root, path: &str) -> Result<bool> {
+        Ok(self.key(root, path).is_some())
+    }
+
+    fn subkeys(&self, root: Root, path: &str) -> Result<Vec<String>> {
+        Ok(self.key(root, path).map_or(Vec::new(), |key| {
+            key.subkeys.iter().map(|(name, _)| name.clone()).collect()
+        }))
+    }
+
+    fn values(&self, root: Root, path: &str) -> Result<Vec<(String, Value)>> {
+        Ok(self
+            .key(root, path)
+            .map_or(Vec::new(), |key| key.values.clone()))
+    }
+
+    fn create_key(&mut self, root: Root, path: &str) -> Result<()> {
+        self.key_mut(root, path);
+        Ok(())
+    }
+
+    fn set_value(&mut self, root: Root, path: &str, name: &str, value: &Value) -> Result<()> {
+        let key = self.key_mut(root, path);
+        match key
+            .values
+            .iter_mut()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+        {
+            Some((_, old)) => *old = value.clone(),
+            None => {
+                key.values.push((name.to_owned(), value.clone()));
+                key.values.sort_by_key(|(x, _)| x.to_ascii_lowercase());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delete_key(&mut self, root: Root, path: &str) -> Result<()> {
+        let (parent, name) = match path.rsplit_once('\\') {
+            Some((parent, name)) => (parent, name),
+            None => ("", path),
+        };
+
+        if self.key(root, parent).is_some() {
+            let parent = self.key_mut(root, parent);
+            parent
+                .subkeys
+                .retain(|(x, _)| !x.eq_ignore_ascii_case(name));
+        }
+
+        // So registries with the same keys compare equal
+        if self.roots.get(&root) == Some(&Key::default()) {
+            self.roots.remove(&root);
+        }
+
+        Ok(())
+    }
+
+    fn delete_value(&mut self, root: Root, path: &str, name: &str) -> Result<()> {
+        if self.key(root, path).is_some() {
+            let key = self.key_mut(root, path);
+            key.values.retain(|(x, _)| !x.eq_ignore_ascii_case(name));
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for MemoryRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (root, key) in &self.roots {
+            for (name, subkey) in &key.subkeys {
+                write_key(f, &join(root.name(), name), subkey)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_key(f: &mut fmt::Formatter<'_>, path: &str, key: &Key) -> fmt::Result {
+    writeln!(f, "[{path}]")?;
+    for (name, value) in &key.values {
+        match name.as_str() {
+            "" => writeln!(f, "@={value}")?,
+            name => writeln!(f, "\"{name}\"={value}")?,
+        }
+    }
+    writeln!(f)?;
+
+    for (name, subkey) in &key.subkeys {
+        write_key(f, &join(path, name), subkey)?;
+    }
+
+    Ok(())
+}
+
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('\\').filter(|x| !x.is_empty())
+}
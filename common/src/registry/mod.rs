@@ -0,0 +1,698 @@
+use std::fmt::{self, Display};
+
+use anyhow::Result;
+
+use crate::config::Config;
+
+mod memory;
+pub mod shell;
+mod transaction;
+
+pub use memory::MemoryRegistry;
+pub use transaction::Transaction;
+
+/// The Windows registry, or something standing in for it. Paths are relative
+/// to a root key and separated by backslashes, and names are case
+/// insensitive like in the real registry.
+pub trait Registry {
+    fn key_exists(&self, root: Root, path: &str) -> Result<bool>;
+    /// Names of the keys directly under a key, or none if it doesn't exist.
+    fn subkeys(&self, root: Root, path: &str) -> Result<Vec<String>>;
+    /// Values in a key, or none if it doesn't exist. The default value has
+    /// an empty name.
+    fn values(&self, root: Root, path: &str) -> Result<Vec<(String, Value)>>;
+    /// Creates a key along with any missing parents.
+    fn create_key(&mut self, root: Root, path: &str) -> Result<()>;
+    /// Sets a value, creating the key if it doesn't exist.
+    fn set_value(&mut self, root: Root, path: &str, name: &str, value: &Value) -> Result<()>;
+    /// Deletes a key and everything in it, if it exists.
+    fn delete_key(&mut self, root: Root, path: &str) -> Result<()>;
+    /// Deletes a value, if it exists.
+    fn delete_value(&mut self, root: Root, path: &str, name: &str) -> Result<()>;
+
+    fn value(&self, root: Root, path: &str, name: &str) -> Result<Option<Value>> {
+        let values = self.values(root, path)?;
+        Ok(values
+            .into_iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value))
+    }
+
+    /// Reads a key and everything in it.
+    fn export(&self, root: Root, path: &str) -> Result<Option<Key>> {
+        if !self.key_exists(root, path)? {
+            return Ok(None);
+        }
+
+        let mut key = Key {
+            values: self.values(root, path)?,
+            subkeys: Vec::new(),
+        };
+        for name in self.subkeys(root, path)? {
+            if let Some(subkey) = self.export(root, &join(path, &name))? {
+                key.subkeys.push((name, subkey));
+            }
+        }
+
+        Ok(Some(key))
+    }
+
+    /// Writes a key and everything in it, on top of what's already there.
+    fn import(&mut self, root: Root, path: &str, key: &Key) -> Result<()> {
+        self.create_key(root, path)?;
+        for (name, value) in &key.values {
+            self.set_value(root, path, name, value)?;
+        }
+        for (name, subkey) in &key.subkeys {
+            self.import(root, &join(path, name), subkey)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Root {
+    ClassesRoot,
+    LocalMachine,
+}
+
+/// The types of registry value the shell extension uses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Dword(u32),
+}
+
+/// A key and everything in it, as read by [`Registry::export`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Key {
+    pub values: Vec<(String, Value)>,
+    pub subkeys: Vec<(String, Key)>,
+}
+
+/// A key written when registering a handler, which is deleted again when
+/// unregistering it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeySpec {
+    pub root: Root,
+    pub path: String,
+    pub values: Vec<(String, Value)>,
+}
+
+/// Where a handler is registered for each video extension, as a key per
+/// extension in `parent` with the handler's CLSID as the default value of
+/// `subkey` under it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtensionKeys {
+    pub root: Root,
+    pub parent: &'static str,
+    pub subkey: &'static str,
+    /// For handlers that take the place of whatever was registered before,
+    /// the name of a value the previous CLSID is kept in. It's put back when
+    /// unregistering, instead of deleting the key.
+    pub chained: Option<&'static str>,
+}
+
+/// Everything a shell handler writes to the registry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Registration {
+    /// The handler's CLSID, formatted with braces.
+    pub clsid: String,
+    pub keys: Vec<KeySpec>,
+    pub extensions: Option<ExtensionKeys>,
+}
+
+impl Root {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Root::ClassesRoot => "HKEY_CLASSES_ROOT",
+            Root::LocalMachine => "HKEY_LOCAL_MACHINE",
+        }
+    }
+}
+
+impl KeySpec {
+    pub fn new(root: Root, path: impl Into<String>, values: &[(&str, Value)]) -> Self {
+        Self {
+            root,
+            path: path.into(),
+            values: values
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+        }
+    }
+
+    fn write(&self, registry: &mut dyn Registry) -> Result<()> {
+        registry.create_key(self.root, &self.path)?;
+        for (name, value) in &self.values {
+            registry.set_value(self.root, &self.path, name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ExtensionKeys {
+    /// Path of the key an extension's handler is stored in.
+    pub fn path(&self, extension: &str) -> String {
+        match self.subkey {
+            "" => format!(r"{}\.{extension}", self.parent),
+            subkey => format!(r"{}\.{extension}\{subkey}", self.parent),
+        }
+    }
+
+    /// Finds the extensions a handler is registered for, by looking for its
+    /// CLSID in the key of every extension.
+    pub fn registered(&self, registry: &dyn Registry, clsid: &str) -> Result<Vec<String>> {
+        let mut extensions = Vec::new();
+        for name in registry.subkeys(self.root, self.parent)? {
+            let Some(extension) = name.strip_prefix('.') else {
+                continue;
+            };
+
+            let value = registry.value(self.root, &self.path(extension), "")?;
+            if matches!(value, Some(Value::String(x)) if x.eq_ignore_ascii_case(clsid)) {
+                extensions.push(extension.to_ascii_lowercase());
+            }
+        }
+
+        Ok(extensions)
+    }
+
+    /// Gets the CLSID of the handler registered before this one for an
+    /// extension, see [`Self::chained`].
+    pub fn previous(&self, registry: &dyn Registry, extension: &str) -> Result<Option<String>> {
+        let Some(chained) = self.chained else {
+            return Ok(None);
+        };
+
+        Ok(
+            match registry.value(self.root, &self.path(extension), chained)? {
+                Some(Value::String(clsid)) => Some(clsid),
+                _ => None,
+            },
+        )
+    }
+
+    /// Keeps the handler currently registered for an extension, if it isn't
+    /// this one, before it's replaced.
+    fn chain(&self, registry: &mut dyn Registry, extension: &str, clsid: &str) -> Result<()> {
+        let Some(chained) = self.chained else {
+            return Ok(());
+        };
+
+        let path = self.path(extension);
+        match registry.value(self.root, &path, "")? {
+            Some(Value::String(x)) if !x.is_empty() && !x.eq_ignore_ascii_case(clsid) => {
+                registry.set_value(self.root, &path, chained, &Value::String(x))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Unregisters the handler for an extension, putting back the one it
+    /// replaced if there was one. Chained keys can belong to Windows, so
+    /// without a handler to put back only the CLSID is deleted. Keys left
+    /// empty are deleted too, up to the extension's key.
+    fn remove(&self, registry: &mut dyn Registry, extension: &str) -> Result<()> {
+        let path = self.path(extension);
+        match (self.chained, self.previous(registry, extension)?) {
+            (Some(chained), Some(previous)) => {
+                registry.set_value(self.root, &path, "", &Value::String(previous))?;
+                registry.delete_value(self.root, &path, chained)
+            }
+            (Some(chained), None) => {
+                registry.delete_value(self.root, &path, "")?;
+                registry.delete_value(self.root, &path, chained)?;
+                self.prune(registry, &path)
+            }
+            (None, _) => {
+                registry.delete_key(self.root, &path)?;
+                self.prune(registry, &path)
+            }
+        }
+    }
+
+    /// Deletes a key and its parents while they're empty, stopping at
+    /// [`Self::parent`].
+    fn prune(&self, registry: &mut dyn Registry, mut path: &str) -> Result<()> {
+        while path.len() > self.parent.len() {
+            if !registry.values(self.root, path)?.is_empty()
+                || !registry.subkeys(self.root, path)?.is_empty()
+            {
+                break;
+            }
+
+            registry.delete_key(self.root, path)?;
+            path = path.rsplit_once('\\').map_or("", |(parent, _)| parent);
+        }
+
+        Ok(())
+    }
+}
+
+impl Registration {
+    /// Every key the handler is registered under for these extensions.
+    pub fn keys(&self, extensions: &[String]) -> Vec<KeySpec> {
+        let mut keys = self.keys.clone();
+        if let Some(per_extension) = &self.extensions {
+            keys.extend(extensions.iter().map(|extension| {
+                KeySpec::new(
+                    per_extension.root,
+                    per_extension.path(extension),
+                    &[("", Value::String(self.clsid.clone()))],
+                )
+            }));
+        }
+
+        keys
+    }
+
+    /// Registers the handler. Extensions it was registered for before that
+    /// aren't videos anymore are unregistered.
+    pub fn register(&self, registry: &mut dyn Registry, extensions: &[String]) -> Result<()> {
+        if let Some(per_extension) = &self.extensions {
+            for extension in per_extension.registered(registry, &self.clsid)? {
+                if !extensions.contains(&extension) {
+                    per_extension.remove(registry, &extension)?;
+                }
+            }
+            for extension in extensions {
+                per_extension.chain(registry, extension, &self.clsid)?;
+            }
+        }
+
+        for key in self.keys(extensions) {
+            key.write(registry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes everything the handler is registered under.
+    pub fn unregister(&self, registry: &mut dyn Registry) -> Result<()> {
+        if let Some(per_extension) = &self.extensions {
+            for extension in per_extension.registered(registry, &self.clsid)? {
+                per_extension.remove(registry, &extension)?;
+            }
+        }
+
+        // Children before parents, though deleting a parent would get them anyway
+        for key in self.keys.iter().rev() {
+            registry.delete_key(key.root, &key.path)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(x) => write!(f, "\"{}\"", x.replace('\\', r"\\").replace('"', "\\\"")),
+            Value::Dword(x) => write!(f, "dword:{x:08x}"),
+        }
+    }
+}
+
+/// Registers every handler, or none of them. If anything fails, whatever was
+/// already changed is put back how it was.
+pub fn register_all(
+    registry: &mut dyn Registry,
+    registrations: &[Registration],
+    extensions: &[String],
+) -> Result<()> {
+    let mut transaction = Transaction::new(registry);
+    let result = registrations
+        .iter()
+        .try_for_each(|x| x.register(&mut transaction, extensions));
+    transaction.finish(result)
+}
+
+/// Unregisters every handler, or none of them, see [`register_all`].
+pub fn unregister_all(registry: &mut dyn Registry, registrations: &[Registration]) -> Result<()> {
+    let mut transaction = Transaction::new(registry);
+    let result = registrations
+        .iter()
+        .try_for_each(|x| x.unregister(&mut transaction));
+    transaction.finish(result)
+}
+
+/// Extensions of the files handlers are registered for, lowercase and
+/// without the dot.
+pub fn video_extensions(config: &Config) -> Vec<String> {
+    let mut extensions = config
+        .video_extensions
+        .iter()
+        .map(|x| x.trim_start_matches('.').to_ascii_lowercase())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    extensions.sort();
+    extensions.dedup();
+    extensions
+}
+
+fn join(path: &str, name: &str) -> String {
+    match path {
+        "" => name.to_owned(),
+        path => format!(r"{path}\{name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+
+    use super::*;
+
+    const CLSID: &str = "{00000000-0000-0000-0000-000000000001}";
+    const WINDOWS_HANDLER: &str = "{00000000-0000-0000-0000-0000000000ff}";
+
+    /// A registry that fails one write, to check changes are rolled back.
+    struct Failing {
+        registry: MemoryRegistry,
+        writes: usize,
+        fail_at: usize,
+    }
+
+    impl Failing {
+        fn write(&mut self) -> Result<&mut MemoryRegistry> {
+            self.writes += 1;
+            if self.writes == self.fail_at {
+                bail!("Write {} failed", self.writes);
+            }
+
+            Ok(&mut self.registry)
+        }
+    }
+
+    impl Registry for Failing {
+        fn key_exists(&self, root: Root, path: &str) -> Result<bool> {
+            self.registry.key_exists(root, path)
+        }
+
+        fn subkeys(&self, root: Root, path: &str) -> Result<Vec<String>> {
+            self.registry.subkeys(root, path)
+        }
+
+        fn values(&self, root: Root, path: &str) -> Result<Vec<(String, Value)>> {
+            self.registry.values(root, path)
+        }
+
+        fn create_key(&mut self, root: Root, path: &str) -> Result<()> {
+            self.write()?.create_key(root, path)
+        }
+
+        fn set_value(&mut self, root: Root, path: &str, name: &str, value: &Value) -> Result<()> {
+            self.write()?.set_value(root, path, name, value)
+        }
+
+        fn delete_key(&mut self, root: Root, path: &str) -> Result<()> {
+            self.write()?.delete_key(root, path)
+        }
+
+        fn delete_value(&mut self, root: Root, path: &str, name: &str) -> Result<()> {
+            self.write()?.delete_value(root, path, name)
+        }
+    }
+
+    /// Keys Windows already has, which registering has to leave alone.
+    fn system() -> MemoryRegistry {
+        let mut registry = MemoryRegistry::new();
+        let video = [("PerceivedType", Value::String("video".to_owned()))];
+        let keys = [
+            KeySpec::new(Root::ClassesRoot, "CLSID", &[]),
+            KeySpec::new(
+                Root::ClassesRoot,
+                r"Directory\ShellEx\ContextMenuHandlers",
+                &[],
+            ),
+            KeySpec::new(
+                Root::ClassesRoot,
+                r"Directory\Background\ShellEx\ContextMenuHandlers",
+                &[],
+            ),
+            KeySpec::new(Root::ClassesRoot, r"SystemFileAssociations\.mkv", &video),
+            KeySpec::new(Root::ClassesRoot, r"SystemFileAssociations\.mp4", &video),
+            KeySpec::new(
+                Root::LocalMachine,
+                shell::PROPERTY_HANDLERS.path("mkv"),
+                &[("", Value::String(WINDOWS_HANDLER.to_owned()))],
+            ),
+        ];
+        for key in keys {
+            key.write(&mut registry).unwrap();
+        }
+
+        registry
+    }
+
+    fn registrations() -> Vec<Registration> {
+        vec![
+            shell::context_menu("{CONTEXT-MENU}", r"C:\last_watched.dll"),
+            shell::property_store("{PROPERTY-STORE}", r"C:\last_watched.dll"),
+        ]
+    }
+
+    fn extensions(extensions: &[&str]) -> Vec<String> {
+        extensions.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn registered(extensions: &[&str]) -> MemoryRegistry {
+        let mut registry = system();
+        register_all(
+            &mut registry,
+            &registrations(),
+            &self::extensions(extensions),
+        )
+        .unwrap();
+        registry
+    }
+
+    #[test]
+    fn registers_every_handler() {
+        let registry = registered(&["mkv", "mp4"]);
+        let expected = r#"[HKEY_CLASSES_ROOT\CLSID]
+
+[HKEY_CLASSES_ROOT\CLSID\{CONTEXT-MENU}]
+@="last-watched"
+
+[HKEY_CLASSES_ROOT\CLSID\{CONTEXT-MENU}\InProcServer32]
+@="C:\\last_watched.dll"
+"ThreadingModel"="Apartment"
+
+[HKEY_CLASSES_ROOT\CLSID\{PROPERTY-STORE}]
+@="last-watched"
+
+[HKEY_CLASSES_ROOT\CLSID\{PROPERTY-STORE}\InProcServer32]
+@="C:\\last_watched.dll"
+"DisableProcessIsolation"=dword:00000001
+"ThreadingModel"="Apartment"
+
+[HKEY_CLASSES_ROOT\Directory]
+
+[HKEY_CLASSES_ROOT\Directory\Background]
+
+[HKEY_CLASSES_ROOT\Directory\Background\ShellEx]
+
+[HKEY_CLASSES_ROOT\Directory\Background\ShellEx\ContextMenuHandlers]
+
+[HKEY_CLASSES_ROOT\Directory\Background\ShellEx\ContextMenuHandlers\LastWatched]
+@="{CONTEXT-MENU}"
+
+[HKEY_CLASSES_ROOT\Directory\ShellEx]
+
+[HKEY_CLASSES_ROOT\Directory\ShellEx\ContextMenuHandlers]
+
+[HKEY_CLASSES_ROOT\Directory\ShellEx\ContextMenuHandlers\LastWatched]
+@="{CONTEXT-MENU}"
+
+[HKEY_CLASSES_ROOT\SystemFileAssociations]
+
+[HKEY_CLASSES_ROOT\SystemFileAssociations\.mkv]
+"PerceivedType"="video"
+
+[HKEY_CLASSES_ROOT\SystemFileAssociations\.mkv\ShellEx]
+
+[HKEY_CLASSES_ROOT\SystemFileAssociations\.mkv\ShellEx\ContextMenuHandlers]
+
+[HKEY_CLASSES_ROOT\SystemFileAssociations\.mkv\ShellEx\ContextMenuHandlers\LastWatched]
+@="{CONTEXT-MENU}"
+
+[HKEY_CLASSES_ROOT\SystemFileAssociations\.mp4]
+"PerceivedType"="video"
+
+[HKEY_CLASSES_ROOT\SystemFileAssociations\.mp4\ShellEx]
+
+[HKEY_CLASSES_ROOT\SystemFileAssociations\.mp4\ShellEx\ContextMenuHandlers]
+
+[HKEY_CLASSES_ROOT\SystemFileAssociations\.mp4\ShellEx\ContextMenuHandlers\LastWatched]
+@="{CONTEXT-MENU}"
+
+[HKEY_LOCAL_MACHINE\SOFTWARE]
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft]
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows]
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion]
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\PropertySystem]
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\PropertySystem\PropertyHandlers]
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\PropertySystem\PropertyHandlers\.mkv]
+@="{PROPERTY-STORE}"
+"LastWatchedChained"="{00000000-0000-0000-0000-0000000000ff}"
+
+[HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Windows\CurrentVersion\PropertySystem\PropertyHandlers\.mp4]
+@="{PROPERTY-STORE}"
+
+"#;
+        assert_eq!(registry.to_string(), expected);
+
+        // Registering again changes nothing
+        let mut again = registry.clone();
+        register_all(&mut again, &registrations(), &extensions(&["mkv", "mp4"])).unwrap();
+        assert_eq!(again, registry);
+    }
+
+    #[test]
+    fn unregisters_every_handler() {
+        let mut registry = registered(&["mkv", "mp4"]);
+        unregister_all(&mut registry, &registrations()).unwrap();
+        assert_eq!(registry.to_string(), system().to_string());
+        assert_eq!(registry, system());
+    }
+
+    #[test]
+    fn reregistering_unregisters_removed_extensions() {
+        let mut registry = registered(&["mkv", "mp4"]);
+        register_all(&mut registry, &registrations(), &extensions(&["mp4"])).unwrap();
+        assert_eq!(registry, registered(&["mp4"]));
+
+        let handlers = &shell::PROPERTY_HANDLERS;
+        assert_eq!(
+            handlers.registered(&registry, "{PROPERTY-STORE}").unwrap(),
+            ["mp4"]
+        );
+        assert_eq!(
+            registry
+                .value(Root::LocalMachine, &handlers.path("mkv"), "")
+                .unwrap(),
+            Some(Value::String(WINDOWS_HANDLER.to_owned()))
+        );
+    }
+
+    #[test]
+    fn restores_chained_property_handler() {
+        let handlers = &shell::PROPERTY_HANDLERS;
+        let mut registry = registered(&["mkv", "mp4"]);
+        assert_eq!(
+            handlers.previous(&registry, "mkv").unwrap().as_deref(),
+            Some(WINDOWS_HANDLER)
+        );
+        assert_eq!(handlers.previous(&registry, "mp4").unwrap(), None);
+
+        // Windows registering its handler again is chained in its place
+        let path = handlers.path("mkv");
+        let other = "{00000000-0000-0000-0000-0000000000fe}";
+        let value = Value::String(other.to_owned());
+        registry
+            .set_value(Root::LocalMachine, &path, "", &value)
+            .unwrap();
+        register_all(&mut registry, &registrations(), &extensions(&["mkv"])).unwrap();
+        assert_eq!(
+            handlers.previous(&registry, "mkv").unwrap().as_deref(),
+            Some(other)
+        );
+
+        unregister_all(&mut registry, &registrations()).unwrap();
+        let key = registry.export(Root::LocalMachine, &path).unwrap().unwrap();
+        assert_eq!(key.values, [(String::new(), value)]);
+    }
+
+    #[test]
+    fn rolls_back_when_a_write_fails() {
+        let mut failures = 0;
+        for fail_at in 1.. {
+            let mut registry = Failing {
+                registry: system(),
+                writes: 0,
+                fail_at,
+            };
+            let result = register_all(&mut registry, &registrations(), &extensions(&["mkv"]));
+            if result.is_ok() {
+                break;
+            }
+
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                format!("Write {fail_at} failed")
+            );
+            assert_eq!(registry.registry, system(), "failed at write {fail_at}");
+            failures += 1;
+        }
+        assert!(failures > 5);
+
+        let registered = registered(&["mkv", "mp4"]);
+        for fail_at in 1.. {
+            let mut registry = Failing {
+                registry: registered.clone(),
+                writes: 0,
+                fail_at,
+            };
+            if unregister_all(&mut registry, &registrations()).is_ok() {
+                assert_eq!(registry.registry, system());
+                break;
+            }
+
+            assert_eq!(registry.registry, registered, "failed at write {fail_at}");
+        }
+    }
+
+    fn mkv_handler(registry: &MemoryRegistry) -> Option<Key> {
+        registry
+            .export(Root::LocalMachine, &shell::PROPERTY_HANDLERS.path("mkv"))
+            .unwrap()
+    }
+
+    #[test]
+    fn unregistering_never_deletes_property_handler_keys_it_did_not_create() {
+        // As left by a version that replaced the handler without keeping it
+        let mut registry = MemoryRegistry::new();
+        let path = shell::PROPERTY_HANDLERS.path("mkv");
+        let value = [("", Value::String(CLSID.to_owned()))];
+        KeySpec::new(Root::LocalMachine, &path, &value)
+            .write(&mut registry)
+            .unwrap();
+        KeySpec::new(Root::LocalMachine, format!(r"{path}\Extra"), &[])
+            .write(&mut registry)
+            .unwrap();
+
+        let registration = shell::property_store(CLSID, "last_watched.dll");
+        unregister_all(&mut registry, &[registration]).unwrap();
+
+        let key = mkv_handler(&registry).unwrap();
+        assert!(key.values.is_empty());
+        assert_eq!(key.subkeys.len(), 1);
+    }
+
+    #[test]
+    fn unregistering_deletes_property_handler_keys_left_empty() {
+        let mut registry = MemoryRegistry::new();
+        let registration = shell::property_store(CLSID, "last_watched.dll");
+        register_all(
+            &mut registry,
+            std::slice::from_ref(&registration),
+            &["mkv".to_owned()],
+        )
+        .unwrap();
+        assert!(mkv_handler(&registry).is_some());
+
+        unregister_all(&mut registry, &[registration]).unwrap();
+        assert_eq!(mkv_handler(&registry), None);
+    }
+}
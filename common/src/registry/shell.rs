@@ -0,0 +1,91 @@
+//! The keys each of the shell extension's handlers is registered under.
+
+use super::{ExtensionKeys, KeySpec, Registration, Root, Value};
+
+/// Each overlay has its own identifier, named `name`. Explorer only shows the
+/// first 15 identifiers by name, across every program that installs them.
+pub fn icon_overlay(name: &str, clsid: &str, module_path: &str) -> Registration {
+    let mut keys = class(clsid, module_path, &[]);
+    keys.push(KeySpec::new(
+        Root::LocalMachine,
+        format!(
+            r"Software\Microsoft\Windows\CurrentVersion\Explorer\ShellIconOverlayIdentifiers\{name}"
+        ),
+        &[("", Value::String(clsid.to_owned()))],
+    ));
+
+    Registration {
+        clsid: clsid.to_owned(),
+        keys,
+        extensions: None,
+    }
+}
+
+/// The menu is shown for videos, folders and the background of folders.
+pub fn context_menu(clsid: &str, module_path: &str) -> Registration {
+    let mut keys = class(clsid, module_path, &[]);
+    for parent in [r"Directory", r"Directory\Background"] {
+        keys.push(KeySpec::new(
+            Root::ClassesRoot,
+            format!(r"{parent}\ShellEx\ContextMenuHandlers\LastWatched"),
+            &[("", Value::String(clsid.to_owned()))],
+        ));
+    }
+
+    Registration {
+        clsid: clsid.to_owned(),
+        keys,
+        extensions: Some(ExtensionKeys {
+            root: Root::ClassesRoot,
+            parent: "SystemFileAssociations",
+            subkey: r"ShellEx\ContextMenuHandlers\LastWatched",
+            chained: None,
+        }),
+    }
+}
+
+/// There's only one property handler per extension, so the one it replaces is
+/// kept in a value next to it. The handler passes on everything it doesn't
+/// handle itself to that one.
+pub const PROPERTY_HANDLERS: ExtensionKeys = ExtensionKeys {
+    root: Root::LocalMachine,
+    parent: r"SOFTWARE\Microsoft\Windows\CurrentVersion\PropertySystem\PropertyHandlers",
+    subkey: "",
+    chained: Some("LastWatchedChained"),
+};
+
+/// Property handlers are looked up by extension and run out of process
+/// unless told otherwise.
+pub fn property_store(clsid: &str, module_path: &str) -> Registration {
+    Registration {
+        clsid: clsid.to_owned(),
+        keys: class(
+            clsid,
+            module_path,
+            &[("DisableProcessIsolation", Value::Dword(1))],
+        ),
+        extensions: Some(PROPERTY_HANDLERS),
+    }
+}
+
+/// Registers a COM class implemented by the extension's dll.
+fn class(clsid: &str, module_path: &str, extra: &[(&str, Value)]) -> Vec<KeySpec> {
+    let mut inproc = vec![
+        ("", Value::String(module_path.to_owned())),
+        ("ThreadingModel", Value::String("Apartment".to_owned())),
+    ];
+    inproc.extend_from_slice(extra);
+
+    vec![
+        KeySpec::new(
+            Root::ClassesRoot,
+            format!(r"CLSID\{clsid}"),
+            &[("", Value::String("last-watched".to_owned()))],
+        ),
+        KeySpec::new(
+            Root::ClassesRoot,
+            format!(r"CLSID\{clsid}\InProcServer32"),
+            &inproc,
+        ),
+    ]
+}
@@ -0,0 +1,109 @@
+use anyhow::{Context, Result};
+
+use super::{Key, Registry, Root, Value};
+
+/// Changes to a registry that can be rolled back. Before a key is first
+/// changed, everything in it is saved, or for new keys, the first of its
+/// parents that had to be created.
+pub struct Transaction<'a> {
+    registry: &'a mut dyn Registry,
+    saved: Vec<(Root, String, Option<Key>)>,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn new(registry: &'a mut dyn Registry) -> Self {
+        Self {
+            registry,
+            saved: Vec::new(),
+        }
+    }
+
+    /// Keeps the changes if everything worked, or rolls them back if not.
+    pub fn finish(self, result: Result<()>) -> Result<()> {
+        let Err(err) = result else {
+            return Ok(());
+        };
+
+        self.rollback()
+            .with_context(|| format!("Failed to roll back after: {err:#}"))?;
+        Err(err)
+    }
+
+    /// Puts every key changed back how it was, newest change first.
+    pub fn rollback(self) -> Result<()> {
+        for (root, path, key) in self.saved.into_iter().rev() {
+            self.registry.delete_key(root, &path)?;
+            if let Some(key) = key {
+                self.registry.import(root, &path, &key)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Saves a key before it's changed, unless it or one of its parents
+    /// already has been.
+    fn save(&mut self, root: Root, path: &str) -> Result<()> {
+        let covered = self
+            .saved
+            .iter()
+            .any(|(saved_root, saved, _)| *saved_root == root && is_within(path, saved));
+        if covered {
+            return Ok(());
+        }
+
+        // Deleting the first missing parent deletes everything created under it
+        let mut top = path;
+        while let Some((parent, _)) = top.rsplit_once('\\') {
+            if self.registry.key_exists(root, parent)? {
+                break;
+            }
+            top = parent;
+        }
+
+        let key = self.registry.export(root, top)?;
+        self.saved.push((root, top.to_owned(), key));
+        Ok(())
+    }
+}
+
+impl Registry for Transaction<'_> {
+    fn key_exists(&self, root: Root, path: &str) -> Result<bool> {
+        self.registry.key_exists(root, path)
+    }
+
+    fn subkeys(&self, root: Root, path: &str) -> Result<Vec<String>> {
+        self.registry.subkeys(root, path)
+    }
+
+    fn values(&self, root: Root, path: &str) -> Result<Vec<(String, Value)>> {
+        self.registry.values(root, path)
+    }
+
+    fn create_key(&mut self, root: Root, path: &str) -> Result<()> {
+        self.save(root, path)?;
+        self.registry.create_key(root, path)
+    }
+
+    fn set_value(&mut self, root: Root, path: &str, name: &str, value: &Value) -> Result<()> {
+        self.save(root, path)?;
+        self.registry.set_value(root, path, name, value)
+    }
+
+    fn delete_key(&mut self, root: Root, path: &str) -> Result<()> {
+        self.save(root, path)?;
+        self.registry.delete_key(root, path)
+    }
+
+    fn delete_value(&mut self, root: Root, path: &str, name: &str) -> Result<()> {
+        self.save(root, path)?;
+        self.registry.delete_value(root, path, name)
+    }
+}
+
+/// Checks if a path is a key or somewhere under it.
+fn is_within(path: &str, key: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    let key = key.to_ascii_lowercase();
+    path == key || path.starts_with(&format!(r"{key}\"))
+}
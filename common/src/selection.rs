@@ -0,0 +1,399 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    batch, cache,
+    config::Config,
+    history::{record, Action, Event, Source},
+    journal::Operation,
+    sidecar::{open_or_create_sidecar, read_sidecar, Sidecar},
+};
+
+/// Something that can be done to a selection of videos, like from the
+/// context menu.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionAction {
+    MarkWatched,
+    /// Mark the selected episode and every one before it as watched.
+    MarkWatchedUpTo,
+    MarkUnwatched,
+}
+
+/// Selected videos, along with whether each one is watched.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Selection {
+    /// Videos in the selected files, and in the selected directories once
+    /// they are expanded, see [`batch::expand`].
+    pub videos: Vec<(PathBuf, bool)>,
+    /// Videos up to and including the selected one, if a single video file
+    /// is selected, see [`batch::up_to`]. Like directories, they aren't
+    /// searched for until "Mark all up to here" is applied.
+    pub up_to: Vec<(PathBuf, bool)>,
+    /// Selected directories, which aren't searched for videos until an
+    /// action is applied, see [`Selection::expand`].
+    pub folders: Vec<PathBuf>,
+}
+
+impl Selection {
+    /// Looks up whether the selected videos are watched. Directories and
+    /// earlier episodes are only searched for once an action needs them, as
+    /// searching a whole library is too slow to do before showing a menu.
+    pub fn read(paths: &[PathBuf]) -> Result<Self> {
+        let (folders, files): (Vec<_>, Vec<_>) = paths.iter().cloned().partition(|x| x.is_dir());
+        let videos = Sidecars::default().watched(batch::expand(&files)?)?;
+
+        Ok(Self {
+            videos,
+            up_to: Vec::new(),
+            folders,
+        })
+    }
+
+    /// Finds the videos in the selected directories, along with the selected
+    /// files, and looks up whether they are watched.
+    pub fn expand(&self) -> Result<Self> {
+        let mut paths = self
+            .videos
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        paths.extend(self.folders.iter().cloned());
+
+        Ok(Self {
+            videos: Sidecars::default().watched(batch::expand(&paths)?)?,
+            up_to: Vec::new(),
+            folders: Vec::new(),
+        })
+    }
+
+    /// Finds the videos up to the selected one and looks up whether they are
+    /// watched, see [`batch::up_to`].
+    fn expand_up_to(&self) -> Result<Self> {
+        let ([(video, _)], []) = (self.videos.as_slice(), self.folders.as_slice()) else {
+            bail!("Mark all up to here needs a single video");
+        };
+
+        Ok(Self {
+            videos: self.videos.clone(),
+            up_to: Sidecars::default().watched(batch::up_to(video)?)?,
+            folders: Vec::new(),
+        })
+    }
+
+    /// Actions that would change something, so marking as watched is only
+    /// offered if some of the videos aren't watched and the other way round.
+    /// Mixed selections get both, as do selections with directories since
+    /// their videos haven't been looked up. Marking up to here is offered for
+    /// any single video, as the ones before it haven't been looked up either.
+    pub fn actions(&self) -> Vec<SelectionAction> {
+        let folders = !self.folders.is_empty();
+        let mut actions = Vec::new();
+        if folders || self.videos.iter().any(|(_, watched)| !watched) {
+            actions.push(SelectionAction::MarkWatched);
+        }
+
+        if !folders && self.videos.len() == 1 {
+            actions.push(SelectionAction::MarkWatchedUpTo);
+        }
+
+        if folders || self.videos.iter().any(|(_, watched)| *watched) {
+            actions.push(SelectionAction::MarkUnwatched);
+        }
+
+        actions
+    }
+
+    /// Videos that an action would change, leaving out the ones in
+    /// directories and before the selected video that haven't been searched
+    /// for yet.
+    pub fn targets(&self, action: SelectionAction) -> Vec<&Path> {
+        let (videos, watched) = match action {
+            SelectionAction::MarkWatched => (&self.videos, false),
+            SelectionAction::MarkWatchedUpTo => (&self.up_to, false),
+            SelectionAction::MarkUnwatched => (&self.videos, true),
+        };
+
+        videos
+            .iter()
+            .filter(|(_, x)| *x == watched)
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+
+    /// Applies an action to every video it changes as one operation in the
+    /// journal, so it can be undone. Returns the videos that were changed.
+    pub fn apply(&self, action: SelectionAction, source: Source) -> Result<Vec<PathBuf>> {
+        if !self.folders.is_empty() {
+            return self.expand()?.apply(action, source);
+        }
+        if action == SelectionAction::MarkWatchedUpTo && self.up_to.is_empty() {
+            let expanded = self.expand_up_to()?;
+            if expanded.up_to.is_empty() {
+                return Ok(Vec::new());
+            }
+            return expanded.apply(action, source);
+        }
+
+        let targets = self
+            .targets(action)
+            .into_iter()
+            .map(Path::to_owned)
+            .collect::<Vec<_>>();
+        let description = match action {
+            SelectionAction::MarkWatched => "watched",
+            SelectionAction::MarkWatchedUpTo => "watched up to",
+            SelectionAction::MarkUnwatched => "unwatched",
+        };
+
+        // Up to here is described by the selected video rather than everything it marks
+        let described: Vec<&Path> = match action {
+            SelectionAction::MarkWatchedUpTo => {
+                self.videos.iter().map(|(path, _)| path.as_path()).collect()
+            }
+            _ => targets.iter().map(PathBuf::as_path).collect(),
+        };
+        let mut operation = Operation::new(match described.as_slice() {
+            [video] => format!("{description} {}", video.display()),
+            videos => format!("{description} {} videos", videos.len()),
+        });
+
+        for video in &targets {
+            let config = Config::for_path(video)?;
+            let name = config
+                .entry_name(video)
+                .context("Selected path is not a file")?;
+            let sidecar_path = config
+                .sidecar_path(video)
+                .context("Can't open sidecar for root directory")?;
+            let mut sidecar = Sidecar::new(open_or_create_sidecar(&config, video)?)?;
+
+            let before = sidecar.get(&name).cloned();
+            let event = match action {
+                SelectionAction::MarkWatched | SelectionAction::MarkWatchedUpTo => {
+                    sidecar.add(&name)?;
+                    Action::Watched
+                }
+                SelectionAction::MarkUnwatched => {
+                    sidecar.remove(&name)?;
+                    Action::Unwatched
+                }
+            };
+
+            operation.record(&sidecar_path, &name, before.as_ref(), sidecar.get(&name))?;
+            record(&config, &Event::new(source, event, video))?;
+
+            // Don't wait for the cache to notice, as the change should show right away
+            cache::shared().invalidate(&sidecar_path);
+        }
+
+        Ok(targets)
+    }
+}
+
+/// Configs and sidecar entries already read while looking up a selection,
+/// since the videos are mostly in the same few directories.
+#[derive(Default)]
+struct Sidecars {
+    configs: HashMap<PathBuf, Config>,
+    entries: HashMap<PathBuf, Vec<String>>,
+}
+
+impl Sidecars {
+    fn watched(&mut self, videos: Vec<PathBuf>) -> Result<Vec<(PathBuf, bool)>> {
+        let mut watched = Vec::new();
+        for video in videos {
+            let dir = video.parent().unwrap_or(&video).to_owned();
+            if !self.configs.contains_key(&dir) {
+                self.configs.insert(dir.clone(), Config::for_path(&video)?);
+            }
+
+            let config = &self.configs[&dir];
+            let (Some(sidecar), Some(name)) =
+                (config.sidecar_path(&video), config.entry_name(&video))
+            else {
+                continue;
+            };
+
+            if !self.entries.contains_key(&sidecar) {
+                let names = read_sidecar(&sidecar)?
+                    .into_iter()
+                    .filter(|x| x.watched)
+                    .map(|x| x.name)
+                    .collect();
+                self.entries.insert(sidecar.clone(), names);
+            }
+
+            let is_watched = self.entries[&sidecar].contains(&name);
+            watched.push((video, is_watched));
+        }
+
+        Ok(watched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{journal::read_journal, test_util::library};
+
+    const SHOW: &[&str] = &[
+        "Show/Show.S01E01.mkv",
+        "Show/Show.S01E02.mkv",
+        "Show/Show.S01E03.mkv",
+        "Show/notes.txt",
+    ];
+
+    fn mark(dir: &Path, names: &[&str]) {
+        let lines = names.iter().map(|x| format!("{x}\twatched\n"));
+        fs::write(dir.join(".watched"), lines.collect::<String>()).unwrap();
+    }
+
+    fn names(paths: Vec<&Path>) -> Vec<String> {
+        paths
+            .iter()
+            .map(|x| x.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn unwatched_selection_can_only_be_marked() {
+        let dir = library(SHOW);
+        let show = dir.path().join("Show");
+        let selection =
+            Selection::read(&[show.join("Show.S01E02.mkv"), show.join("Show.S01E01.mkv")]).unwrap();
+
+        assert_eq!(selection.actions(), [SelectionAction::MarkWatched]);
+        assert_eq!(
+            names(selection.targets(SelectionAction::MarkWatched)),
+            ["Show.S01E01.mkv", "Show.S01E02.mkv"]
+        );
+        assert!(selection.targets(SelectionAction::MarkUnwatched).is_empty());
+    }
+
+    #[test]
+    fn watched_selection_can_only_be_unmarked() {
+        let dir = library(SHOW);
+        let show = dir.path().join("Show");
+        mark(&show, &["Show.S01E01.mkv", "Show.S01E02.mkv"]);
+        let files = [show.join("Show.S01E01.mkv"), show.join("Show.S01E02.mkv")];
+        let selection = Selection::read(&files).unwrap();
+
+        assert_eq!(selection.actions(), [SelectionAction::MarkUnwatched]);
+        assert_eq!(
+            names(selection.targets(SelectionAction::MarkUnwatched)),
+            ["Show.S01E01.mkv", "Show.S01E02.mkv"]
+        );
+    }
+
+    #[test]
+    fn folders_are_not_searched_until_expanded() {
+        let dir = library(SHOW);
+        let show = dir.path().join("Show");
+        mark(
+            &show,
+            &["Show.S01E01.mkv", "Show.S01E02.mkv", "Show.S01E03.mkv"],
+        );
+        let selection = Selection::read(&[show.clone(), show.join("Show.S01E01.mkv")]).unwrap();
+
+        assert_eq!(selection.folders, [show]);
+        assert_eq!(
+            selection.actions(),
+            [SelectionAction::MarkWatched, SelectionAction::MarkUnwatched]
+        );
+        assert!(selection.targets(SelectionAction::MarkWatched).is_empty());
+        assert_eq!(
+            names(selection.targets(SelectionAction::MarkUnwatched)),
+            ["Show.S01E01.mkv"]
+        );
+
+        let selection = selection.expand().unwrap();
+        assert!(selection.folders.is_empty());
+        assert_eq!(selection.actions(), [SelectionAction::MarkUnwatched]);
+        assert_eq!(selection.videos.len(), 3);
+    }
+
+    #[test]
+    fn mixed_folder_selection_gets_both() {
+        let dir = library(SHOW);
+        let show = dir.path().join("Show");
+        mark(&show, &["Show.S01E02.mkv"]);
+        let selection = Selection::read(&[show]).unwrap().expand().unwrap();
+
+        assert_eq!(
+            selection.actions(),
+            [SelectionAction::MarkWatched, SelectionAction::MarkUnwatched]
+        );
+        assert_eq!(
+            names(selection.targets(SelectionAction::MarkWatched)),
+            ["Show.S01E01.mkv", "Show.S01E03.mkv"]
+        );
+        assert_eq!(
+            names(selection.targets(SelectionAction::MarkUnwatched)),
+            ["Show.S01E02.mkv"]
+        );
+    }
+
+    #[test]
+    fn finds_earlier_episodes_when_marking_up_to() {
+        let dir = library(SHOW);
+        let show = dir.path().join("Show");
+        mark(&show, &["Show.S01E02.mkv"]);
+        let selection = Selection::read(&[show.join("Show.S01E03.mkv")]).unwrap();
+
+        // Offered without searching for the earlier episodes
+        assert_eq!(
+            selection.actions(),
+            [
+                SelectionAction::MarkWatched,
+                SelectionAction::MarkWatchedUpTo
+            ]
+        );
+        assert!(selection.up_to.is_empty());
+
+        let changed = selection
+            .apply(SelectionAction::MarkWatchedUpTo, Source::Shell)
+            .unwrap();
+        assert_eq!(
+            names(changed.iter().map(PathBuf::as_path).collect()),
+            ["Show.S01E01.mkv", "Show.S01E03.mkv"]
+        );
+
+        let operation = read_journal().unwrap().pop().unwrap();
+        assert_eq!(
+            operation.description,
+            format!("watched up to {}", show.join("Show.S01E03.mkv").display())
+        );
+
+        // Only for single videos
+        let selection = Selection::read(&[show.join("Show.S01E01.mkv"), show.clone()]).unwrap();
+        assert!(!selection
+            .actions()
+            .contains(&SelectionAction::MarkWatchedUpTo));
+    }
+
+    #[test]
+    fn applies_action_as_one_operation() {
+        let dir = library(SHOW);
+        let show = dir.path().join("Show");
+        mark(&show, &["Show.S01E02.mkv"]);
+        let selection = Selection::read(std::slice::from_ref(&show)).unwrap();
+
+        let changed = selection
+            .apply(SelectionAction::MarkWatched, Source::Shell)
+            .unwrap();
+        assert_eq!(changed.len(), 2);
+
+        let watched = read_sidecar(&show.join(".watched")).unwrap();
+        assert_eq!(watched.iter().filter(|x| x.watched).count(), 3);
+
+        let operation = read_journal().unwrap().pop().unwrap();
+        assert_eq!(operation.description, "watched 2 videos");
+        assert_eq!(operation.changes.len(), 2);
+    }
+}
@@ -1,25 +1,62 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufRead, BufWriter, Read, Seek, SeekFrom, Write},
+    fmt::{self, Display},
+    fs::{self, File, OpenOptions},
+    io::ErrorKind,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
 use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
 
-use crate::winapi::ensure_hidden;
+use crate::config::{Config, Storage};
+
+#[cfg(windows)]
+pub(crate) use crate::winapi::ensure_hidden;
 
 pub struct Sidecar {
     file: File,
-    lines: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+/// A video in a sidecar file.
+///
+/// Each entry is stored on its own line as the file name, optionally followed
+/// by tab separated `key=value` fields. Lines with only a file name are
+/// written by older versions and are treated as watched at an unknown time,
+/// and fields added by newer versions are kept as they are.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub watched: bool,
+    /// When the video was last watched.
+    pub watched_at: Option<DateTime<Utc>>,
+    /// How many times the video has been watched, zero if it isn't watched.
+    pub count: u32,
+    pub progress: Option<Progress>,
+    /// Rating out of 10.
+    pub rating: Option<u8>,
+    pub favourite: bool,
+    /// A short note, which can't contain tabs or newlines.
+    pub note: Option<String>,
+    /// Fields this version doesn't know, written back after the others.
+    pub unknown: Vec<String>,
+}
+
+/// How far into an unfinished video playback was stopped, in seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub position: u64,
+    pub duration: u64,
 }
 
 impl Sidecar {
     pub fn new(mut file: File) -> Result<Self> {
-        let mut data = Vec::new();
-        file.read_to_end(&mut data)?;
+        let mut data = String::new();
+        file.read_to_string(&mut data)?;
 
-        let lines = data.lines().map(|x| x.unwrap()).collect();
-        Ok(Self { lines, file })
+        let entries = parse_entries(&data);
+        Ok(Self { entries, file })
     }
 
     pub fn rewrite(&mut self) -> Result<()> {
@@ -27,38 +64,331 @@ impl Sidecar {
         self.file.seek(SeekFrom::Start(0))?;
 
         let mut writer = BufWriter::new(&self.file);
-        for line in &self.lines {
-            writer.write_all(line.as_bytes())?;
-            writer.write_all(b"\n")?;
+        for entry in &self.entries {
+            writeln!(writer, "{entry}")?;
         }
 
         writer.flush()?;
         Ok(())
     }
 
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn get(&self, file: &str) -> Option<&Entry> {
+        self.entries.iter().find(|x| x.name == file)
+    }
+
+    pub fn is_watched(&self, file: &str) -> bool {
+        self.get(file).is_some_and(|x| x.watched)
+    }
+
+    /// Marks a file as watched now.
     pub fn add(&mut self, file: &str) -> Result<()> {
-        if self.lines.contains(&file.to_string()) {
-            return Ok(());
+        self.add_at(file, Utc::now())
+    }
+
+    /// Marks a file as watched at a specific time. Files that are already
+    /// watched have their count incremented and keep the latest time, unless
+    /// they were already marked at exactly this time.
+    pub fn add_at(&mut self, file: &str, time: DateTime<Utc>) -> Result<()> {
+        if let Some(entry) = self.entries.iter_mut().find(|x| x.name == file) {
+            // Importing the same history twice shouldn't count as rewatching
+            if entry.watched && entry.watched_at == Some(time) {
+                return Ok(());
+            }
+
+            entry.watched = true;
+            entry.watched_at = entry.watched_at.max(Some(time));
+            entry.count += 1;
+            entry.progress = None;
+            return self.rewrite();
         }
 
-        self.lines.push(file.to_string());
+        let entry = Entry {
+            watched: true,
+            watched_at: Some(time),
+            count: 1,
+            ..Entry::new(file)
+        };
 
         self.file.seek(SeekFrom::End(0))?;
-        self.file.write_all(file.as_bytes())?;
-        self.file.write_all(b"\n")?;
+        writeln!(self.file, "{entry}")?;
+        self.entries.push(entry);
         Ok(())
     }
 
+    /// Sets how many times a watched file has been watched, marking it as
+    /// watched now if it wasn't.
+    pub fn set_count(&mut self, file: &str, count: u32) -> Result<()> {
+        match self.entries.iter_mut().find(|x| x.name == file) {
+            Some(entry) => {
+                entry.watched_at = entry.watched_at.or(Some(Utc::now()));
+                entry.watched = true;
+                entry.count = count;
+                entry.progress = None;
+            }
+            None => self.entries.push(Entry {
+                watched: true,
+                watched_at: Some(Utc::now()),
+                count,
+                ..Entry::new(file)
+            }),
+        }
+
+        self.rewrite()
+    }
+
+    /// Saves where playback of an unfinished file was stopped.
+    pub fn set_progress(&mut self, file: &str, progress: Progress) -> Result<()> {
+        match self.entries.iter_mut().find(|x| x.name == file) {
+            Some(entry) => entry.progress = Some(progress),
+            None => self.entries.push(Entry {
+                progress: Some(progress),
+                ..Entry::new(file)
+            }),
+        }
+
+        self.rewrite()
+    }
+
+    /// Changes an entry, creating it if it doesn't exist yet. Entries left
+    /// with nothing in them are removed.
+    pub fn update(&mut self, file: &str, change: impl FnOnce(&mut Entry)) -> Result<()> {
+        let idx = match self.entries.iter().position(|x| x.name == file) {
+            Some(idx) => idx,
+            None => {
+                self.entries.push(Entry::new(file));
+                self.entries.len() - 1
+            }
+        };
+
+        change(&mut self.entries[idx]);
+        if self.entries[idx].is_empty() {
+            self.entries.remove(idx);
+        }
+
+        self.rewrite()
+    }
+
+    /// Puts an entry back to a previous state, removing it if it didn't exist.
+    pub fn restore(&mut self, file: &str, entry: Option<Entry>) -> Result<()> {
+        let idx = self.entries.iter().position(|x| x.name == file);
+        match (idx, entry) {
+            (Some(idx), Some(entry)) => self.entries[idx] = entry,
+            (Some(idx), None) => drop(self.entries.remove(idx)),
+            (None, Some(entry)) => self.entries.push(entry),
+            (None, None) => return Ok(()),
+        }
+
+        self.rewrite()
+    }
+
+    /// Marks a file as not watched, forgetting its progress. The entry is
+    /// only removed if it doesn't have a rating, note or favourite to keep.
     pub fn remove(&mut self, file: &str) -> Result<()> {
-        self.lines.retain(|x| x != file);
-        self.rewrite()?;
+        self.update(file, |entry| {
+            entry.watched = false;
+            entry.watched_at = None;
+            entry.count = 0;
+            entry.progress = None;
+        })
+    }
+}
+
+impl Entry {
+    /// Creates an entry for a file that isn't watched and has no other data.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            watched: false,
+            watched_at: None,
+            count: 0,
+            progress: None,
+            rating: None,
+            favourite: false,
+            note: None,
+            unknown: Vec::new(),
+        }
+    }
+
+    /// Checks if the entry has nothing worth keeping in the sidecar.
+    pub fn is_empty(&self) -> bool {
+        !self.watched
+            && self.progress.is_none()
+            && self.rating.is_none()
+            && !self.favourite
+            && self.note.is_none()
+            && self.unknown.is_empty()
+    }
+
+    pub fn parse(line: &str) -> Self {
+        let mut fields = line.split('\t').peekable();
+        let name = fields.next().unwrap_or_default();
+
+        // Old sidecars only list the names of watched files
+        let mut entry = Self {
+            watched: fields.peek().is_none(),
+            ..Self::new(name)
+        };
+
+        for field in fields {
+            let (key, value) = field.split_once('=').unwrap_or((field, ""));
+            match key {
+                "watched" => {
+                    entry.watched = true;
+                    entry.watched_at = DateTime::parse_from_rfc3339(value).ok().map(|x| x.to_utc());
+                }
+                "count" => entry.count = value.parse().unwrap_or_default(),
+                "rating" => entry.rating = value.parse().ok().filter(|x| (1..=10).contains(x)),
+                "favourite" => entry.favourite = true,
+                "note" => entry.note = Some(value.to_owned()).filter(|x| !x.is_empty()),
+                "progress" => {
+                    entry.progress = value.split_once('/').and_then(|(position, duration)| {
+                        Some(Progress {
+                            position: position.parse().ok()?,
+                            duration: duration.parse().ok()?,
+                        })
+                    })
+                }
+                _ => entry.unknown.push(field.to_owned()),
+            }
+        }
+
+        // Entries without a count have been watched once
+        entry.count = match entry.watched {
+            true => entry.count.max(1),
+            false => 0,
+        };
+        entry
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields = Vec::new();
+        if self.watched && self.count > 1 {
+            fields.push(format!("count={}", self.count));
+        }
+
+        if let Some(Progress { position, duration }) = self.progress {
+            fields.push(format!("progress={position}/{duration}"));
+        }
+
+        if let Some(rating) = self.rating {
+            fields.push(format!("rating={rating}"));
+        }
+
+        if self.favourite {
+            fields.push("favourite".into());
+        }
+
+        if let Some(note) = &self.note {
+            fields.push(format!("note={}", note.replace(['\t', '\r', '\n'], " ")));
+        }
+
+        fields.extend(self.unknown.iter().cloned());
+
+        // A bare name is enough for a video watched at an unknown time
+        match self.watched_at {
+            Some(time) if self.watched => fields.insert(
+                0,
+                format!(
+                    "watched={}",
+                    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+                ),
+            ),
+            None if self.watched && !fields.is_empty() => fields.insert(0, "watched".into()),
+            _ => {}
+        }
+
+        f.write_str(&self.name)?;
+        for field in fields {
+            write!(f, "\t{field}")?;
+        }
+
         Ok(())
     }
 }
 
-pub fn open_sidecar(path: &Path) -> Option<Result<File>> {
-    let sidecar = path.parent()?.join(".watched");
-    let _ = ensure_hidden(&sidecar);
+/// Parses the lines of a sidecar. Players that only append to the file can
+/// leave multiple lines for the same file, which are merged together, with
+/// each watched line counting as another watch.
+pub(crate) fn parse_entries(data: &str) -> Vec<Entry> {
+    let mut entries = Vec::<Entry>::new();
+    for entry in data.lines().map(Entry::parse) {
+        if entry.name.is_empty() {
+            continue;
+        }
+
+        let Some(existing) = entries.iter_mut().find(|x| x.name == entry.name) else {
+            entries.push(entry);
+            continue;
+        };
+
+        if entry.watched {
+            existing.watched = true;
+            existing.watched_at = existing.watched_at.max(entry.watched_at);
+            existing.count += entry.count;
+            existing.progress = None;
+        } else {
+            existing.progress = entry.progress.or(existing.progress);
+        }
+
+        existing.rating = entry.rating.or(existing.rating);
+        existing.favourite |= entry.favourite;
+        existing.note = entry.note.or(existing.note.take());
+        for field in entry.unknown {
+            let key = field.split('=').next();
+            existing.unknown.retain(|x| x.split('=').next() != key);
+            existing.unknown.push(field);
+        }
+    }
+
+    entries
+}
+
+/// Sidecars are dotfiles, so they are already hidden everywhere but Windows.
+#[cfg(not(windows))]
+pub(crate) fn ensure_hidden(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Reads the entries of the sidecar a video is stored in, without opening it
+/// for writing. Videos without a sidecar have no entries.
+pub fn read_entries(config: &Config, video: &Path) -> Result<Vec<Entry>> {
+    match config.sidecar_path(video) {
+        Some(sidecar) => read_sidecar(&sidecar),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Looks up the entry of a single video, see [`read_entries`].
+pub fn read_entry(config: &Config, video: &Path) -> Result<Option<Entry>> {
+    let Some(name) = config.entry_name(video) else {
+        return Ok(None);
+    };
+
+    Ok(read_entries(config, video)?
+        .into_iter()
+        .find(|x| x.name == name))
+}
+
+/// Reads the entries of a sidecar file, returning none if it doesn't exist.
+pub fn read_sidecar(sidecar: &Path) -> Result<Vec<Entry>> {
+    match fs::read_to_string(sidecar) {
+        Ok(data) => Ok(parse_entries(&data)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub fn open_sidecar(config: &Config, path: &Path) -> Option<Result<File>> {
+    let sidecar = config.sidecar_path(path)?;
+    if config.hide_sidecar {
+        let _ = ensure_hidden(&sidecar);
+    }
 
     sidecar.exists().then(|| {
         match OpenOptions::new()
@@ -74,17 +404,336 @@ pub fn open_sidecar(path: &Path) -> Option<Result<File>> {
     })
 }
 
-pub fn open_or_create_sidecar(path: &Path) -> Result<File> {
-    let sidecar = path
-        .parent()
-        .context("Can't open sidecar for root directory")?
-        .join(".watched");
-    let _ = ensure_hidden(&sidecar);
+pub fn open_or_create_sidecar(config: &Config, path: &Path) -> Result<File> {
+    let sidecar = config
+        .sidecar_path(path)
+        .context("Can't open sidecar for root directory")?;
+
+    // Central storage lives in a directory that may not exist yet, but
+    // sidecars are only ever made next to videos that do
+    if let (Storage::Central, Some(parent)) = (config.storage, sidecar.parent()) {
+        fs::create_dir_all(parent)?;
+    }
 
-    Ok(OpenOptions::new()
+    let file = OpenOptions::new()
         .create(true)
         .truncate(false)
         .read(true)
         .write(true)
-        .open(sidecar)?)
+        .open(&sidecar)?;
+
+    if config.hide_sidecar {
+        let _ = ensure_hidden(&sidecar);
+    }
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::library;
+
+    fn time(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let lines = [
+            "Show.S01E01.mkv",
+            "Show.S01E02.mkv\twatched=2024-08-22T19:30:00Z",
+            "Show.S01E03.mkv\twatched=2024-08-23T19:30:00Z\tcount=3\trating=8\tfavourite\tnote=Best one",
+            "Show.S01E04.mkv\tprogress=754/1320",
+            "Show.S01E05.mkv\trating=3",
+            "Show.S01E06.mkv\twatched\tfavourite",
+        ];
+        for line in lines {
+            assert_eq!(Entry::parse(line).to_string(), line);
+        }
+
+        let entry = Entry::parse(lines[2]);
+        assert_eq!(
+            entry,
+            Entry {
+                watched: true,
+                watched_at: Some(time("2024-08-23T19:30:00Z")),
+                count: 3,
+                rating: Some(8),
+                favourite: true,
+                note: Some("Best one".to_owned()),
+                ..Entry::new("Show.S01E03.mkv")
+            }
+        );
+        assert_eq!(
+            Entry::parse(lines[3]).progress,
+            Some(Progress {
+                position: 754,
+                duration: 1320
+            })
+        );
+    }
+
+    #[test]
+    fn bare_names_are_watched_at_an_unknown_time() {
+        let entries = parse_entries("Show.S01E01.mkv\nShow.S01E02.mkv\r\n\nShow.S01E03.mkv");
+        let names = entries.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["Show.S01E01.mkv", "Show.S01E02.mkv", "Show.S01E03.mkv"]
+        );
+        assert!(entries
+            .iter()
+            .all(|x| x.watched && x.watched_at.is_none() && x.count == 1));
+
+        // Rewritten the same way, so older versions can still read them
+        assert_eq!(entries[0].to_string(), "Show.S01E01.mkv");
+    }
+
+    #[test]
+    fn merges_lines_for_the_same_video() {
+        let data = [
+            "Show.S01E01.mkv\tprogress=300/1320",
+            "Show.S01E01.mkv\twatched=2024-08-22T19:30:00Z\trating=6",
+            "Show.S01E02.mkv\tprogress=300/1320",
+            "Show.S01E01.mkv\twatched=2024-08-21T19:30:00Z\tcount=2\tnote=Again",
+            "Show.S01E02.mkv\tprogress=600/1320\tfavourite",
+            "Show.S01E01.mkv\trating=9",
+        ];
+        let entries = parse_entries(&data.join("\n"));
+
+        assert_eq!(
+            entries,
+            [
+                Entry {
+                    watched: true,
+                    watched_at: Some(time("2024-08-22T19:30:00Z")),
+                    count: 3,
+                    rating: Some(9),
+                    note: Some("Again".to_owned()),
+                    ..Entry::new("Show.S01E01.mkv")
+                },
+                Entry {
+                    progress: Some(Progress {
+                        position: 600,
+                        duration: 1320
+                    }),
+                    favourite: true,
+                    ..Entry::new("Show.S01E02.mkv")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_fields() {
+        let line = "Show.S01E01.mkv\twatched=2024-08-22T19:30:00Z\tsource=plex\tskipped_intro";
+        let entry = Entry::parse(line);
+        assert_eq!(entry.unknown, ["source=plex", "skipped_intro"]);
+        assert_eq!(entry.to_string(), line);
+
+        // Written after the known ones, with the last value of each kept
+        let data = "Show.S01E01.mkv\tsource=plex\nShow.S01E01.mkv\tsource=kodi\trating=7";
+        let entries = parse_entries(data);
+        assert_eq!(
+            entries[0].to_string(),
+            "Show.S01E01.mkv\trating=7\tsource=kodi"
+        );
+
+        // Not emptied by unmarking, as a newer version might still need them
+        let mut entry = Entry::parse(line);
+        entry.watched = false;
+        assert!(!entry.is_empty());
+        assert_eq!(
+            entry.to_string(),
+            "Show.S01E01.mkv\tsource=plex\tskipped_intro"
+        );
+    }
+
+    #[test]
+    fn ignores_values_that_are_out_of_range() {
+        let entry =
+            Entry::parse("Show.S01E01.mkv\twatched=yesterday\tcount=many\trating=11\tprogress=1/");
+        assert_eq!(
+            entry,
+            Entry {
+                watched: true,
+                count: 1,
+                ..Entry::new("Show.S01E01.mkv")
+            }
+        );
+    }
+
+    fn sidecar(data: &str) -> Sidecar {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        Sidecar::new(file).unwrap()
+    }
+
+    /// What's in the file, checking it matches the entries in memory.
+    fn contents(sidecar: &mut Sidecar) -> String {
+        let mut data = String::new();
+        sidecar.file.seek(SeekFrom::Start(0)).unwrap();
+        sidecar.file.read_to_string(&mut data).unwrap();
+        let entries = sidecar.entries.iter().map(|x| format!("{x}\n"));
+        assert_eq!(data, entries.collect::<String>());
+        data
+    }
+
+    #[test]
+    fn adding_counts_rewatches() {
+        let mut sidecar = sidecar("Show.S01E01.mkv\tprogress=300/1320\n");
+        sidecar
+            .add_at("Show.S01E02.mkv", time("2024-08-22T19:30:00Z"))
+            .unwrap();
+        sidecar
+            .add_at("Show.S01E01.mkv", time("2024-08-23T19:30:00Z"))
+            .unwrap();
+        assert_eq!(
+            contents(&mut sidecar),
+            "Show.S01E01.mkv\twatched=2024-08-23T19:30:00Z\n\
+             Show.S01E02.mkv\twatched=2024-08-22T19:30:00Z\n"
+        );
+
+        // An earlier watch adds to the count but keeps the latest time
+        sidecar
+            .add_at("Show.S01E01.mkv", time("2024-08-20T19:30:00Z"))
+            .unwrap();
+        let entry = sidecar.get("Show.S01E01.mkv").unwrap();
+        assert_eq!(entry.count, 2);
+        assert_eq!(entry.watched_at, Some(time("2024-08-23T19:30:00Z")));
+
+        // The same watch imported again
+        sidecar
+            .add_at("Show.S01E01.mkv", time("2024-08-20T19:30:00Z"))
+            .unwrap();
+        sidecar
+            .add_at("Show.S01E02.mkv", time("2024-08-22T19:30:00Z"))
+            .unwrap();
+        assert_eq!(sidecar.get("Show.S01E01.mkv").unwrap().count, 3);
+        assert_eq!(sidecar.get("Show.S01E02.mkv").unwrap().count, 1);
+        contents(&mut sidecar);
+    }
+
+    #[test]
+    fn setting_the_count_marks_as_watched() {
+        let mut sidecar = sidecar(
+            "Show.S01E01.mkv\twatched=2024-08-22T19:30:00Z\tcount=4\n\
+             Show.S01E02.mkv\tprogress=300/1320\trating=7\n",
+        );
+        sidecar.set_count("Show.S01E01.mkv", 1).unwrap();
+        sidecar.set_count("Show.S01E02.mkv", 2).unwrap();
+        sidecar.set_count("Show.S01E03.mkv", 5).unwrap();
+
+        let first = sidecar.get("Show.S01E01.mkv").unwrap();
+        assert_eq!(first.count, 1);
+        assert_eq!(first.watched_at, Some(time("2024-08-22T19:30:00Z")));
+        let second = sidecar.get("Show.S01E02.mkv").unwrap();
+        assert!(second.watched && second.watched_at.is_some());
+        assert_eq!(
+            (second.count, second.progress, second.rating),
+            (2, None, Some(7))
+        );
+        assert_eq!(sidecar.get("Show.S01E03.mkv").unwrap().count, 5);
+        assert!(
+            contents(&mut sidecar).starts_with("Show.S01E01.mkv\twatched=2024-08-22T19:30:00Z\n")
+        );
+    }
+
+    #[test]
+    fn updates_drop_empty_entries() {
+        let mut sidecar = sidecar("Show.S01E01.mkv\twatched=2024-08-22T19:30:00Z\n");
+        sidecar
+            .update("Show.S01E02.mkv", |x| x.note = Some("Later".to_owned()))
+            .unwrap();
+        sidecar
+            .update("Show.S01E01.mkv", |x| x.rating = Some(9))
+            .unwrap();
+        assert_eq!(
+            contents(&mut sidecar),
+            "Show.S01E01.mkv\twatched=2024-08-22T19:30:00Z\trating=9\n\
+             Show.S01E02.mkv\tnote=Later\n"
+        );
+
+        sidecar
+            .update("Show.S01E02.mkv", |x| x.note = None)
+            .unwrap();
+        sidecar
+            .update("Show.S01E03.mkv", |x| x.favourite = false)
+            .unwrap();
+        assert_eq!(
+            contents(&mut sidecar),
+            "Show.S01E01.mkv\twatched=2024-08-22T19:30:00Z\trating=9\n"
+        );
+    }
+
+    #[test]
+    fn removing_keeps_ratings_notes_and_favourites() {
+        let mut sidecar = sidecar(
+            "Show.S01E01.mkv\twatched=2024-08-22T19:30:00Z\tcount=2\n\
+             Show.S01E02.mkv\twatched=2024-08-22T19:30:00Z\trating=8\tfavourite\n\
+             Show.S01E03.mkv\tprogress=300/1320\tnote=Halfway\n",
+        );
+        for name in [
+            "Show.S01E01.mkv",
+            "Show.S01E02.mkv",
+            "Show.S01E03.mkv",
+            "Show.S01E04.mkv",
+        ] {
+            sidecar.remove(name).unwrap();
+        }
+
+        assert_eq!(
+            contents(&mut sidecar),
+            "Show.S01E02.mkv\trating=8\tfavourite\n\
+             Show.S01E03.mkv\tnote=Halfway\n"
+        );
+    }
+
+    #[test]
+    fn restores_previous_entries() {
+        let mut sidecar = sidecar("Show.S01E01.mkv\twatched=2024-08-22T19:30:00Z\n");
+        let before = sidecar.get("Show.S01E01.mkv").cloned();
+        sidecar
+            .add_at("Show.S01E01.mkv", time("2024-08-23T19:30:00Z"))
+            .unwrap();
+        sidecar
+            .add_at("Show.S01E02.mkv", time("2024-08-23T19:30:00Z"))
+            .unwrap();
+
+        sidecar.restore("Show.S01E01.mkv", before).unwrap();
+        sidecar.restore("Show.S01E02.mkv", None).unwrap();
+        assert_eq!(
+            contents(&mut sidecar),
+            "Show.S01E01.mkv\twatched=2024-08-22T19:30:00Z\n"
+        );
+
+        let restored = Entry::parse("Show.S01E03.mkv\trating=4");
+        sidecar
+            .restore("Show.S01E03.mkv", Some(restored.clone()))
+            .unwrap();
+        sidecar.restore("Show.S01E04.mkv", None).unwrap();
+        assert_eq!(sidecar.entries()[1..], [restored]);
+        contents(&mut sidecar);
+    }
+
+    #[test]
+    fn sidecars_are_only_made_in_existing_directories() {
+        let dir = library(&[]);
+        let video = dir.path().join("Missing/Show.S01E01.mkv");
+
+        let config = Config::default();
+        assert!(open_or_create_sidecar(&config, &video).is_err());
+        assert!(!dir.path().join("Missing").exists());
+
+        // The central sidecar's directory is made when it's first needed
+        let config = Config {
+            storage: Storage::Central,
+            ..Config::default()
+        };
+        open_or_create_sidecar(&config, &video).unwrap();
+        assert!(config.sidecar_path(&video).unwrap().is_file());
+        assert!(!dir.path().join("Missing").exists());
+    }
 }
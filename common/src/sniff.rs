@@ -0,0 +1,107 @@
+use std::{fs::File, io::Read, path::Path};
+
+use anyhow::Result;
+
+/// Number of bytes from the start of a file needed to recognise any of the
+/// supported containers.
+pub const SNIFF_LEN: usize = 4 + M2TS_PACKET_LEN * 2 + 1;
+
+const EBML_MAGIC: &[u8] = &[0x1A, 0x45, 0xDF, 0xA3];
+const ASF_MAGIC: &[u8] = &[
+    0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11, 0xA6, 0xD9, 0x00, 0xAA, 0x00, 0x62, 0xCE, 0x6C,
+];
+const MPEG_PS_MAGIC: &[u8] = &[0x00, 0x00, 0x01, 0xBA];
+const MP4_BOXES: &[&[u8]] = &[b"ftyp", b"moov", b"mdat", b"wide"];
+
+const TS_SYNC: u8 = 0x47;
+const TS_PACKET_LEN: usize = 188;
+/// M2TS (Blu-ray) packets have a four byte timecode before each TS packet.
+const M2TS_PACKET_LEN: usize = 192;
+
+/// Video container formats that can be recognised from a file's contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Container {
+    /// Matroska or WebM.
+    Matroska,
+    /// MP4 or QuickTime.
+    Mp4,
+    Avi,
+    Flv,
+    /// ASF, used by WMV.
+    Asf,
+    /// MPEG program stream, used by DVD `.vob` files.
+    MpegPs,
+    /// MPEG transport stream, including Blu-ray's M2TS variant.
+    MpegTs,
+}
+
+/// Recognises a video container from the first bytes of a file, which should
+/// be at least [`SNIFF_LEN`] long unless the file is shorter.
+pub fn sniff(header: &[u8]) -> Option<Container> {
+    if header.starts_with(EBML_MAGIC) {
+        Some(Container::Matroska)
+    } else if header.get(4..8).is_some_and(|x| MP4_BOXES.contains(&x)) {
+        Some(Container::Mp4)
+    } else if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"AVI ") {
+        Some(Container::Avi)
+    } else if header.starts_with(b"FLV\x01") {
+        Some(Container::Flv)
+    } else if header.starts_with(ASF_MAGIC) {
+        Some(Container::Asf)
+    } else if header.starts_with(MPEG_PS_MAGIC) {
+        Some(Container::MpegPs)
+    } else if is_transport_stream(header, 0, TS_PACKET_LEN)
+        || is_transport_stream(header, 4, M2TS_PACKET_LEN)
+    {
+        Some(Container::MpegTs)
+    } else {
+        None
+    }
+}
+
+/// Reads the start of a file and tries to recognise its container.
+pub fn sniff_file(path: &Path) -> Result<Option<Container>> {
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)?;
+    Ok(sniff(&header))
+}
+
+/// A single sync byte is too common to go on, so this checks that the first
+/// three packets all start with one.
+fn is_transport_stream(header: &[u8], offset: usize, packet_len: usize) -> bool {
+    (0..3).all(|packet| header.get(offset + packet * packet_len) == Some(&TS_SYNC))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three packets of a transport stream, with the sync byte `offset`
+    /// bytes into each.
+    fn packets(offset: usize, packet_len: usize) -> Vec<u8> {
+        let mut data = vec![0; packet_len * 3];
+        for packet in data.chunks_mut(packet_len) {
+            packet[offset] = TS_SYNC;
+        }
+        data
+    }
+
+    #[test]
+    fn recognises_transport_streams_within_sniff_len() {
+        let ts = packets(0, TS_PACKET_LEN);
+        assert_eq!(sniff(&ts[..SNIFF_LEN]), Some(Container::MpegTs));
+
+        let m2ts = packets(4, M2TS_PACKET_LEN);
+        assert_eq!(sniff(&m2ts[..SNIFF_LEN]), Some(Container::MpegTs));
+    }
+
+    #[test]
+    fn needs_three_sync_bytes() {
+        let mut ts = packets(4, M2TS_PACKET_LEN);
+        ts[4 + M2TS_PACKET_LEN * 2] = 0;
+        assert_eq!(sniff(&ts), None);
+        assert_eq!(sniff(&[TS_SYNC]), None);
+    }
+}
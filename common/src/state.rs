@@ -0,0 +1,237 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+
+use crate::{
+    cache::{self, CachedFolderState, SidecarCache},
+    config::Config,
+    progress::is_watched,
+    sidecar::Entry,
+};
+
+/// How many levels of folders are searched for videos when classifying a
+/// folder. Enough for a show with season folders, without walking a whole
+/// library every time Explorer draws its root.
+pub const FOLDER_DEPTH: usize = 2;
+
+/// How far a video has been watched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoState {
+    Unwatched,
+    /// Playback was stopped before the watched threshold.
+    InProgress,
+    Watched,
+}
+
+/// How many of the videos in a folder have been watched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FolderState {
+    Unwatched,
+    /// Some videos are watched or in progress, but not all of them are watched.
+    PartiallyWatched,
+    Watched,
+}
+
+impl VideoState {
+    /// Classifies a video by its sidecar entry. A resume position past the
+    /// threshold counts as watched, like the player would have marked it.
+    pub fn of(entry: Option<&Entry>, threshold: f64) -> Self {
+        let Some(entry) = entry else {
+            return Self::Unwatched;
+        };
+        if entry.watched {
+            return Self::Watched;
+        }
+
+        match entry.progress {
+            Some(progress) => {
+                let position = Duration::from_secs(progress.position);
+                let duration = Duration::from_secs(progress.duration);
+                match is_watched(position, duration, threshold) {
+                    true => Self::Watched,
+                    false => Self::InProgress,
+                }
+            }
+            None => Self::Unwatched,
+        }
+    }
+}
+
+impl FolderState {
+    /// Combines the states of the videos in a folder, none if there aren't any.
+    pub fn of(videos: impl IntoIterator<Item = VideoState>) -> Option<Self> {
+        let mut any = false;
+        let mut started = false;
+        let mut finished = true;
+        for state in videos {
+            any = true;
+            started |= state != VideoState::Unwatched;
+            finished &= state == VideoState::Watched;
+        }
+
+        match (any, started, finished) {
+            (false, _, _) => None,
+            (true, _, true) => Some(Self::Watched),
+            (true, true, false) => Some(Self::PartiallyWatched),
+            (true, false, false) => Some(Self::Unwatched),
+        }
+    }
+}
+
+/// Looks up the state of a video.
+pub fn video_state(cache: &mut SidecarCache, config: &Config, video: &Path) -> Result<VideoState> {
+    let entry = cache.entry(config, video)?;
+    Ok(VideoState::of(entry.as_ref(), config.watched_threshold))
+}
+
+/// Looks up the state of the videos in a folder and the folders in it, up to
+/// [`FOLDER_DEPTH`] deep. Only extensions are checked, as sniffing every file
+/// would be too slow for the overlays.
+///
+/// Explorer asks each overlay about every folder it shows, from several
+/// threads, so states are kept in the shared cache and it isn't locked while
+/// directories are listed.
+pub fn folder_state(config: &Config, dir: &Path) -> Result<Option<FolderState>> {
+    let cached = cache::shared().folder(dir);
+    let listing = match cached {
+        Some(CachedFolderState::Fresh(state)) => return Ok(state),
+        Some(CachedFolderState::Stale(listing)) if listing.is_current()? => listing,
+        _ => Arc::new(Listing::read(config, dir)?),
+    };
+
+    let mut cache = cache::shared();
+    let states = listing
+        .videos
+        .iter()
+        .map(|video| video_state(&mut cache, config, video))
+        .collect::<Result<Vec<_>>>()?;
+    let state = FolderState::of(states);
+    cache.insert_folder(dir, listing, state);
+    Ok(state)
+}
+
+/// The videos found in a folder.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Listing {
+    /// Directories searched, with when they were modified, which changes
+    /// when anything is added to or removed from them.
+    pub dirs: Vec<(PathBuf, SystemTime)>,
+    pub videos: Vec<PathBuf>,
+}
+
+impl Listing {
+    fn read(config: &Config, root: &Path) -> Result<Self> {
+        let mut listing = Self::default();
+        let mut dirs = vec![(root.to_owned(), 0)];
+        while let Some((dir, depth)) = dirs.pop() {
+            listing
+                .dirs
+                .push((dir.clone(), fs::metadata(&dir)?.modified()?));
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                if !config.include_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+
+                let path = entry.path();
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() && depth + 1 < FOLDER_DEPTH {
+                    dirs.push((path, depth + 1));
+                } else if file_type.is_file() && config.has_video_extension(&path) {
+                    listing.videos.push(path);
+                }
+            }
+        }
+
+        Ok(listing)
+    }
+
+    /// Checks that none of the directories changed since they were listed.
+    fn is_current(&self) -> Result<bool> {
+        for (dir, modified) in &self.dirs {
+            match fs::metadata(dir) {
+                Ok(metadata) if metadata.modified()? == *modified => {}
+                Ok(_) => return Ok(false),
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{library, touch};
+
+    const SHOW: &[&str] = &[
+        "Show/Season 1/Show.S01E01.mkv",
+        "Show/Season 1/Show.S01E02.mkv",
+        "Show/Season 1/Extras/Show.S01E02.Deleted.Scenes.mkv",
+        "Show/notes.txt",
+    ];
+
+    /// Marks videos in a season folder and lets the cache know, like the
+    /// context menu does.
+    fn mark(season: &Path, names: &[&str]) {
+        let lines = names.iter().map(|x| format!("{x}\twatched\n"));
+        fs::write(season.join(".watched"), lines.collect::<String>()).unwrap();
+        cache::shared().invalidate(&season.join(".watched"));
+    }
+
+    #[test]
+    fn folder_state_follows_sidecars_and_listing() {
+        let dir = library(SHOW);
+        let show = dir.path().join("Show");
+        let season = show.join("Season 1");
+        let config = Config::for_path(&show).unwrap();
+        let state = || folder_state(&config, &show).unwrap();
+
+        assert_eq!(state(), Some(FolderState::Unwatched));
+        mark(&season, &["Show.S01E01.mkv"]);
+        assert_eq!(state(), Some(FolderState::PartiallyWatched));
+
+        // Videos deeper than FOLDER_DEPTH don't count
+        mark(&season, &["Show.S01E01.mkv", "Show.S01E02.mkv"]);
+        assert_eq!(state(), Some(FolderState::Watched));
+
+        // A new video changes the season folder, so it's listed again
+        touch(&season.join("Show.S01E03.mkv"));
+        mark(&season, &["Show.S01E01.mkv", "Show.S01E02.mkv"]);
+        assert_eq!(state(), Some(FolderState::PartiallyWatched));
+
+        let empty = dir.path().join("Empty");
+        fs::create_dir(&empty).unwrap();
+        assert_eq!(folder_state(&config, &empty).unwrap(), None);
+    }
+
+    #[test]
+    fn reuses_fresh_folder_states() {
+        let dir = library(SHOW);
+        let show = dir.path().join("Show");
+        let config = Config::for_path(&show).unwrap();
+        mark(&show.join("Season 1"), &["Show.S01E01.mkv"]);
+        assert_eq!(
+            folder_state(&config, &show).unwrap(),
+            Some(FolderState::PartiallyWatched)
+        );
+
+        // Asked again for the next overlay without touching the disk
+        fs::remove_dir_all(&show).unwrap();
+        assert_eq!(
+            folder_state(&config, &show).unwrap(),
+            Some(FolderState::PartiallyWatched)
+        );
+
+        cache::shared().invalidate(&show.join("Season 1/.watched"));
+        assert!(folder_state(&config, &show).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+//! Helpers shared by the tests of this crate and the ones that use it, with
+//! the `test-util` feature.
+
+use std::{
+    env, fs,
+    path::Path,
+    sync::{Mutex, MutexGuard, OnceLock, PoisonError},
+};
+
+use tempfile::TempDir;
+
+use crate::config::USER_ENV;
+
+/// A library directory for one test, with the config and data directories
+/// pointed at a temporary directory shared by every test so the real ones are
+/// never touched.
+///
+/// Tests with a library run one at a time, as they share the journal and the
+/// environment. The environment is only changed while one is alive.
+pub struct Library {
+    dir: TempDir,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Library {
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Switches to another user like `--user`, or back to the default one.
+    pub fn set_user(&self, user: Option<&str>) {
+        match user {
+            Some(user) => env::set_var(USER_ENV, user),
+            None => env::remove_var(USER_ENV),
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        self.set_user(None);
+    }
+}
+
+/// Creates a library with empty files for each video.
+pub fn library(videos: &[&str]) -> Library {
+    static DIRS: OnceLock<TempDir> = OnceLock::new();
+    static LOCK: Mutex<()> = Mutex::new(());
+    let lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+    DIRS.get_or_init(|| {
+        let dir = TempDir::new().unwrap();
+        env::set_var("XDG_CONFIG_HOME", dir.path().join("config"));
+        env::set_var("XDG_DATA_HOME", dir.path().join("data"));
+        env::set_var("XDG_CACHE_HOME", dir.path().join("cache"));
+        env::remove_var(USER_ENV);
+        dir
+    });
+
+    let dir = TempDir::new().unwrap();
+    for video in videos {
+        touch(&dir.path().join(video));
+    }
+
+    Library { dir, _lock: lock }
+}
+
+pub fn touch(path: &Path) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, "").unwrap();
+}
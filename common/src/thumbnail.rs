@@ -0,0 +1,288 @@
+//! Watched badges on the thumbnails Linux file managers cache, following the
+//! [thumbnail spec](https://specifications.freedesktop.org/thumbnail-spec/latest/).
+//!
+//! The thumbnails themselves are still made by the file manager. A watched
+//! video's thumbnail has the badge drawn over it, and an unwatched video's
+//! badged thumbnail is deleted so the file manager makes a clean one again.
+
+use std::{
+    fs,
+    io::{BufWriter, ErrorKind, Read},
+    path::{self, Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{bail, Context, Result};
+use md5::{Digest, Md5};
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+/// Size directories in the thumbnail cache, up to 1024 pixels.
+const SIZES: &[&str] = &["normal", "large", "x-large", "xx-large"];
+
+const MTIME_KEY: &str = "Thumb::MTime";
+/// Set on thumbnails with the badge, so they aren't badged twice and only
+/// ours are deleted.
+const BADGE_KEY: &str = "LastWatched::Badge";
+
+/// The shell extension's overlay icon, whose largest image is a PNG with the
+/// check mark in the bottom left corner.
+const ICON: &[u8] = include_bytes!("../assets/icon.ico");
+
+/// A decoded PNG, converted to 8 bit RGBA.
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    /// Text chunks, which hold the thumbnail's metadata.
+    text: Vec<(String, String)>,
+}
+
+/// Badges or unbadges the cached thumbnails of a video, in every size the
+/// file manager made one for. Thumbnails made before the video last changed
+/// are left alone, as the file manager replaces them anyway.
+pub fn update(video: &Path, watched: bool) -> Result<()> {
+    let dir = dirs::cache_dir()
+        .context("Can't find the cache directory")?
+        .join("thumbnails");
+    let video = path::absolute(video)?;
+    let name = thumbnail_name(&video);
+    let mtime = fs::metadata(&video)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_secs()
+        .to_string();
+
+    for size in SIZES {
+        let path = dir.join(size).join(&name);
+        let mut thumbnail = match fs::File::open(&path) {
+            Ok(file) => {
+                decode(file).with_context(|| format!("Invalid thumbnail {}", path.display()))?
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        match (watched, thumbnail.get(BADGE_KEY).is_some()) {
+            (true, false) if thumbnail.get(MTIME_KEY) == Some(&mtime) => {
+                thumbnail.draw_badge()?;
+                thumbnail.text.push((BADGE_KEY.to_owned(), "1".to_owned()));
+                write(&path, &thumbnail)?;
+            }
+            (false, true) => fs::remove_file(&path)?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Thumbnails are named after the MD5 of the video's URI, in lowercase hex.
+fn thumbnail_name(video: &Path) -> String {
+    let hash = Md5::digest(file_uri(video));
+    let hex = hash.iter().map(|x| format!("{x:02x}")).collect::<String>();
+    format!("{hex}.png")
+}
+
+/// Escapes an absolute path into a `file://` URI the same way GLib does, as
+/// the hash has to match the one the file manager made.
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_encoded_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => uri.push(byte as char),
+            b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b'-' | b'.' | b'/'
+            | b':' | b'=' | b'@' | b'_' | b'~' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    uri
+}
+
+impl Image {
+    fn get(&self, key: &str) -> Option<&String> {
+        self.text.iter().find(|(x, _)| x == key).map(|(_, x)| x)
+    }
+
+    /// Draws the icon over the bottom left corner, two thirds the size of the
+    /// thumbnail's shorter side.
+    fn draw_badge(&mut self) -> Result<()> {
+        let icon = badge()?;
+        let size = self.width.min(self.height) * 2 / 3;
+        let top = self.height - size;
+
+        for y in 0..size {
+            for x in 0..size {
+                let [r, g, b, a] = icon.sample(x, y, size);
+                let i = (((top + y) * self.width + x) * 4) as usize;
+                let pixel = &mut self.pixels[i..i + 4];
+                // Source over, with the icon premultiplied
+                for (channel, value) in pixel.iter_mut().zip([r, g, b, a]) {
+                    *channel = (value + *channel as f32 * (1.0 - a / 255.0)).round() as u8;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Averages the pixels under one pixel of the image scaled down to
+    /// `size`, premultiplied by alpha so transparent pixels don't darken the
+    /// edges.
+    fn sample(&self, x: u32, y: u32, size: u32) -> [f32; 4] {
+        let range = |i: u32, len: u32| {
+            let start = i * len / size;
+            start..((i + 1) * len / size).max(start + 1)
+        };
+
+        let mut sum = [0.0; 4];
+        let mut count = 0.0;
+        for sy in range(y, self.height) {
+            for sx in range(x, self.width) {
+                let i = ((sy * self.width + sx) * 4) as usize;
+                let pixel = &self.pixels[i..i + 4];
+                let alpha = pixel[3] as f32 / 255.0;
+                for c in 0..3 {
+                    sum[c] += pixel[c] as f32 * alpha;
+                }
+                sum[3] += pixel[3] as f32;
+                count += 1.0;
+            }
+        }
+
+        sum.map(|x| x / count)
+    }
+}
+
+/// Finds the PNG image in the icon, as the smaller sizes are bitmaps.
+fn badge() -> Result<Image> {
+    let read_u16 = |at: usize| u16::from_le_bytes([ICON[at], ICON[at + 1]]) as usize;
+    let read_u32 = |at: usize| {
+        u32::from_le_bytes([ICON[at], ICON[at + 1], ICON[at + 2], ICON[at + 3]]) as usize
+    };
+
+    // Each image has a 16 byte entry after the 6 byte header, ending with its
+    // size and offset
+    for entry in (0..read_u16(4)).map(|i| 6 + i * 16) {
+        let (size, offset) = (read_u32(entry + 8), read_u32(entry + 12));
+        let data = &ICON[offset..offset + size];
+        if data.starts_with(b"\x89PNG") {
+            return decode(data);
+        }
+    }
+
+    bail!("The icon has no PNG image")
+}
+
+fn decode(data: impl Read) -> Result<Image> {
+    let mut decoder = Decoder::new(data);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf)?;
+    buf.truncate(frame.buffer_size());
+    // Text chunks can also come after the image data
+    reader.finish()?;
+
+    let pixels = match frame.color_type {
+        ColorType::Rgba => buf,
+        ColorType::Rgb => buf
+            .chunks(3)
+            .flat_map(|x| [x[0], x[1], x[2], 255])
+            .collect(),
+        ColorType::GrayscaleAlpha => buf
+            .chunks(2)
+            .flat_map(|x| [x[0], x[0], x[0], x[1]])
+            .collect(),
+        ColorType::Grayscale => buf.iter().flat_map(|&x| [x, x, x, 255]).collect(),
+        ColorType::Indexed => bail!("Palette wasn't expanded"),
+    };
+
+    let info = reader.info();
+    let mut text = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|x| (x.keyword.clone(), x.text.clone()))
+        .collect::<Vec<_>>();
+    for chunk in &info.compressed_latin1_text {
+        text.push((chunk.keyword.clone(), chunk.get_text()?));
+    }
+    for chunk in &info.utf8_text {
+        text.push((chunk.keyword.clone(), chunk.get_text()?));
+    }
+
+    Ok(Image {
+        width: frame.width,
+        height: frame.height,
+        pixels,
+        text,
+    })
+}
+
+/// Writes a thumbnail through a temporary file, so the file manager never
+/// reads half of one. The spec asks for them to only be readable by the user.
+fn write(path: &Path, image: &Image) -> Result<()> {
+    let mut temp = PathBuf::from(path);
+    temp.set_extension(format!("png.{}.tmp", std::process::id()));
+
+    let file = fs::File::create(&temp)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+
+    let mut encoder = Encoder::new(BufWriter::new(file), image.width, image.height);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    for (key, value) in &image.text {
+        match value.is_ascii() {
+            true => encoder.add_text_chunk(key.clone(), value.clone())?,
+            false => encoder.add_itxt_chunk(key.clone(), value.clone())?,
+        }
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.pixels)?;
+    writer.finish()?;
+
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_thumbnails_like_the_spec() {
+        let path = Path::new("/home/jens/photos/me.png");
+        assert_eq!(file_uri(path), "file:///home/jens/photos/me.png");
+        assert_eq!(thumbnail_name(path), "c6ee772d9e49320e97ec29a7eb5b1697.png");
+    }
+
+    #[test]
+    fn escapes_paths_like_glib() {
+        let path = Path::new("/home/user/My Videos/Café #1 [1080p].mkv");
+        assert_eq!(
+            file_uri(path),
+            "file:///home/user/My%20Videos/Caf%C3%A9%20%231%20%5B1080p%5D.mkv"
+        );
+        assert_eq!(thumbnail_name(path), "59f3069776e4191a707ebb9a88a07699.png");
+
+        let path = Path::new("/media/Show (2019)/It's a Show, S01E01 + extras~.mkv");
+        assert_eq!(
+            file_uri(path),
+            "file:///media/Show%20(2019)/It's%20a%20Show,%20S01E01%20+%20extras~.mkv"
+        );
+    }
+
+    #[test]
+    fn finds_the_badge_in_the_icon() {
+        let badge = badge().unwrap();
+        assert!(badge.width >= 64 && badge.width == badge.height);
+        assert_eq!(
+            badge.pixels.len(),
+            (badge.width * badge.height * 4) as usize
+        );
+    }
+}
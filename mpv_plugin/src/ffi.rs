@@ -0,0 +1,46 @@
+//! The parts of mpv's client API (`mpv/client.h`) used by the plugin.
+//! These symbols are exported by the mpv binary itself when it loads a C plugin.
+
+#![allow(non_camel_case_types)]
+
+use std::ffi::{c_char, c_int, c_void};
+
+pub const MPV_FORMAT_DOUBLE: c_int = 5;
+
+pub const MPV_EVENT_SHUTDOWN: c_int = 1;
+pub const MPV_EVENT_END_FILE: c_int = 7;
+pub const MPV_EVENT_FILE_LOADED: c_int = 8;
+pub const MPV_EVENT_PROPERTY_CHANGE: c_int = 22;
+
+#[repr(C)]
+pub struct mpv_handle {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct mpv_event {
+    pub event_id: c_int,
+    pub error: c_int,
+    pub reply_userdata: u64,
+    pub data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct mpv_event_property {
+    pub name: *const c_char,
+    pub format: c_int,
+    pub data: *mut c_void,
+}
+
+extern "C" {
+    pub fn mpv_wait_event(ctx: *mut mpv_handle, timeout: f64) -> *mut mpv_event;
+    pub fn mpv_observe_property(
+        ctx: *mut mpv_handle,
+        reply_userdata: u64,
+        name: *const c_char,
+        format: c_int,
+    ) -> c_int;
+    pub fn mpv_get_property_string(ctx: *mut mpv_handle, name: *const c_char) -> *mut c_char;
+    pub fn mpv_command(ctx: *mut mpv_handle, args: *mut *const c_char) -> c_int;
+    pub fn mpv_free(data: *mut c_void);
+}
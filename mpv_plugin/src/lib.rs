@@ -0,0 +1,210 @@
+use std::{
+    ffi::{c_int, CStr, CString},
+    path::{Path, PathBuf},
+    ptr,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use common::{
+    config::Config,
+    history::{record, Action, Event, Source},
+    progress::is_watched,
+    sidecar::{open_or_create_sidecar, read_entry, Progress, Sidecar},
+    thumbnail,
+};
+
+mod ffi;
+use ffi::{
+    mpv_command, mpv_event_property, mpv_free, mpv_get_property_string, mpv_handle,
+    mpv_observe_property, mpv_wait_event, MPV_EVENT_END_FILE, MPV_EVENT_FILE_LOADED,
+    MPV_EVENT_PROPERTY_CHANGE, MPV_EVENT_SHUTDOWN, MPV_FORMAT_DOUBLE,
+};
+
+/// Positions before this many seconds aren't worth saving.
+const MIN_PROGRESS: f64 = 10.0;
+
+struct Mpv(*mut mpv_handle);
+
+/// The video currently open in mpv.
+struct Session {
+    path: PathBuf,
+    config: Config,
+    position: f64,
+    duration: f64,
+    /// Whether the video has been marked during this playback. Rewatching a
+    /// video marks it again, incrementing its watch count.
+    marked: bool,
+}
+
+/// Entry point called by mpv on its own thread for each loaded C plugin.
+/// Returning from it unloads the plugin.
+#[no_mangle]
+unsafe extern "C" fn mpv_open_cplugin(handle: *mut mpv_handle) -> c_int {
+    let mpv = Mpv(handle);
+    mpv.observe(c"time-pos");
+    mpv.observe(c"duration");
+
+    let mut session = None;
+    loop {
+        let event = &*mpv_wait_event(handle, -1.0);
+        match event.event_id {
+            MPV_EVENT_FILE_LOADED => session = Session::start(&mpv),
+            MPV_EVENT_PROPERTY_CHANGE => {
+                let property = &*(event.data as *const mpv_event_property);
+                if property.format != MPV_FORMAT_DOUBLE {
+                    continue;
+                }
+
+                let value = *(property.data as *const f64);
+                if let Some(session) = &mut session {
+                    match CStr::from_ptr(property.name).to_bytes() {
+                        b"time-pos" => session.update(&mpv, value),
+                        b"duration" => session.duration = value,
+                        _ => {}
+                    }
+                }
+            }
+            MPV_EVENT_END_FILE => {
+                if let Some(session) = session.take() {
+                    session.finish();
+                }
+            }
+            MPV_EVENT_SHUTDOWN => {
+                if let Some(session) = session.take() {
+                    session.finish();
+                }
+                return 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Session {
+    fn start(mpv: &Mpv) -> Option<Self> {
+        let working_directory = mpv.get_string(c"working-directory")?;
+        let path = Path::new(&working_directory).join(mpv.get_string(c"path")?);
+        if !path.is_file() {
+            return None;
+        }
+
+        let config = Config::for_path(&path)
+            .inspect_err(|err| eprintln!("[last-watched] {err:#}"))
+            .ok()?;
+        if !config.is_video(&path) {
+            return None;
+        }
+
+        let watched = read_entry(&config, &path)
+            .unwrap_or_default()
+            .is_some_and(|x| x.watched);
+
+        if watched {
+            mpv.osd("Already watched");
+        }
+
+        Some(Self {
+            path,
+            config,
+            position: 0.0,
+            duration: 0.0,
+            marked: false,
+        })
+    }
+
+    fn update(&mut self, mpv: &Mpv, position: f64) {
+        self.position = position;
+
+        let seconds = |x: f64| Duration::try_from_secs_f64(x).unwrap_or_default();
+        let (position, duration) = (seconds(self.position), seconds(self.duration));
+        if self.marked || !is_watched(position, duration, self.config.watched_threshold) {
+            return;
+        }
+
+        // Only mark once per playback, even if seeking back past the threshold
+        self.marked = true;
+        match self.mark() {
+            Ok(()) => mpv.osd("Marked as watched"),
+            Err(err) => eprintln!("[last-watched] {err:#}"),
+        }
+    }
+
+    /// Saves how far into the video playback got if it wasn't finished.
+    fn finish(self) {
+        if self.marked || self.position < MIN_PROGRESS {
+            return;
+        }
+
+        let progress = Progress {
+            position: self.position as u64,
+            duration: self.duration as u64,
+        };
+
+        if let Err(err) = self.save_progress(progress) {
+            eprintln!("[last-watched] {err:#}");
+        }
+    }
+
+    fn mark(&self) -> Result<()> {
+        let (mut sidecar, name) = self.sidecar()?;
+        sidecar.add(&name)?;
+        self.record(Action::Watched)?;
+
+        // The video is already marked, so failing to badge is only reported
+        if self.config.thumbnails {
+            if let Err(err) = thumbnail::update(&self.path, true) {
+                eprintln!("[last-watched] Failed to badge the thumbnails: {err:#}");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn save_progress(&self, progress: Progress) -> Result<()> {
+        let (mut sidecar, name) = self.sidecar()?;
+        sidecar.set_progress(&name, progress)?;
+        self.record(Action::Progress)
+    }
+
+    fn record(&self, action: Action) -> Result<()> {
+        record(&self.config, &Event::new(Source::Mpv, action, &self.path))
+    }
+
+    fn sidecar(&self) -> Result<(Sidecar, String)> {
+        let name = self
+            .config
+            .entry_name(&self.path)
+            .context("Video has no file name")?;
+        let sidecar = Sidecar::new(open_or_create_sidecar(&self.config, &self.path)?)?;
+        Ok((sidecar, name))
+    }
+}
+
+impl Mpv {
+    fn observe(&self, name: &CStr) {
+        unsafe { mpv_observe_property(self.0, 0, name.as_ptr(), MPV_FORMAT_DOUBLE) };
+    }
+
+    fn get_string(&self, name: &CStr) -> Option<String> {
+        unsafe {
+            let value = mpv_get_property_string(self.0, name.as_ptr());
+            if value.is_null() {
+                return None;
+            }
+
+            let string = CStr::from_ptr(value).to_string_lossy().into_owned();
+            mpv_free(value.cast());
+            Some(string)
+        }
+    }
+
+    fn osd(&self, message: &str) {
+        let Ok(message) = CString::new(message) else {
+            return;
+        };
+
+        let mut args = [c"show-text".as_ptr(), message.as_ptr(), ptr::null()];
+        unsafe { mpv_command(self.0, args.as_mut_ptr()) };
+    }
+}
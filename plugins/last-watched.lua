@@ -39,19 +39,27 @@ function on_file_loaded(event)
     local sidecar_path = join_paths(folder, ".watched")
     local success, lines = pcall(io.lines, sidecar_path)
 
-    -- Check for the current file in the sidecar file, returning if it is already there
+    -- Check if the current file is already in the sidecar file
+    local watched = false
     if success then
         for line in lines do
-            if line == file then
-                mp.osd_message("Already watched")
-                return
+            -- Entries can have extra tab separated fields after the file name,
+            -- and are only watched if they have no fields or a watched field
+            local name, fields = line:match("^([^\t]*)(.*)$")
+            if name == file and (fields == "" or fields:find("\twatched")) then
+                watched = true
             end
         end
     end
-    
-    mp.osd_message("Marking as watched")
+
+    -- Rewatches are appended too, each extra line counts as another watch
+    if watched then
+        mp.osd_message("Already watched, marking as rewatched")
+    else
+        mp.osd_message("Marking as watched")
+    end
     local sidecar = io.open(sidecar_path, "a+")
-    sidecar:write(file .. "\n")
+    sidecar:write(file .. "\twatched=" .. os.date("!%Y-%m-%dT%H:%M:%SZ") .. "\n")
     sidecar:close()
 end
 
@@ -1,9 +1,16 @@
+#![cfg(windows)]
+
 use std::{ffi::c_void, panic, process};
 
+use common::{
+    config::Config,
+    registry::{register_all, unregister_all, video_extensions, Registration},
+};
 use windows::Win32::{
     Foundation::{CLASS_E_CLASSNOTAVAILABLE, HANDLE, HINSTANCE, MAX_PATH, S_OK},
     System::{
         Com::IClassFactory,
+        Ole::SELFREG_E_CLASS,
         ProcessStatus::GetProcessImageFileNameA,
         SystemServices::{DLL_PROCESS_ATTACH, DLL_PROCESS_DETACH},
     },
@@ -17,9 +24,10 @@ mod registry;
 use misc::get_module_path;
 use providers::{
     context_menu::{self, WatchedContextMenuFactory, CONTEXT_MENU_CLSID},
-    icon_overlay::{self, WatchedOverlayFactory, OVERLAY_CLSID},
+    icon_overlay::{self, Overlay, StateOverlayFactory, OVERLAYS},
     property_store::{self, WatchedPropertyStoreFactory, PROPERTY_STORE_CLSID},
 };
+use registry::WinRegistry;
 
 static mut INSTANCE: HINSTANCE = HINSTANCE(0 as _);
 
@@ -59,8 +67,11 @@ unsafe extern "system" fn DllGetClassObject(
     ppv: *mut *mut c_void,
 ) -> HRESULT {
     log!("DllGetClassObject: {:?}", *rclsid);
+    if let Some(overlay) = Overlay::from_clsid(&*rclsid) {
+        return IClassFactory::from(StateOverlayFactory { overlay }).query(riid, ppv);
+    }
+
     match *rclsid {
-        OVERLAY_CLSID => IClassFactory::from(WatchedOverlayFactory).query(riid, ppv),
         CONTEXT_MENU_CLSID => IClassFactory::from(WatchedContextMenuFactory).query(riid, ppv),
         PROPERTY_STORE_CLSID => IClassFactory::from(WatchedPropertyStoreFactory).query(riid, ppv),
         _ => CLASS_E_CLASSNOTAVAILABLE,
@@ -75,18 +86,47 @@ unsafe extern "system" fn DllCanUnloadNow() -> HRESULT {
 #[no_mangle]
 unsafe extern "system" fn DllRegisterServer() -> HRESULT {
     let module_path = get_module_path(INSTANCE);
-    icon_overlay::register(&module_path).unwrap();
-    context_menu::register(&module_path).unwrap();
-    property_store::register(&module_path).unwrap();
+    if let Err(err) = property_store::register_schema(&module_path) {
+        log!("Failed to register property schema: {err:?}");
+        return SELFREG_E_CLASS;
+    }
+
+    let extensions = video_extensions(&Config::load().unwrap_or_default());
+    if let Err(err) = register_all(&mut WinRegistry, &registrations(&module_path), &extensions) {
+        log!("Failed to register: {err:?}");
+        let _ = property_store::unregister_schema(&module_path);
+        return SELFREG_E_CLASS;
+    }
+
     SHChangeNotify(SHCNE_ASSOCCHANGED, SHCNF_IDLIST, None, None);
     S_OK
 }
 
 #[no_mangle]
 unsafe extern "system" fn DllUnregisterServer() -> HRESULT {
-    icon_overlay::unregister().unwrap();
-    context_menu::unregister().unwrap();
-    property_store::unregister().unwrap();
+    let module_path = get_module_path(INSTANCE);
+    if let Err(err) = unregister_all(&mut WinRegistry, &registrations(&module_path)) {
+        log!("Failed to unregister: {err:?}");
+        return SELFREG_E_CLASS;
+    }
+
+    // A leftover schema only describes columns nothing fills in
+    let _ = property_store::unregister_schema(&module_path);
+
     SHChangeNotify(SHCNE_ASSOCCHANGED, SHCNF_IDLIST, None, None);
     S_OK
 }
+
+/// Everything the extension registers, see [`common::registry::shell`].
+fn registrations(module_path: &str) -> Vec<Registration> {
+    let overlays = OVERLAYS
+        .into_iter()
+        .map(|x| icon_overlay::registration(x, module_path));
+
+    overlays
+        .chain([
+            context_menu::registration(module_path),
+            property_store::registration(module_path),
+        ])
+        .collect()
+}
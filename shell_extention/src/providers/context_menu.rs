@@ -1,46 +1,62 @@
-use std::mem;
+use std::{cell::RefCell, ffi::OsString, mem, os::windows::ffi::OsStringExt, path::PathBuf};
 
+use common::{
+    history::Source,
+    registry::{shell, Registration},
+    selection::{Selection, SelectionAction},
+};
 use windows::Win32::{
-    Foundation::{BOOL, ERROR_NOT_FOUND, S_FALSE},
-    System::Com::{IClassFactory, IClassFactory_Impl},
+    Foundation::{BOOL, ERROR_NOT_FOUND, MAX_PATH, S_FALSE},
+    System::{
+        Com::{
+            IClassFactory, IClassFactory_Impl, IDataObject, DVASPECT_CONTENT, FORMATETC,
+            TYMED_HGLOBAL,
+        },
+        Ole::{ReleaseStgMedium, CF_HDROP},
+        Registry::HKEY,
+    },
     UI::{
         Shell::{
-            IContextMenu, IContextMenu_Impl, CMF_DEFAULTONLY, CMINVOKECOMMANDINFO, GCS_HELPTEXTA,
-            GCS_HELPTEXTW, GCS_VALIDATEA, GCS_VALIDATEW, GCS_VERBA, GCS_VERBW,
+            Common::ITEMIDLIST, DragQueryFileW, IContextMenu, IContextMenu_Impl, IShellExtInit,
+            IShellExtInit_Impl, SHChangeNotify, SHGetPathFromIDListW, CMF_DEFAULTONLY,
+            CMINVOKECOMMANDINFO, GCS_HELPTEXTA, GCS_HELPTEXTW, GCS_VALIDATEA, GCS_VALIDATEW,
+            GCS_VERBA, GCS_VERBW, HDROP, SHCNE_UPDATEITEM, SHCNF_PATHW,
         },
         WindowsAndMessaging::{
-            InsertMenuItemW, HMENU, MENUITEMINFOW, MFT_STRING, MIIM_FTYPE, MIIM_STATE, MIIM_STRING,
+            InsertMenuItemW, HMENU, MENUITEMINFOW, MFT_STRING, MIIM_FTYPE, MIIM_ID, MIIM_STATE,
+            MIIM_STRING,
         },
     },
 };
 use windows_core::{
-    implement, Error, IInspectable, IUnknown, Interface, Result, GUID, PSTR, PWSTR,
-};
-use winreg::{
-    enums::{HKEY_CLASSES_ROOT, KEY_ALL_ACCESS},
-    RegKey,
+    implement, Error, IInspectable, IUnknown, Interface, Result, GUID, HRESULT, PSTR, PWSTR,
 };
 
-use crate::{
-    log,
-    misc::to_pcwstr,
-    registry::{format_guid, register_clsid, unregister_clsid},
-};
+use crate::{log, misc::to_pcwstr, registry::format_guid};
 
 // {fc46d627-5ab0-452b-9262-38121078759e}
 pub const CONTEXT_MENU_CLSID: GUID = GUID::from_u128(0xfc46d627_5ab0_452b_9262_38121078759e);
 
 const ID_MARK_WATCHED: u32 = 0x0;
 const ID_MARK_UNWATCHED: u32 = 0x1;
+const ID_MARK_WATCHED_UP_TO: u32 = 0x2;
 
-const MENU_ITEMS: [MenuItem; 2] = [
+const MENU_ITEMS: [MenuItem; 3] = [
     MenuItem {
         id: ID_MARK_WATCHED,
+        action: SelectionAction::MarkWatched,
         name: "mark-watched",
         help_text: "Mark video as watched",
     },
+    MenuItem {
+        id: ID_MARK_WATCHED_UP_TO,
+        action: SelectionAction::MarkWatchedUpTo,
+        name: "mark-watched-up-to",
+        help_text: "Mark all up to here as watched",
+    },
     MenuItem {
         id: ID_MARK_UNWATCHED,
+        action: SelectionAction::MarkUnwatched,
         name: "mark-unwatched",
         help_text: "Mark video as not watched",
     },
@@ -48,23 +64,86 @@ const MENU_ITEMS: [MenuItem; 2] = [
 
 struct MenuItem {
     id: u32,
+    action: SelectionAction,
     name: &'static str,
     help_text: &'static str,
 }
 
-#[implement(IContextMenu)]
-pub struct WatchedContextMenu;
+#[implement(IShellExtInit, IContextMenu)]
+pub struct WatchedContextMenu {
+    selection: RefCell<Selection>,
+}
 
 #[implement(IClassFactory)]
 pub struct WatchedContextMenuFactory;
 
+impl IShellExtInit_Impl for WatchedContextMenu_Impl {
+    fn Initialize(
+        &self,
+        pidlfolder: *const ITEMIDLIST,
+        pdtobj: Option<&IDataObject>,
+        _hkeyprogid: HKEY,
+    ) -> Result<()> {
+        let paths = match pdtobj {
+            Some(data) => unsafe { dropped_files(data)? },
+            // Directory backgrounds only get the folder that was clicked in
+            None => {
+                let mut buf = [0u16; MAX_PATH as usize];
+                if !unsafe { SHGetPathFromIDListW(pidlfolder, &mut buf) }.as_bool() {
+                    return Err(Error::from(ERROR_NOT_FOUND));
+                }
+
+                let len = buf.iter().position(|&x| x == 0).unwrap_or(buf.len());
+                vec![PathBuf::from(OsString::from_wide(&buf[..len]))]
+            }
+        };
+
+        // Runs on Explorer's UI thread, so folders and earlier episodes are
+        // only searched once a command is picked
+        log!("Initialize: {paths:?}");
+        let selection = Selection::read(&paths).map_err(|err| {
+            log!("Failed to read selection: {err:?}");
+            Error::from(ERROR_NOT_FOUND)
+        })?;
+        self.selection.replace(selection);
+        Ok(())
+    }
+}
+
+/// Gets the paths of the files selected in Explorer.
+unsafe fn dropped_files(data: &IDataObject) -> Result<Vec<PathBuf>> {
+    let format = FORMATETC {
+        cfFormat: CF_HDROP.0,
+        ptd: std::ptr::null_mut(),
+        dwAspect: DVASPECT_CONTENT.0,
+        lindex: -1,
+        tymed: TYMED_HGLOBAL.0 as u32,
+    };
+
+    let mut medium = data.GetData(&format)?;
+    let drop = HDROP(medium.u.hGlobal.0);
+
+    let count = DragQueryFileW(drop, u32::MAX, None);
+    let paths = (0..count)
+        .map(|idx| {
+            let len = DragQueryFileW(drop, idx, None) as usize;
+            let mut buf = vec![0u16; len + 1];
+            DragQueryFileW(drop, idx, Some(&mut buf));
+            PathBuf::from(OsString::from_wide(&buf[..len]))
+        })
+        .collect();
+
+    ReleaseStgMedium(&mut medium);
+    Ok(paths)
+}
+
 impl IContextMenu_Impl for WatchedContextMenu_Impl {
     fn QueryContextMenu(
         &self,
         hmenu: HMENU,
-        _indexmenu: u32,
-        _idcmdfirst: u32,
-        _idcmdlast: u32,
+        indexmenu: u32,
+        idcmdfirst: u32,
+        idcmdlast: u32,
         uflags: u32,
     ) -> Result<()> {
         if uflags & CMF_DEFAULTONLY != 0 {
@@ -72,32 +151,75 @@ impl IContextMenu_Impl for WatchedContextMenu_Impl {
         }
 
         log!("QueryContextMenu");
-        for item in MENU_ITEMS.iter() {
+        let actions = self.selection.borrow().actions();
+        let items = MENU_ITEMS
+            .iter()
+            .filter(|item| actions.contains(&item.action))
+            .filter(|item| idcmdfirst + item.id <= idcmdlast);
+
+        let mut added = 0;
+        for item in items {
+            // Has to stay alive until the item is inserted, which copies it
+            let mut text = to_pcwstr(item.help_text);
             let menu_item = MENUITEMINFOW {
                 cbSize: mem::size_of::<MENUITEMINFOW>() as u32,
-                fMask: MIIM_FTYPE | MIIM_STRING | MIIM_STATE,
+                fMask: MIIM_FTYPE | MIIM_STRING | MIIM_STATE | MIIM_ID,
                 fType: MFT_STRING,
-                dwTypeData: PWSTR(to_pcwstr(item.help_text).as_mut_ptr()),
-                cch: 2 * item.help_text.len() as u32,
+                wID: idcmdfirst + item.id,
+                dwTypeData: PWSTR(text.as_mut_ptr()),
+                cch: text.len() as u32 - 1,
                 ..Default::default()
             };
 
-            unsafe { InsertMenuItemW(hmenu, item.id, true, &menu_item)? };
+            unsafe { InsertMenuItemW(hmenu, indexmenu + added, true, &menu_item)? };
+            added += 1;
         }
 
-        Ok(())
+        // The success code tells Explorer how many command IDs were used
+        let used = MENU_ITEMS.iter().map(|item| item.id + 1).max().unwrap_or(0);
+        match added {
+            0 => Ok(()),
+            _ => Err(Error::from_hresult(HRESULT(used as i32))),
+        }
     }
 
     fn InvokeCommand(&self, pici: *const CMINVOKECOMMANDINFO) -> Result<()> {
-        let command_name = unsafe { (*pici).lpVerb.to_string().unwrap() };
-        let command = MENU_ITEMS
-            .iter()
-            .find(|item| item.name == command_name)
-            .ok_or(Error::from(ERROR_NOT_FOUND))?;
-
-        let parameter = unsafe { (*pici).lpParameters.to_string().unwrap() };
-        let directory = unsafe { (*pici).lpDirectory.to_string().unwrap() };
-        log!("InvokeCommand: {} {parameter} {directory}", command.name,);
+        let verb = unsafe { (*pici).lpVerb };
+
+        // The verb is either an offset from the first command ID, or a name
+        let item = match verb.0 as usize >> 16 {
+            0 => MENU_ITEMS
+                .iter()
+                .find(|item| item.id as usize == verb.0 as usize),
+            _ => {
+                let name = unsafe { verb.to_string() }.unwrap_or_default();
+                MENU_ITEMS.iter().find(|item| item.name == name)
+            }
+        }
+        .ok_or(Error::from(ERROR_NOT_FOUND))?;
+        log!("InvokeCommand: {}", item.name);
+
+        let changed = self
+            .selection
+            .borrow()
+            .apply(item.action, Source::Shell)
+            .map_err(|err| {
+                log!("Failed to {}: {err:?}", item.name);
+                Error::from(ERROR_NOT_FOUND)
+            })?;
+
+        // Let Explorer know to redraw the overlays of the changed files
+        for path in changed {
+            let path = to_pcwstr(&path.to_string_lossy());
+            unsafe {
+                SHChangeNotify(
+                    SHCNE_UPDATEITEM,
+                    SHCNF_PATHW,
+                    Some(path.as_ptr().cast()),
+                    None,
+                )
+            };
+        }
 
         Ok(())
     }
@@ -112,12 +234,18 @@ impl IContextMenu_Impl for WatchedContextMenu_Impl {
     ) -> Result<()> {
         log!("GetCommandString");
 
-        let item = MENU_ITEMS.get(idcmd).ok_or(Error::from(S_FALSE))?;
+        let item = MENU_ITEMS
+            .iter()
+            .find(|item| item.id as usize == idcmd)
+            .ok_or(Error::from(S_FALSE))?;
 
+        // The W variants are passed a wide string buffer, with `cchmax` in characters
         let return_unicode = |string: &str| unsafe {
-            let len = string.len().max(cchmax as usize - 1);
-            pszname.0.copy_from(string.as_ptr(), len);
-            pszname.0.add(len).write(0);
+            let string = string.encode_utf16().collect::<Vec<_>>();
+            let len = string.len().min(cchmax.saturating_sub(1) as usize);
+            let pszname = pszname.0.cast::<u16>();
+            pszname.copy_from(string.as_ptr(), len);
+            pszname.add(len).write(0);
         };
 
         let return_ascii = |string: &str| unsafe {
@@ -125,8 +253,8 @@ impl IContextMenu_Impl for WatchedContextMenu_Impl {
                 .chars()
                 .map(|c| if c.is_ascii() { c as u8 } else { b'#' })
                 .collect::<Vec<_>>();
-            let len = string.len().max(cchmax as usize - 1);
-            pszname.0.copy_from(string.as_ptr() as *const u8, len);
+            let len = string.len().min(cchmax.saturating_sub(1) as usize);
+            pszname.0.copy_from(string.as_ptr(), len);
             pszname.0.add(len).write(0);
         };
 
@@ -157,7 +285,9 @@ impl IClassFactory_Impl for WatchedContextMenuFactory_Impl {
         riid: *const windows_core::GUID,
         ppvobject: *mut *mut core::ffi::c_void,
     ) -> Result<()> {
-        let obj = IInspectable::from(WatchedContextMenu);
+        let obj = IInspectable::from(WatchedContextMenu {
+            selection: RefCell::default(),
+        });
         unsafe { obj.query(riid, ppvobject).ok() }
     }
 
@@ -166,30 +296,6 @@ impl IClassFactory_Impl for WatchedContextMenuFactory_Impl {
     }
 }
 
-pub fn register(module_path: &str) -> anyhow::Result<()> {
-    register_clsid(module_path, CONTEXT_MENU_CLSID)?;
-
-    let associations = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("SystemFileAssociations")?;
-    for format in [".mkv"] {
-        let (key, _) = associations
-            .create_subkey(format!(r"{format}\ShellEx\ContextMenuHandlers\LastWatched"))?;
-        key.set_value("", &format_guid(&CONTEXT_MENU_CLSID))?;
-    }
-
-    Ok(())
-}
-
-pub fn unregister() -> anyhow::Result<()> {
-    unregister_clsid(CONTEXT_MENU_CLSID)?;
-
-    let associations = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("SystemFileAssociations")?;
-    for format in [".mkv"] {
-        let key = associations.open_subkey_with_flags(
-            format!(r"{format}\ShellEx\ContextMenuHandlers"),
-            KEY_ALL_ACCESS,
-        )?;
-        key.delete_subkey_all("LastWatched")?;
-    }
-
-    Ok(())
+pub fn registration(module_path: &str) -> Registration {
+    shell::context_menu(&format_guid(&CONTEXT_MENU_CLSID), module_path)
 }
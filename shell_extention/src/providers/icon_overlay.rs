@@ -1,9 +1,10 @@
-use std::{ffi::c_void, fs, iter, os::windows::ffi::OsStrExt, path::Path};
+use std::{ffi::c_void, iter, os::windows::ffi::OsStrExt, path::Path};
 
 use windows::{
     core::{implement, Error, Result, PCWSTR, PWSTR},
     Win32::{
-        Foundation::{BOOL, ERROR_INSUFFICIENT_BUFFER, S_FALSE},
+        Foundation::{BOOL, ERROR_INSUFFICIENT_BUFFER, E_FAIL, S_FALSE},
+        Storage::FileSystem::FILE_ATTRIBUTE_DIRECTORY,
         System::Com::{IClassFactory, IClassFactory_Impl},
         UI::Shell::{
             IShellIconOverlayIdentifier, IShellIconOverlayIdentifier_Impl, ISIOI_ICONFILE,
@@ -11,63 +12,130 @@ use windows::{
     },
 };
 use windows_core::{IInspectable, IUnknown, Interface, GUID};
-use winreg::{
-    enums::{HKEY_LOCAL_MACHINE, KEY_ALL_ACCESS},
-    RegKey,
-};
 
-use crate::{
-    log,
-    misc::get_module_path,
-    registry::{format_guid, register_clsid, unregister_clsid},
-    INSTANCE,
+use crate::{log, misc::get_module_path, registry::format_guid, INSTANCE};
+use common::{
+    cache,
+    config::Config,
+    registry::{shell, Registration},
+    state::{folder_state, video_state, FolderState, VideoState},
 };
-use common::{winapi::ensure_hidden, VIDEO_EXTENSIONS};
 
 // {172d5af2-6916-48d3-a611-368273076434}
 pub const OVERLAY_CLSID: GUID = GUID::from_u128(0x172d5af2_6916_48d3_a611_368273076434);
+// {6c54255c-1a23-41e2-b91b-fdb02f8157c9}
+pub const PROGRESS_OVERLAY_CLSID: GUID = GUID::from_u128(0x6c54255c_1a23_41e2_b91b_fdb02f8157c9);
+// {d618b600-ab17-4fa7-b954-b737f2226acc}
+pub const PARTIAL_OVERLAY_CLSID: GUID = GUID::from_u128(0xd618b600_ab17_4fa7_b954_b737f2226acc);
+
+/// The states shown with an overlay. Explorer only asks each identifier
+/// whether a file gets its one icon, so every state is its own identifier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overlay {
+    /// Watched videos, and folders where every video is watched.
+    Watched,
+    /// Videos stopped before the end.
+    InProgress,
+    /// Folders where some videos are watched or in progress.
+    PartiallyWatched,
+}
+
+pub const OVERLAYS: [Overlay; 3] = [
+    Overlay::Watched,
+    Overlay::InProgress,
+    Overlay::PartiallyWatched,
+];
+
+impl Overlay {
+    pub fn from_clsid(clsid: &GUID) -> Option<Self> {
+        OVERLAYS.into_iter().find(|x| x.clsid() == *clsid)
+    }
+
+    pub fn clsid(self) -> GUID {
+        match self {
+            Self::Watched => OVERLAY_CLSID,
+            Self::InProgress => PROGRESS_OVERLAY_CLSID,
+            Self::PartiallyWatched => PARTIAL_OVERLAY_CLSID,
+        }
+    }
+
+    /// Name of the identifier's registry key.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Watched => "LastWatched",
+            Self::InProgress => "LastWatchedInProgress",
+            Self::PartiallyWatched => "LastWatchedPartial",
+        }
+    }
+
+    /// Icon file next to the dll, from `common/assets`.
+    fn icon(self) -> &'static str {
+        match self {
+            Self::Watched => "icon.ico",
+            Self::InProgress => "icon-progress.ico",
+            Self::PartiallyWatched => "icon-partial.ico",
+        }
+    }
+
+    fn is_member(self, config: &Config, path: &Path, is_dir: bool) -> anyhow::Result<bool> {
+        if is_dir {
+            return Ok(match (self, folder_state(config, path)?) {
+                (Self::Watched, Some(FolderState::Watched)) => true,
+                (Self::PartiallyWatched, Some(FolderState::PartiallyWatched)) => true,
+                _ => false,
+            });
+        }
+
+        // Sniffing the contents of every file Explorer shows would be too slow
+        if !config.has_video_extension(path) {
+            return Ok(false);
+        }
+
+        Ok(
+            match (self, video_state(&mut cache::shared(), config, path)?) {
+                (Self::Watched, VideoState::Watched) => true,
+                (Self::InProgress, VideoState::InProgress) => true,
+                _ => false,
+            },
+        )
+    }
+}
 
 #[implement(IShellIconOverlayIdentifier)]
-pub struct WatchedOverlay;
+pub struct StateOverlay {
+    overlay: Overlay,
+}
 
 #[implement(IClassFactory)]
-pub struct WatchedOverlayFactory;
+pub struct StateOverlayFactory {
+    pub overlay: Overlay,
+}
 
 enum IsMemberOfResult {
     Member,
     NotMember,
 }
 
-impl IShellIconOverlayIdentifier_Impl for WatchedOverlay_Impl {
-    fn IsMemberOf(&self, pwszpath: &PCWSTR, _dwattrib: u32) -> Result<()> {
+impl IShellIconOverlayIdentifier_Impl for StateOverlay_Impl {
+    fn IsMemberOf(&self, pwszpath: &PCWSTR, dwattrib: u32) -> Result<()> {
         let path = unsafe { pwszpath.to_string()? };
-        let ext = path.rsplit_once('.').unwrap_or_default().1;
-
-        if !VIDEO_EXTENSIONS.contains(&ext) {
-            return IsMemberOfResult::NotMember.into();
-        }
-
         let path = Path::new(&path);
-        let Some(parent) = path.parent() else {
-            return IsMemberOfResult::NotMember.into();
-        };
+        let is_dir = dwattrib & FILE_ATTRIBUTE_DIRECTORY.0 != 0;
 
-        let sidecar = parent.join(".watched");
-        if !sidecar.exists() {
+        // Configs are cached by directory, as Explorer asks about every icon
+        let dir = match is_dir {
+            true => path,
+            false => path.parent().unwrap_or(path),
+        };
+        let Ok(config) = cache::shared_configs().config(dir) else {
             return IsMemberOfResult::NotMember.into();
-        }
-
-        // Make sure the sidecar is hidden
-        let _ = ensure_hidden(&sidecar);
+        };
 
-        // TODO: cache the content
-        let file = fs::read_to_string(sidecar)?;
-        let filename = path.file_name().unwrap();
-        if file.lines().any(|line| line == filename) {
-            return IsMemberOfResult::Member.into();
+        match self.overlay.is_member(&config, path, is_dir) {
+            Ok(true) => IsMemberOfResult::Member.into(),
+            Ok(false) => IsMemberOfResult::NotMember.into(),
+            Err(err) => Err(Error::new(E_FAIL, format!("{err:#}"))),
         }
-
-        IsMemberOfResult::NotMember.into()
     }
 
     fn GetOverlayInfo(
@@ -77,11 +145,11 @@ impl IShellIconOverlayIdentifier_Impl for WatchedOverlay_Impl {
         pindex: *mut i32,
         pdwflags: *mut u32,
     ) -> Result<()> {
-        log!("GetOverlayInfo");
+        log!("GetOverlayInfo: {:?}", self.overlay);
         let icon = Path::new(&unsafe { get_module_path(INSTANCE) })
             .parent()
             .unwrap()
-            .join("icon.ico");
+            .join(self.overlay.icon());
         let icon = icon
             .as_os_str()
             .encode_wide()
@@ -109,14 +177,16 @@ impl IShellIconOverlayIdentifier_Impl for WatchedOverlay_Impl {
     }
 }
 
-impl IClassFactory_Impl for WatchedOverlayFactory_Impl {
+impl IClassFactory_Impl for StateOverlayFactory_Impl {
     fn CreateInstance(
         &self,
         _punkouter: Option<&IUnknown>,
         riid: *const GUID,
         ppvobject: *mut *mut c_void,
     ) -> Result<()> {
-        let obj = IInspectable::from(WatchedOverlay);
+        let obj = IInspectable::from(StateOverlay {
+            overlay: self.overlay,
+        });
         unsafe { obj.query(riid, ppvobject).ok() }
     }
 
@@ -134,25 +204,6 @@ impl From<IsMemberOfResult> for Result<()> {
     }
 }
 
-pub fn register(module_path: &str) -> anyhow::Result<()> {
-    register_clsid(module_path, OVERLAY_CLSID)?;
-
-    let (overlays, _) = RegKey::predef(HKEY_LOCAL_MACHINE).create_subkey(
-        r"Software\Microsoft\Windows\CurrentVersion\Explorer\ShellIconOverlayIdentifiers\LastWatched",
-    )?;
-    overlays.set_value("", &format_guid(&OVERLAY_CLSID))?;
-
-    Ok(())
-}
-
-pub fn unregister() -> anyhow::Result<()> {
-    unregister_clsid(OVERLAY_CLSID)?;
-
-    let overlays = RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey_with_flags(
-        r"Software\Microsoft\Windows\CurrentVersion\Explorer\ShellIconOverlayIdentifiers",
-        KEY_ALL_ACCESS,
-    )?;
-    overlays.delete_subkey_all("LastWatched")?;
-
-    Ok(())
+pub fn registration(overlay: Overlay, module_path: &str) -> Registration {
+    shell::icon_overlay(overlay.name(), &format_guid(&overlay.clsid()), module_path)
 }
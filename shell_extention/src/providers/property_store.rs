@@ -1,84 +1,240 @@
-use std::{cell::RefCell, fs::File, path::Path};
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+};
 
-use common::sidecar::Sidecar;
+use common::{
+    config::Config,
+    history::Source,
+    properties::{to_filetime, Property, PropertyChanges, PropertyValue, FORMAT_ID, PROPERTIES},
+    registry::{shell, Registration},
+    sidecar::{read_entry, Entry},
+};
 use windows::Win32::{
-    Foundation::{BOOL, ERROR_FILE_NOT_FOUND, ERROR_NOT_FOUND},
-    System::Com::{IClassFactory, IClassFactory_Impl},
-    UI::Shell::PropertiesSystem::{
-        IInitializeWithFile, IInitializeWithFile_Impl, IPropertyStore, IPropertyStore_Impl,
-        PROPERTYKEY,
+    Foundation::{BOOL, E_FAIL, E_INVALIDARG, E_UNEXPECTED, FILETIME, STG_E_ACCESSDENIED, S_FALSE},
+    Storage::FileSystem::FILE_ATTRIBUTE_NORMAL,
+    System::Com::{
+        CLSIDFromString, CoCreateInstance, IClassFactory, IClassFactory_Impl,
+        StructuredStorage::InitPropVariantFromFileTime, CLSCTX_INPROC_SERVER, STGM_READWRITE,
+        STGM_WRITE,
+    },
+    UI::Shell::{
+        PropertiesSystem::{
+            IInitializeWithFile, IInitializeWithFile_Impl, IInitializeWithStream, IPropertyStore,
+            IPropertyStoreCapabilities, IPropertyStoreCapabilities_Impl, IPropertyStore_Impl,
+            PSRegisterPropertySchema, PSUnregisterPropertySchema, PROPERTYKEY,
+        },
+        SHChangeNotify, SHCreateStreamOnFileEx, SHCNE_UPDATEITEM, SHCNF_PATHW,
     },
 };
 use windows_core::{
-    implement, IInspectable, IUnknown, Interface, Result, GUID, PCWSTR, PROPVARIANT,
-};
-use winreg::{
-    enums::{HKEY_CLASSES_ROOT, KEY_ALL_ACCESS},
-    RegKey,
+    implement, Error, IInspectable, IUnknown, Interface, Result, GUID, PCWSTR, PROPVARIANT,
 };
 
 use crate::{
     log,
-    registry::{format_guid, register_clsid, unregister_clsid},
+    misc::to_pcwstr,
+    registry::{format_guid, WinRegistry},
 };
 
 // {1c49d817-ff89-46d2-b25f-5be1cedb338a}
 pub const PROPERTY_STORE_CLSID: GUID = GUID::from_u128(0x1c49d817_ff89_46d2_b25f_5be1cedb338a);
-// {1c49d817-ff89-46d2-b25f-5be1cedb338a}
-pub const WATCHED_PROPERTY: GUID = GUID::from_u128(0x1c49d817_ff89_46d2_b25f_5be1cedb338a);
+pub const PROPERTY_FORMAT_ID: GUID = GUID::from_u128(FORMAT_ID);
+
+/// The property schema, next to the dll.
+const SCHEMA: &str = "last-watched.propdesc";
 
-#[implement(IPropertyStore, IInitializeWithFile)]
+#[implement(IPropertyStore, IPropertyStoreCapabilities, IInitializeWithFile)]
 pub struct WatchedPropertyStore {
-    sidecar: RefCell<Option<Sidecar>>,
+    video: RefCell<Option<Video>>,
 }
 
 #[implement(IClassFactory)]
 pub struct WatchedPropertyStoreFactory;
 
+/// The video the store was initialized with.
+struct Video {
+    path: PathBuf,
+    entry: Option<Entry>,
+    /// Whether the store was opened for writing.
+    writable: bool,
+    /// Values set since the last commit.
+    changes: PropertyChanges,
+    /// The handler that was registered for the extension before this one,
+    /// which handles every property that isn't ours.
+    chained: Option<IPropertyStore>,
+    /// Whether values have been set in the chained handler since the last
+    /// commit.
+    chained_changed: bool,
+}
+
 impl IPropertyStore_Impl for WatchedPropertyStore_Impl {
     fn GetCount(&self) -> Result<u32> {
-        log!("GetCount");
-        Ok(1)
+        let video = self.video.borrow();
+        let chained = match video.as_ref().and_then(|x| x.chained.as_ref()) {
+            Some(chained) => unsafe { chained.GetCount()? },
+            None => 0,
+        };
+
+        Ok(PROPERTIES.len() as u32 + chained)
     }
 
-    fn GetAt(&self, iprop: u32, _pkey: *mut PROPERTYKEY) -> Result<()> {
+    fn GetAt(&self, iprop: u32, pkey: *mut PROPERTYKEY) -> Result<()> {
         log!("GetAt: {iprop}");
-        Ok(())
+        if let Some(property) = PROPERTIES.get(iprop as usize) {
+            unsafe { *pkey = key(*property) };
+            return Ok(());
+        }
+
+        let video = self.video.borrow();
+        match video.as_ref().and_then(|x| x.chained.as_ref()) {
+            Some(chained) => unsafe { chained.GetAt(iprop - PROPERTIES.len() as u32, pkey) },
+            None => Err(E_INVALIDARG.into()),
+        }
     }
 
     fn GetValue(&self, key: *const PROPERTYKEY) -> Result<PROPVARIANT> {
-        log!("GetValue");
-        if unsafe { (*key).fmtid } != WATCHED_PROPERTY {
-            return Err(ERROR_NOT_FOUND.into());
+        let key = unsafe { *key };
+        let video = self.video.borrow();
+        let Some(video) = video.as_ref() else {
+            return Ok(PROPVARIANT::default());
+        };
+
+        if key.fmtid != PROPERTY_FORMAT_ID {
+            return match &video.chained {
+                Some(chained) => unsafe { chained.GetValue(&key) },
+                None => Ok(PROPVARIANT::default()),
+            };
         }
 
-        Ok(true.into())
+        match Property::from_id(key.pid) {
+            Some(property) => to_propvariant(property.value(video.entry.as_ref())),
+            None => Ok(PROPVARIANT::default()),
+        }
     }
 
-    fn SetValue(&self, _key: *const PROPERTYKEY, _propvar: *const PROPVARIANT) -> Result<()> {
-        log!("SetValue");
-        Ok(())
+    fn SetValue(&self, key: *const PROPERTYKEY, propvar: *const PROPVARIANT) -> Result<()> {
+        let key = unsafe { *key };
+        let mut video = self.video.borrow_mut();
+        let Some(video) = video.as_mut() else {
+            return Err(E_UNEXPECTED.into());
+        };
+
+        if key.fmtid != PROPERTY_FORMAT_ID {
+            let Some(chained) = &video.chained else {
+                return Err(STG_E_ACCESSDENIED.into());
+            };
+            unsafe { chained.SetValue(&key, propvar)? };
+            video.chained_changed = true;
+            return Ok(());
+        }
+
+        let property = Property::from_id(key.pid).ok_or(Error::from(E_INVALIDARG))?;
+        log!("SetValue: {property:?}");
+        if !video.writable || !property.is_writable() {
+            return Err(STG_E_ACCESSDENIED.into());
+        }
+
+        let value = from_propvariant(property, unsafe { &*propvar })?;
+        video
+            .changes
+            .set(property, value)
+            .map_err(|err| Error::new(E_INVALIDARG, format!("{err:#}")))
     }
 
     fn Commit(&self) -> Result<()> {
         log!("Commit");
+        let mut video = self.video.borrow_mut();
+        let Some(video) = video.as_mut() else {
+            return Err(E_UNEXPECTED.into());
+        };
+
+        if video.chained_changed {
+            if let Some(chained) = &video.chained {
+                unsafe { chained.Commit()? };
+            }
+            video.chained_changed = false;
+        }
+
+        if video.changes.is_empty() {
+            return Ok(());
+        }
+
+        let path = &video.path;
+        let entry = Config::for_path(path)
+            .and_then(|config| {
+                video.changes.commit(&config, path, Source::Shell)?;
+                read_entry(&config, path)
+            })
+            .map_err(|err| Error::new(E_FAIL, format!("{err:#}")))?;
+        video.entry = entry;
+        video.changes = PropertyChanges::default();
+
+        // So the overlay changes right away
+        let path = to_pcwstr(&video.path.to_string_lossy());
+        unsafe {
+            SHChangeNotify(
+                SHCNE_UPDATEITEM,
+                SHCNF_PATHW,
+                Some(path.as_ptr().cast()),
+                None,
+            )
+        };
+
         Ok(())
     }
 }
 
+impl IPropertyStoreCapabilities_Impl for WatchedPropertyStore_Impl {
+    fn IsPropertyWritable(&self, key: *const PROPERTYKEY) -> Result<()> {
+        let key = unsafe { *key };
+        let video = self.video.borrow();
+        let Some(video) = video.as_ref() else {
+            return Err(E_UNEXPECTED.into());
+        };
+
+        if key.fmtid != PROPERTY_FORMAT_ID {
+            let capabilities = video
+                .chained
+                .as_ref()
+                .and_then(|x| x.cast::<IPropertyStoreCapabilities>().ok());
+            return match capabilities {
+                Some(capabilities) => unsafe { capabilities.IsPropertyWritable(&key) },
+                None => Err(Error::from_hresult(S_FALSE)),
+            };
+        }
+
+        match Property::from_id(key.pid) {
+            Some(property) if video.writable && property.is_writable() => Ok(()),
+            _ => Err(Error::from_hresult(S_FALSE)),
+        }
+    }
+}
+
 impl IInitializeWithFile_Impl for WatchedPropertyStore_Impl {
-    fn Initialize(&self, pszfilepath: &PCWSTR, _grfmode: u32) -> Result<()> {
+    fn Initialize(&self, pszfilepath: &PCWSTR, grfmode: u32) -> Result<()> {
         let path_string = unsafe { pszfilepath.to_string() }?;
         log!("Initialize: {path_string:?}");
 
+        // Videos without a sidecar, or with a broken one, still get the
+        // chained handler's properties
         let path = Path::new(&path_string);
-        let sidecar_path = path.parent().unwrap().join(".watched");
-
-        let Ok(sidecar) = Sidecar::new(File::open(sidecar_path)?) else {
-            return Err(ERROR_FILE_NOT_FOUND.into());
+        let entry = match Config::for_path(path).and_then(|config| read_entry(&config, path)) {
+            Ok(entry) => entry,
+            Err(err) => {
+                log!("Failed to read sidecar: {err:?}");
+                None
+            }
         };
 
-        self.sidecar.replace(Some(sidecar));
+        self.video.replace(Some(Video {
+            path: path.to_owned(),
+            entry,
+            writable: grfmode & (STGM_WRITE.0 | STGM_READWRITE.0) != 0,
+            changes: PropertyChanges::default(),
+            chained: chained(path, grfmode),
+            chained_changed: false,
+        }));
         Ok(())
     }
 }
@@ -91,7 +247,7 @@ impl IClassFactory_Impl for WatchedPropertyStoreFactory_Impl {
         ppvobject: *mut *mut core::ffi::c_void,
     ) -> Result<()> {
         let obj = IInspectable::from(WatchedPropertyStore {
-            sidecar: RefCell::new(None),
+            video: RefCell::new(None),
         });
         unsafe { obj.query(riid, ppvobject).ok() }
     }
@@ -101,32 +257,110 @@ impl IClassFactory_Impl for WatchedPropertyStoreFactory_Impl {
     }
 }
 
-pub fn register(module_path: &str) -> anyhow::Result<()> {
-    let inproc =
-        register_clsid(module_path, PROPERTY_STORE_CLSID)?.open_subkey("InProcServer32")?;
-    inproc.set_value("DisableProcessIsolation", &1u32)?;
+/// Creates and initializes the handler this one replaced, if there was one.
+/// Any errors are logged, as a video is still worth showing our properties for.
+fn chained(path: &Path, grfmode: u32) -> Option<IPropertyStore> {
+    let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+    let clsid = match shell::PROPERTY_HANDLERS.previous(&WinRegistry, &extension) {
+        Ok(clsid) => clsid?,
+        Err(err) => {
+            log!("Failed to find chained handler: {err:?}");
+            return None;
+        }
+    };
 
-    let associations = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("SystemFileAssociations")?;
-    for format in [".mkv"] {
-        let (key, _) = associations
-            .create_subkey(format!(r"{format}\ShellEx\ContextMenuHandlers\LastWatched"))?;
-        key.set_value("", &format_guid(&PROPERTY_STORE_CLSID))?;
-    }
+    let result = unsafe {
+        let wide_clsid = to_pcwstr(&clsid);
+        let wide_path = to_pcwstr(&path.to_string_lossy());
+        let path = PCWSTR(wide_path.as_ptr());
+
+        CLSIDFromString(PCWSTR(wide_clsid.as_ptr()))
+            .and_then(|clsid| {
+                CoCreateInstance::<_, IPropertyStore>(&clsid, None, CLSCTX_INPROC_SERVER)
+            })
+            .and_then(|store| {
+                // System handlers mostly only take streams
+                match store.cast::<IInitializeWithFile>() {
+                    Ok(init) => init.Initialize(path, grfmode)?,
+                    Err(_) => {
+                        let stream = SHCreateStreamOnFileEx(
+                            path,
+                            grfmode,
+                            FILE_ATTRIBUTE_NORMAL.0,
+                            false,
+                            None,
+                        )?;
+                        store
+                            .cast::<IInitializeWithStream>()?
+                            .Initialize(&stream, grfmode)?;
+                    }
+                }
+                Ok(store)
+            })
+    };
 
-    Ok(())
+    match result {
+        Ok(store) => Some(store),
+        Err(err) => {
+            log!("Failed to create chained handler {clsid}: {err:?}");
+            None
+        }
+    }
 }
 
-pub fn unregister() -> anyhow::Result<()> {
-    unregister_clsid(PROPERTY_STORE_CLSID)?;
+fn key(property: Property) -> PROPERTYKEY {
+    PROPERTYKEY {
+        fmtid: PROPERTY_FORMAT_ID,
+        pid: property.id(),
+    }
+}
 
-    let associations = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("SystemFileAssociations")?;
-    for format in [".mkv"] {
-        let key = associations.open_subkey_with_flags(
-            format!(r"{format}\ShellEx\ContextMenuHandlers"),
-            KEY_ALL_ACCESS,
-        )?;
-        key.delete_subkey_all("LastWatched")?;
+/// Reads a value being set, as the type of the property.
+fn from_propvariant(property: Property, value: &PROPVARIANT) -> Result<PropertyValue> {
+    if value.is_empty() {
+        return Ok(PropertyValue::Empty);
     }
 
-    Ok(())
+    Ok(match property {
+        Property::Watched => PropertyValue::Bool(bool::try_from(value)?),
+        Property::WatchCount | Property::Rating => PropertyValue::UInt(u32::try_from(value)?),
+        Property::LastWatched => return Err(STG_E_ACCESSDENIED.into()),
+    })
+}
+
+fn to_propvariant(value: PropertyValue) -> Result<PROPVARIANT> {
+    Ok(match value {
+        PropertyValue::Empty => PROPVARIANT::default(),
+        PropertyValue::Bool(value) => value.into(),
+        PropertyValue::UInt(value) => value.into(),
+        PropertyValue::Time(time) => {
+            let time = to_filetime(time);
+            let time = FILETIME {
+                dwLowDateTime: time as u32,
+                dwHighDateTime: (time >> 32) as u32,
+            };
+            unsafe { InitPropVariantFromFileTime(&time)? }
+        }
+    })
+}
+
+/// Registers the property schema, so Explorer knows how to show the
+/// properties as columns.
+pub fn register_schema(module_path: &str) -> Result<()> {
+    let schema = to_pcwstr(&schema_path(module_path));
+    unsafe { PSRegisterPropertySchema(PCWSTR(schema.as_ptr())) }
+}
+
+pub fn unregister_schema(module_path: &str) -> Result<()> {
+    let schema = to_pcwstr(&schema_path(module_path));
+    unsafe { PSUnregisterPropertySchema(PCWSTR(schema.as_ptr())) }
+}
+
+fn schema_path(module_path: &str) -> String {
+    let dir = Path::new(module_path).parent().unwrap_or(Path::new(""));
+    dir.join(SCHEMA).to_string_lossy().into_owned()
+}
+
+pub fn registration(module_path: &str) -> Registration {
+    shell::property_store(&format_guid(&PROPERTY_STORE_CLSID), module_path)
 }
@@ -1,30 +1,122 @@
+use std::io::ErrorKind;
+
 use anyhow::Result;
+use common::registry::{Registry, Root, Value};
 use windows_core::GUID;
 use winreg::{
-    enums::{HKEY_CLASSES_ROOT, KEY_ALL_ACCESS},
+    enums::{RegType, HKEY_CLASSES_ROOT, HKEY_LOCAL_MACHINE, KEY_ALL_ACCESS},
+    types::FromRegValue,
     RegKey,
 };
 
-pub fn register_clsid(module_path: &str, guid: GUID) -> Result<RegKey> {
-    let clsid = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("CLSID")?;
-    let (key, _) = clsid.create_subkey(&format_guid(&guid))?;
-    key.set_value("", &"last-watched")?;
+/// The real registry, see [`Registry`].
+pub struct WinRegistry;
+
+pub fn format_guid(guid: &GUID) -> String {
+    format!("{{{guid:?}}}")
+}
 
-    let (inproc, _) = key.create_subkey("InProcServer32")?;
-    inproc.set_value("", &module_path)?;
-    inproc.set_value("ThreadingModel", &"Apartment")?;
+impl WinRegistry {
+    /// Opens a key, returning none if it doesn't exist.
+    fn open(&self, root: Root, path: &str) -> Result<Option<RegKey>> {
+        match predef(root).open_subkey(path) {
+            Ok(key) => Ok(Some(key)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
 
-    Ok(key)
+    /// Opens a key for changing, see [`Self::open`].
+    fn open_writable(&self, root: Root, path: &str) -> Result<Option<RegKey>> {
+        match predef(root).open_subkey_with_flags(path, KEY_ALL_ACCESS) {
+            Ok(key) => Ok(Some(key)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
 }
 
-pub fn unregister_clsid(guid: GUID) -> Result<()> {
-    let clsid =
-        RegKey::predef(HKEY_CLASSES_ROOT).open_subkey_with_flags("CLSID", KEY_ALL_ACCESS)?;
-    clsid.delete_subkey_all(&format_guid(&guid))?;
+impl Registry for WinRegistry {
+    fn key_exists(&self, root: Root, path: &str) -> Result<bool> {
+        Ok(self.open(root, path)?.is_some())
+    }
+
+    fn subkeys(&self, root: Root, path: &str) -> Result<Vec<String>> {
+        let Some(key) = self.open(root, path)? else {
+            return Ok(Vec::new());
+        };
+
+        Ok(key.enum_keys().collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn values(&self, root: Root, path: &str) -> Result<Vec<(String, Value)>> {
+        let Some(key) = self.open(root, path)? else {
+            return Ok(Vec::new());
+        };
+
+        let mut values = Vec::new();
+        for value in key.enum_values() {
+            let (name, value) = value?;
+            // Only the types the extension writes are needed
+            let value = match value.vtype {
+                RegType::REG_SZ | RegType::REG_EXPAND_SZ => {
+                    Value::String(String::from_reg_value(&value)?)
+                }
+                RegType::REG_DWORD => Value::Dword(u32::from_reg_value(&value)?),
+                _ => continue,
+            };
+            values.push((name, value));
+        }
+
+        Ok(values)
+    }
 
-    Ok(())
+    fn create_key(&mut self, root: Root, path: &str) -> Result<()> {
+        predef(root).create_subkey(path)?;
+        Ok(())
+    }
+
+    fn set_value(&mut self, root: Root, path: &str, name: &str, value: &Value) -> Result<()> {
+        let (key, _) = predef(root).create_subkey(path)?;
+        match value {
+            Value::String(value) => key.set_value(name, value)?,
+            Value::Dword(value) => key.set_value(name, value)?,
+        }
+
+        Ok(())
+    }
+
+    fn delete_key(&mut self, root: Root, path: &str) -> Result<()> {
+        let (parent, name) = match path.rsplit_once('\\') {
+            Some((parent, name)) => (parent, name),
+            None => ("", path),
+        };
+
+        let Some(parent) = self.open_writable(root, parent)? else {
+            return Ok(());
+        };
+
+        match parent.delete_subkey_all(name) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_value(&mut self, root: Root, path: &str, name: &str) -> Result<()> {
+        let Some(key) = self.open_writable(root, path)? else {
+            return Ok(());
+        };
+
+        match key.delete_value(name) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
 }
 
-pub fn format_guid(guid: &GUID) -> String {
-    format!("{{{guid:?}}}")
+fn predef(root: Root) -> RegKey {
+    RegKey::predef(match root {
+        Root::ClassesRoot => HKEY_CLASSES_ROOT,
+        Root::LocalMachine => HKEY_LOCAL_MACHINE,
+    })
 }